@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE horus_images DROP COLUMN IF EXISTS thumbnail_path;
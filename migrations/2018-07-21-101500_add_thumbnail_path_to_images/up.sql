@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE horus_images ADD COLUMN thumbnail_path varchar;
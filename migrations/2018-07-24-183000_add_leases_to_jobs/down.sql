@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS horus_jobs_claimable_idx;
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS heartbeat;
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS claimed_by;
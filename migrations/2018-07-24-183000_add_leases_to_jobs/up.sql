@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE horus_jobs ADD COLUMN claimed_by varchar(64);
+ALTER TABLE horus_jobs ADD COLUMN heartbeat timestamp;
+
+CREATE INDEX horus_jobs_claimable_idx ON horus_jobs (job_status, priority DESC, time_queued);
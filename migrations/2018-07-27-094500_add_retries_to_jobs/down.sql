@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS run_after;
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS max_attempts;
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS attempts;
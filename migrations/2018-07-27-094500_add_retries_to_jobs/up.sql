@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE horus_jobs ADD COLUMN attempts integer NOT NULL DEFAULT 0;
+ALTER TABLE horus_jobs ADD COLUMN max_attempts integer NOT NULL DEFAULT 3;
+ALTER TABLE horus_jobs ADD COLUMN run_after timestamp;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS horus_job_schedules;
+DELETE FROM horus_users WHERE id = -1;
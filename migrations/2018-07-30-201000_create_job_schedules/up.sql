@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+-- Owner of the jobs the server schedules for itself.
+INSERT INTO horus_users(id, first_name, email) VALUES (-1, 'system', 'system@horus.local')
+  ON CONFLICT DO NOTHING;
+
+CREATE TABLE horus_job_schedules (
+  id SERIAL NOT NULL,
+  owner integer NOT NULL DEFAULT -1 REFERENCES horus_users(id) ON DELETE CASCADE,
+  job_name varchar(32) NOT NULL UNIQUE,
+  job_data bytea,
+  priority integer NOT NULL DEFAULT 3, -- system
+  cron_expression varchar NOT NULL, -- sec min hour day-of-month month day-of-week [year], UTC
+  next_run timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  last_run timestamp,
+  enabled boolean NOT NULL DEFAULT true,
+  PRIMARY KEY(id)
+);
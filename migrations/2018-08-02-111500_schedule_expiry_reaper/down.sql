@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS horus_pastes_expiration_idx;
+DROP INDEX IF EXISTS horus_files_expiration_idx;
+DROP INDEX IF EXISTS horus_videos_expiration_idx;
+DROP INDEX IF EXISTS horus_images_expiration_idx;
+DELETE FROM horus_job_schedules WHERE job_name = 'maintenance:reap_expired';
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+INSERT INTO horus_job_schedules(job_name, cron_expression)
+  VALUES ('maintenance:reap_expired', '0 */10 * * * *') -- every 10 minutes
+  ON CONFLICT DO NOTHING;
+
+-- Used to find expired resources.
+CREATE INDEX horus_images_expiration_idx ON horus_images (expiration_time) WHERE is_expiry;
+CREATE INDEX horus_videos_expiration_idx ON horus_videos (expiration_time) WHERE is_expiry;
+CREATE INDEX horus_files_expiration_idx ON horus_files (expiration_time) WHERE is_expiry;
+CREATE INDEX horus_pastes_expiration_idx ON horus_pastes (expiration_time) WHERE is_expiry;
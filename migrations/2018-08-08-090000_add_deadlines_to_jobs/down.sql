@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS timed_out;
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS deadline;
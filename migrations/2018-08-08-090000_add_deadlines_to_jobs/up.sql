@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE horus_jobs ADD COLUMN deadline timestamp;
+ALTER TABLE horus_jobs ADD COLUMN timed_out boolean NOT NULL DEFAULT false;
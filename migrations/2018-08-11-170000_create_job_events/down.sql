@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS horus_jobs_status_event ON horus_jobs;
+DROP FUNCTION IF EXISTS horus_job_status_event();
+DROP TABLE IF EXISTS horus_job_events;
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS stage;
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS progress;
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+ALTER TABLE horus_jobs ADD COLUMN progress smallint;
+ALTER TABLE horus_jobs ADD COLUMN stage varchar;
+
+CREATE TABLE horus_job_events (
+  id BIGSERIAL NOT NULL,
+  job_id integer NOT NULL REFERENCES horus_jobs(id) ON DELETE CASCADE,
+  event_type varchar(16) NOT NULL, -- status, progress or log
+  event_data text NOT NULL,
+  created_at timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  PRIMARY KEY(id)
+);
+
+CREATE INDEX horus_job_events_job_idx ON horus_job_events (job_id, id);
+
+-- Every change of status is recorded, whichever query made it.
+CREATE FUNCTION horus_job_status_event() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP = 'INSERT' OR NEW.job_status IS DISTINCT FROM OLD.job_status THEN
+    INSERT INTO horus_job_events(job_id, event_type, event_data)
+      VALUES (NEW.id, 'status', NEW.job_status::text);
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER horus_jobs_status_event AFTER INSERT OR UPDATE OF job_status ON horus_jobs
+  FOR EACH ROW EXECUTE PROCEDURE horus_job_status_event();
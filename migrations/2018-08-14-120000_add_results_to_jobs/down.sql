@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS job_result;
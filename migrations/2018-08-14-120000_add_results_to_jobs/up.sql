@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE horus_jobs ADD COLUMN job_result jsonb;
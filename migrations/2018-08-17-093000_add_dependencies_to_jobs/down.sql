@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS horus_jobs_depends_on_idx;
+ALTER TABLE horus_jobs DROP COLUMN IF EXISTS depends_on;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE horus_jobs ADD COLUMN depends_on integer[] NOT NULL DEFAULT '{}';
+CREATE INDEX horus_jobs_depends_on_idx ON horus_jobs USING gin (depends_on);
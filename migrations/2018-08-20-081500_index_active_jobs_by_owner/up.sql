@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Used to count the queued (1) and running (3) jobs of an owner when claiming jobs.
+CREATE INDEX horus_jobs_active_owner_idx ON horus_jobs (owner) WHERE job_status IN (1, 3);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM horus_job_schedules WHERE job_name = 'maintenance:reap_uploads';
+DROP TABLE IF EXISTS horus_upload_sessions;
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+CREATE TABLE horus_upload_sessions (
+  id varchar NOT NULL,
+  owner integer NOT NULL REFERENCES horus_users(id) ON DELETE CASCADE,
+  upload_type varchar(16) NOT NULL, -- 'file' or 'deployment'
+  upload_length bigint NOT NULL,
+  upload_offset bigint NOT NULL DEFAULT 0,
+  -- What the upload becomes once committed.
+  filename varchar,
+  expiration_time timestamp,
+  version_string varchar,
+  platform_string varchar,
+  deployment_key varchar REFERENCES deployment_keys(key) ON DELETE CASCADE,
+  created_at timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  last_activity timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+  PRIMARY KEY(id)
+);
+
+-- Used to find stale uploads.
+CREATE INDEX horus_upload_sessions_activity_idx ON horus_upload_sessions (last_activity);
+
+INSERT INTO horus_job_schedules(job_name, cron_expression)
+  VALUES ('maintenance:reap_uploads', '0 30 * * * *') -- every hour
+  ON CONFLICT DO NOTHING;
@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+DROP RULE IF EXISTS reference_blob_with_images_insert ON horus_images;
+DROP RULE IF EXISTS reference_blob_with_videos_insert ON horus_videos;
+DROP RULE IF EXISTS reference_blob_with_files_insert ON horus_files;
+
+DROP RULE IF EXISTS reference_blob_with_images_delete ON horus_images;
+DROP RULE IF EXISTS reference_blob_with_videos_delete ON horus_videos;
+DROP RULE IF EXISTS reference_blob_with_files_delete ON horus_files;
+
+ALTER TABLE horus_images DROP COLUMN IF EXISTS blob_hash;
+ALTER TABLE horus_videos DROP COLUMN IF EXISTS blob_hash;
+ALTER TABLE horus_files DROP COLUMN IF EXISTS blob_hash;
+
+ALTER TABLE horus_images DROP COLUMN IF EXISTS blob_kind;
+ALTER TABLE horus_videos DROP COLUMN IF EXISTS blob_kind;
+ALTER TABLE horus_files DROP COLUMN IF EXISTS blob_kind;
+
+DROP TABLE IF EXISTS horus_blobs;
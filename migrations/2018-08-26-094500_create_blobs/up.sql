@@ -0,0 +1,67 @@
+-- Your SQL goes here
+-- Content uploaded as different kinds of resources is stored apart, under the
+-- directory of each kind (see `dbtools::blobs::BlobKind`). Blobs aren't shared
+-- between owners, so a password on one's resource can't be undone by another
+-- uploading the same content.
+CREATE TABLE horus_blobs (
+    hash varchar(64) NOT NULL,
+    kind varchar NOT NULL CHECK (kind IN ('image', 'video', 'file')),
+    owner integer NOT NULL,
+    filepath varchar NOT NULL UNIQUE,
+    size bigint NOT NULL,
+    ref_count integer NOT NULL DEFAULT 0,
+    created_at timestamp NOT NULL DEFAULT now(),
+    PRIMARY KEY (hash, kind, owner)
+);
+
+CREATE INDEX horus_blobs_unreferenced ON horus_blobs (hash, kind, owner) WHERE ref_count = 0;
+
+-- Rows stored before blobs existed have none and own their object. Resources
+-- only reference blobs of their own kind and owner.
+ALTER TABLE horus_images ADD COLUMN blob_hash varchar(64);
+ALTER TABLE horus_videos ADD COLUMN blob_hash varchar(64);
+ALTER TABLE horus_files ADD COLUMN blob_hash varchar(64);
+
+ALTER TABLE horus_images ADD COLUMN blob_kind varchar NOT NULL DEFAULT 'image'
+  CHECK (blob_kind = 'image');
+ALTER TABLE horus_videos ADD COLUMN blob_kind varchar NOT NULL DEFAULT 'video'
+  CHECK (blob_kind = 'video');
+ALTER TABLE horus_files ADD COLUMN blob_kind varchar NOT NULL DEFAULT 'file'
+  CHECK (blob_kind = 'file');
+
+ALTER TABLE horus_images ADD FOREIGN KEY (blob_hash, blob_kind, owner)
+  REFERENCES horus_blobs (hash, kind, owner);
+ALTER TABLE horus_videos ADD FOREIGN KEY (blob_hash, blob_kind, owner)
+  REFERENCES horus_blobs (hash, kind, owner);
+ALTER TABLE horus_files ADD FOREIGN KEY (blob_hash, blob_kind, owner)
+  REFERENCES horus_blobs (hash, kind, owner);
+
+CREATE INDEX horus_images_blob_hash ON horus_images (blob_hash);
+CREATE INDEX horus_videos_blob_hash ON horus_videos (blob_hash);
+CREATE INDEX horus_files_blob_hash ON horus_files (blob_hash);
+
+
+CREATE RULE reference_blob_with_images_insert AS ON INSERT TO horus_images
+  DO UPDATE horus_blobs SET ref_count = ref_count + 1
+  WHERE hash = new.blob_hash AND kind = new.blob_kind AND horus_blobs.owner = new.owner;
+
+CREATE RULE reference_blob_with_videos_insert AS ON INSERT TO horus_videos
+  DO UPDATE horus_blobs SET ref_count = ref_count + 1
+  WHERE hash = new.blob_hash AND kind = new.blob_kind AND horus_blobs.owner = new.owner;
+
+CREATE RULE reference_blob_with_files_insert AS ON INSERT TO horus_files
+  DO UPDATE horus_blobs SET ref_count = ref_count + 1
+  WHERE hash = new.blob_hash AND kind = new.blob_kind AND horus_blobs.owner = new.owner;
+
+
+CREATE RULE reference_blob_with_images_delete AS ON DELETE TO horus_images
+  DO UPDATE horus_blobs SET ref_count = ref_count - 1
+  WHERE hash = old.blob_hash AND kind = old.blob_kind AND horus_blobs.owner = old.owner;
+
+CREATE RULE reference_blob_with_videos_delete AS ON DELETE TO horus_videos
+  DO UPDATE horus_blobs SET ref_count = ref_count - 1
+  WHERE hash = old.blob_hash AND kind = old.blob_kind AND horus_blobs.owner = old.owner;
+
+CREATE RULE reference_blob_with_files_delete AS ON DELETE TO horus_files
+  DO UPDATE horus_blobs SET ref_count = ref_count - 1
+  WHERE hash = old.blob_hash AND kind = old.blob_kind AND horus_blobs.owner = old.owner;
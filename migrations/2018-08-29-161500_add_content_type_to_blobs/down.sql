@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE horus_blobs DROP COLUMN IF EXISTS content_type;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE horus_blobs ADD COLUMN content_type varchar NOT NULL DEFAULT 'application/octet-stream';
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM horus_job_schedules WHERE job_name = 'maintenance:reconcile';
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+INSERT INTO horus_job_schedules(job_name, cron_expression)
+  VALUES ('maintenance:reconcile', '0 0 4 * * *') -- daily at 4, reporting only
+  ON CONFLICT DO NOTHING;
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE horus_upload_sessions DROP COLUMN IF EXISTS upload_host;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Sessions started before this are left to whichever host comes along.
+ALTER TABLE horus_upload_sessions ADD COLUMN upload_host varchar NOT NULL DEFAULT '';
+ALTER TABLE horus_upload_sessions ALTER COLUMN upload_host DROP DEFAULT;
@@ -15,11 +15,14 @@ fn main()
     use self::routes::*;
     println!("Checking directory structure...");
     check_dirs();
+    println!("Using {} storage...", dbtools::storage::init());
+    println!("Keeping resumable uploads as {}...", dbtools::upload_host());
     println!("Initializing background job juggler...");
     start_job_juggler();
     println!("Igniting rocket...");
     rocket::ignite()
         .attach(Template::fairing())
+        .attach(shutdown::ShutdownFairing)
         .mount("/user", routes![user::show, user::update])
         .mount("/key", routes![key::validity_check])
         .mount("/paste", routes![paste::new, paste::update, paste::list, 
@@ -45,9 +48,17 @@ fn main()
         .mount("/dist", routes![dist::deploy, dist::enable_deployment, dist::get_version,
                                 dist::get_latest, dist::version_legacy])
         .mount("/static", routes![files::static_asset])
-        //.mount("/admin", routes![jobs::list_jobs, jobs::job_status])
+        .mount("/storage", routes![storage::signed, storage::public])
+        .mount("/upload", routes![upload::new_file, upload::new_file_exp,
+                                  upload::new_deployment, upload::status, upload::append,
+                                  upload::commit, upload::terminate])
+        .mount("/jobs", routes![jobs::list_active_jobs, jobs::list_all_jobs,
+                                jobs::retrieve_job_status, jobs::retrieve_job_state,
+                                jobs::job_detail, jobs::cancel_job,
+                                jobs::admin_list_jobs, jobs::admin_list_jobs_unfiltered,
+                                jobs::stuck_jobs, jobs::job_events, jobs::reconcile])
         .mount("/", routes![favicon, verify_ssl])
-        .catch(errors![http_errors::not_found])
+        .catch(errors![http_errors::not_found, http_errors::gone])
         .manage(self::dbtools::init_pool())
         .launch();
 }
@@ -88,16 +99,22 @@ fn check_dirs()
 
 fn start_job_juggler()
 {
-    use job_juggler::JobJuggler;
-    use std::thread;
+    use std::env;
+    use std::time::Duration;
 
-    let mut juggler = JobJuggler::new();
+    // More jugglers let long jobs (eg. deployments) run without holding up the rest.
+    let juggler_count = env::var("HORUS_JUGGLERS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(2);
 
-    if let Err(e) = juggler.initialize() {
+    // Seconds running jobs get to finish when the server is stopped.
+    let grace_period = env::var("HORUS_SHUTDOWN_GRACE")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(60);
+
+    if let Err(e) = job_juggler::start(juggler_count, Duration::from_secs(grace_period)) {
         panic!("Job juggler could not be initialized: {}", e);
     }
-
-    thread::spawn(move || {
-        juggler.juggle();
-    });
 }
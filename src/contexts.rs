@@ -1,12 +1,27 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
 use models::{HFile, FixedDateHImage, HPaste, HVideo};
 
+/// An item on one of the manage list pages, marked as expired if
+/// its expiration time has passed but it hasn't been removed yet.
+#[derive(Serialize)]
+pub struct ListItem<T: Serialize>
+{
+    #[serde(flatten)]
+    pub item: T,
+    pub expired: bool,
+    /// Where the item can be loaded from, for lists that embed it.
+    pub src: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct ImageList
 {
     pub title: String,
     pub page_title: String,
     pub editable: bool,
-    pub images: Vec<FixedDateHImage>,
+    pub images: Vec<ListItem<FixedDateHImage>>,
 }
 
 #[derive(Serialize)]
@@ -15,7 +30,7 @@ pub struct VideoList
     pub title: String,
     pub page_title: String,
     pub editable: bool,
-    pub videos: Vec<HVideo>,
+    pub videos: Vec<ListItem<HVideo>>,
 }
 
 #[derive(Serialize)]
@@ -23,7 +38,7 @@ pub struct PasteList
 {
     pub title: String,
     pub page_title: String,
-    pub pastes: Vec<HPaste>,
+    pub pastes: Vec<ListItem<HPaste>>,
     pub editable: bool,
 }
 
@@ -32,7 +47,7 @@ pub struct FileList
 {
     pub title: String,
     pub page_title: String,
-    pub files: Vec<HFile>,
+    pub files: Vec<ListItem<HFile>>,
     pub editable: bool,
 }
 
@@ -45,8 +60,10 @@ pub struct ManageImage
     pub editable: bool,
     pub date_added: String,
     pub password: Option<String>,
-    pub img_src: Option<String>,
-    pub is_expiry: bool
+    pub img_src: String,
+    pub is_expiry: bool,
+    pub expiration_time: Option<NaiveDateTime>,
+    pub expired: bool,
 }
 
 #[derive(Serialize)]
@@ -58,8 +75,10 @@ pub struct ManageVideo
     pub editable: bool,
     pub date_added: String,
     pub is_expiry: bool,
+    pub expiration_time: Option<NaiveDateTime>,
+    pub expired: bool,
     pub password: Option<String>,
-    pub vid_src: Option<String>
+    pub vid_src: String,
 }
 
 #[derive(Serialize)]
@@ -70,6 +89,8 @@ pub struct ManageFile
     pub page_title: String,
     pub date_added: String,
     pub is_expiry: bool,
+    pub expiration_time: Option<NaiveDateTime>,
+    pub expired: bool,
     pub password: Option<String>,
     pub editable: bool
 }
@@ -82,6 +103,7 @@ pub struct ManagePaste
     pub page_title: String,
     pub paste: HPaste,
     pub editable: bool,
+    pub expired: bool,
 }
 
 #[derive(Serialize)]
@@ -95,6 +117,7 @@ pub struct ShowPaste
 pub struct ShowVideo
 {
     pub item: HVideo,
+    pub src: String,
     pub meta_tag: Option<String>,
     pub password: bool,
 }
@@ -104,9 +127,18 @@ pub struct ShowImage
 {
     pub password: bool,
     pub item: FixedDateHImage,
+    pub src: String,
     pub meta_tag: Option<String>,
 }
 
+#[derive(Serialize)]
+pub struct ShowFile
+{
+    #[serde(flatten)]
+    pub item: HFile,
+    pub src: String,
+}
+
 #[derive(Serialize)]
 pub struct ShowAccount
 {
@@ -10,7 +10,7 @@ use rocket::request::{self, FromRequest, Request};
 use models::{DeploymentKey, HPaste, LicenseKey, SessionToken};
 use forms::HNewPasteForm;
 use {dbtools, Pool};
-use {DbConn, fields::{self, FileName}};
+use {DbConn, fields::{self, ContentLength, FileName, UploadLength, UploadOffset}};
 
 /// Returns a NaiveDateTime given a duration consisting of a string
 /// that contains the `type` (`days`, `hours`, or `minutes`) and a value
@@ -69,6 +69,51 @@ impl<'a, 'r> FromRequest<'a, 'r> for FileName
     }
 }
 
+/// Reads a header holding a size in bytes, as used by resumable uploads.
+fn byte_count_header(request: &Request, name: &str) -> request::Outcome<i64, String>
+{
+    let values: Vec<_> = request.headers().get(name).collect();
+
+    if values.len() != 1 {
+        return Outcome::Failure((Status::BadRequest, format!("Supply exactly one {}.", name)));
+    }
+
+    match values[0].trim().parse::<i64>() {
+        Ok(count) if count >= 0 => Outcome::Success(count),
+        _ => Outcome::Failure((Status::BadRequest, format!("Invalid {}.", name))),
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for UploadLength
+{
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<UploadLength, Self::Error>
+    {
+        byte_count_header(request, "Upload-Length").map(UploadLength)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for UploadOffset
+{
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<UploadOffset, Self::Error>
+    {
+        byte_count_header(request, "Upload-Offset").map(UploadOffset)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ContentLength
+{
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ContentLength, Self::Error>
+    {
+        byte_count_header(request, "Content-Length").map(ContentLength)
+    }
+}
+
 impl<'a, 'r> FromRequest<'a, 'r> for DeploymentKey
 {
     type Error = String;
@@ -0,0 +1,326 @@
+use std::{fmt, fs};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+use diesel::{self, prelude::*};
+use diesel::pg::PgConnection;
+use diesel::result::Error as DieselError;
+use diesel::sql_types::{Integer, Text};
+use sha2::{Digest, Sha256};
+
+use dbtools::{get_path_partial_upload, get_random_char_id, mime};
+use dbtools::storage::{self, UploadError, Visibility};
+use models::{Blob, BlobKind};
+use schema::horus_blobs;
+
+// Uploads are stored once per content, kind and owner: each one is hashed with
+// SHA-256 and stored as the blob of its kind and owner with that hash, unless there
+// already is one. Blobs aren't shared between owners, as the visibility of a blob
+// follows the passwords of the resources referencing it. Images, videos and files
+// reference their blob by `blob_hash`, `blob_kind` and their `owner`, and the
+// database counts the references (see the `create_blobs` migration). The object of
+// a blob is only deleted once nothing references it anymore.
+
+/// An upload that was written to the local disk, next to the resumable uploads,
+/// and hashed on the way. The file is removed when this is dropped.
+pub struct Spooled
+{
+    file: PathBuf,
+    pub hash: String,
+    pub size: u64,
+    /// The start of the upload, to detect its type with (see `mime::detect`).
+    pub head: Vec<u8>,
+}
+
+impl Spooled
+{
+    /// Opens the spooled upload for reading from the start.
+    pub fn open(&self) -> Result<File, UploadError>
+    {
+        File::open(&self.file).map_err(|e| {
+            UploadError::Store(format!("Couldn't open {}: {}", self.file.display(), e))
+        })
+    }
+}
+
+impl Drop for Spooled
+{
+    fn drop(&mut self)
+    {
+        let _ = fs::remove_file(&self.file);
+    }
+}
+
+/// Why storing or releasing a blob failed.
+#[derive(Debug)]
+pub enum BlobError
+{
+    /// The upload couldn't be read or stored.
+    Upload(UploadError),
+    /// The object couldn't be deleted or have its visibility changed.
+    Storage(String),
+    Database(DieselError),
+}
+
+impl fmt::Display for BlobError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self {
+            BlobError::Upload(ref e) => write!(f, "{}", e),
+            BlobError::Storage(ref e) => write!(f, "{}", e),
+            BlobError::Database(ref e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+/// Writes `data` to the local disk, hashing it as it's read. Uploads have to
+/// be hashed before it's known where, and whether, they're stored.
+pub fn spool(data: &mut dyn Read) -> Result<Spooled, UploadError>
+{
+    let mut spooled = Spooled {
+        file: get_path_partial_upload(&get_random_char_id(32)),
+        hash: String::new(),
+        size: 0,
+        head: Vec::new(),
+    };
+
+    let store_error = |e| UploadError::Store(format!("Couldn't spool upload: {}", e));
+    if let Some(dir) = spooled.file.parent() {
+        fs::create_dir_all(dir).map_err(&store_error)?;
+    }
+
+    let mut out = File::create(&spooled.file).map_err(&store_error)?;
+    let mut hasher = Sha256::default();
+    let mut buffer = [0; 64 * 1024];
+
+    loop {
+        let read = match data.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(UploadError::Read(e)),
+        };
+
+        if spooled.head.len() < mime::SNIFF_LEN {
+            let missing = mime::SNIFF_LEN - spooled.head.len();
+            spooled.head.extend_from_slice(&buffer[..read.min(missing)]);
+        }
+
+        hasher.input(&buffer[..read]);
+        out.write_all(&buffer[..read]).map_err(&store_error)?;
+        spooled.size += read as u64;
+    }
+
+    spooled.hash = storage::hex(&hasher.result());
+    Ok(spooled)
+}
+
+/// Stores `spooled` as the blob of `kind` and `owner` with its hash and `content_type`
+/// unless that blob already exists, then calls `reference` to insert the resource of
+/// `owner` that references the blob. Inserting happens while the blob is locked, so it can't be
+/// released in between. New blobs are public, like new resources.
+pub fn store<T, F>(
+    spooled: &Spooled,
+    kind: BlobKind,
+    owner: i32,
+    content_type: &str,
+    conn: &PgConnection,
+    reference: F,
+) -> Result<T, BlobError>
+where
+    F: FnOnce(&Blob) -> QueryResult<T>,
+{
+    // Storing a large upload takes a while, so it's done before locking anything.
+    // Uploads of the same content racing each other store the same object at the
+    // same path. If one fails to reference the blob, its object is an orphan for
+    // `maintenance:reconcile` to find.
+    let existing = horus_blobs::table
+        .find((&spooled.hash, kind.name(), owner))
+        .get_result::<Blob>(conn)
+        .optional()
+        .map_err(BlobError::Database)?;
+    let stored = existing.is_none();
+    if stored {
+        let path = kind.path(owner, &spooled.hash);
+        put(spooled, &path, content_type).map_err(BlobError::Upload)?;
+    }
+
+    let mut error = None;
+    let result = conn.transaction::<_, DieselError, _>(|| {
+        let blob = match lock(&spooled.hash, kind, owner, "", conn)? {
+            Some(blob) => {
+                // The new resource has no password yet, see `visibility`.
+                if visibility(&blob.hash, kind, owner, conn)? == Visibility::Private {
+                    let public = storage::backend()
+                        .set_visibility(&blob.filepath, Visibility::Public);
+                    if let Err(e) = public {
+                        error = Some(BlobError::Storage(e));
+                        return Err(DieselError::RollbackTransaction);
+                    }
+                }
+                blob
+            }
+            None => {
+                let new_blob = Blob::new(
+                    spooled.hash.clone(),
+                    kind,
+                    owner,
+                    spooled.size as i64,
+                    content_type.to_string(),
+                );
+
+                // Uploads of the same content wait here for each other.
+                let inserted = diesel::insert_into(horus_blobs::table)
+                    .values(&new_blob)
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+                let blob =
+                    lock(&spooled.hash, kind, owner, "", conn)?.ok_or(DieselError::NotFound)?;
+
+                // The blob existed before, but was released since along with its object.
+                if inserted == 1 && !stored {
+                    if let Err(e) = put(spooled, &blob.filepath, &blob.content_type) {
+                        error = Some(BlobError::Upload(e));
+                        return Err(DieselError::RollbackTransaction);
+                    }
+                }
+                blob
+            }
+        };
+
+        reference(&blob)
+    });
+
+    match (result, error) {
+        (_, Some(e)) => Err(e),
+        (Ok(result), None) => Ok(result),
+        (Err(e), None) => Err(BlobError::Database(e)),
+    }
+}
+
+/// Deletes the blob and its object if nothing references it anymore, returning
+/// whether it did. Called after deleting a resource that referenced it.
+pub fn release(
+    hash: &str,
+    kind: BlobKind,
+    owner: i32,
+    conn: &PgConnection,
+) -> Result<bool, BlobError>
+{
+    let mut error = None;
+    let result = conn.transaction::<_, DieselError, _>(|| {
+        // A blob that got referenced again in the meantime isn't locked.
+        let blob = match lock(hash, kind, owner, "AND ref_count <= 0", conn)? {
+            Some(blob) => blob,
+            None => return Ok(false),
+        };
+
+        if let Err(e) = storage::backend().delete(&blob.filepath) {
+            error = Some(BlobError::Storage(e));
+            return Err(DieselError::RollbackTransaction);
+        }
+
+        diesel::delete(&blob).execute(conn)?;
+        Ok(true)
+    });
+
+    match (result, error) {
+        (_, Some(e)) => Err(e),
+        (Ok(released), None) => Ok(released),
+        (Err(e), None) => Err(BlobError::Database(e)),
+    }
+}
+
+/// Returns the hashes, kinds and owners of the blobs nothing references, eg. because
+/// their references were deleted along with their owner, or releasing them failed.
+pub fn unreferenced(conn: &PgConnection) -> QueryResult<Vec<(String, BlobKind, i32)>>
+{
+    use schema::horus_blobs::dsl::*;
+
+    let blobs = horus_blobs
+        .filter(ref_count.le(0))
+        .select((hash, kind, owner))
+        .order((hash.asc(), kind.asc(), owner.asc()))
+        .get_results::<(String, String, i32)>(conn)?;
+
+    Ok(blobs
+        .into_iter()
+        .filter_map(|(blob_hash, blob_kind, blob_owner)| {
+            BlobKind::from_name(&blob_kind).map(|blob_kind| (blob_hash, blob_kind, blob_owner))
+        })
+        .collect())
+}
+
+/// The visibility a blob has to have: public while any of its references has
+/// no password, as that one is linked to publicly, and private otherwise.
+pub fn visibility(
+    blob_hash: &str,
+    kind: BlobKind,
+    owner: i32,
+    conn: &PgConnection,
+) -> QueryResult<Visibility>
+{
+    use schema::{horus_files, horus_images, horus_videos};
+
+    // Blobs are only referenced by resources of their kind and owner.
+    let public = match kind {
+        BlobKind::Image => horus_images::table
+            .filter(horus_images::blob_hash.eq(blob_hash))
+            .filter(horus_images::owner.eq(owner))
+            .filter(horus_images::password.is_null())
+            .count()
+            .get_result::<i64>(conn)?,
+        BlobKind::Video => horus_videos::table
+            .filter(horus_videos::blob_hash.eq(blob_hash))
+            .filter(horus_videos::owner.eq(owner))
+            .filter(horus_videos::password.is_null())
+            .count()
+            .get_result::<i64>(conn)?,
+        BlobKind::File => horus_files::table
+            .filter(horus_files::blob_hash.eq(blob_hash))
+            .filter(horus_files::owner.eq(owner))
+            .filter(horus_files::password.is_null())
+            .count()
+            .get_result::<i64>(conn)?,
+    };
+
+    if public > 0 {
+        Ok(Visibility::Public)
+    } else {
+        Ok(Visibility::Private)
+    }
+}
+
+/// Stores the content of `spooled` at `path`, publicly.
+fn put(spooled: &Spooled, path: &str, content_type: &str) -> Result<(), UploadError>
+{
+    let mut data = spooled.open()?;
+    storage::backend()
+        .put_stream(path, &mut data, content_type, Visibility::Public, None)
+        .map(|_| ())
+}
+
+/// Locks the blob of `kind` and `owner` with `hash` for the rest of the transaction,
+/// if it exists and matches `condition`.
+fn lock(
+    hash: &str,
+    kind: BlobKind,
+    owner: i32,
+    condition: &str,
+    conn: &PgConnection,
+) -> QueryResult<Option<Blob>>
+{
+    let lock_sql = format!(
+        "SELECT * FROM horus_blobs WHERE hash = $1 AND kind = $2 AND owner = $3 {} FOR UPDATE",
+        condition
+    );
+
+    diesel::sql_query(lock_sql)
+        .bind::<Text, _>(hash)
+        .bind::<Text, _>(kind.name())
+        .bind::<Integer, _>(owner)
+        .get_result::<Blob>(conn)
+        .optional()
+}
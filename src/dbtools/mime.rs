@@ -0,0 +1,146 @@
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+use rocket::http::ContentType;
+
+/// How many bytes from the start of an upload are looked at to detect its type.
+pub const SNIFF_LEN: usize = 512;
+/// The type of uploads that are neither recognized nor have a known extension.
+pub const UNKNOWN: &'static str = "application/octet-stream";
+
+/// Types browsers are allowed to show rather than download. Uploads are only
+/// stored as one of these if their content was recognized as it, see `detect`.
+const INLINE: &'static [&'static str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "video/webm",
+    "video/mp4",
+];
+
+/// Types that can run scripts when a browser shows them. Uploads are never
+/// stored as one of these, whatever their extension says.
+const ACTIVE: &'static [&'static str] = &[
+    "text/html",
+    "application/xhtml+xml",
+    "image/svg+xml",
+    "text/xml",
+    "application/xml",
+    "application/javascript",
+    "text/javascript",
+];
+
+/// Signatures at the start of the content of a type, checked in order.
+const MAGIC: &'static [(&'static [u8], &'static str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"), // empty archives
+    (b"PK\x07\x08", "application/zip"), // spanned archives
+    (b"\x1f\x8b", "application/gzip"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"OggS", "application/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"MZ", "application/vnd.microsoft.portable-executable"),
+];
+
+/// Detects the type of content from its first bytes (see `SNIFF_LEN`), and
+/// otherwise from the extension of `filename`. Extensions of types that are
+/// shown inline or can run scripts are ignored, that content is `UNKNOWN`.
+pub fn detect(head: &[u8], filename: Option<&str>) -> String
+{
+    if let Some(sniffed) = sniff(head) {
+        return sniffed.to_string();
+    }
+
+    let by_extension = filename
+        .and_then(|f| Path::new(f).extension())
+        .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy().to_lowercase()))
+        .map(|content_type| content_type.to_string());
+
+    match by_extension {
+        Some(ref content_type) if !is_inline(content_type) && !is_active(content_type) => {
+            content_type.clone()
+        }
+        _ => UNKNOWN.to_string(),
+    }
+}
+
+/// Whether browsers may show content of `content_type` rather than download it.
+pub fn is_inline(content_type: &str) -> bool
+{
+    INLINE.contains(&essence(content_type).as_str())
+}
+
+/// Whether content of `content_type` can run scripts when a browser shows it.
+fn is_active(content_type: &str) -> bool
+{
+    let essence = essence(content_type);
+    ACTIVE.contains(&essence.as_str()) || essence.ends_with("+xml")
+}
+
+/// The type without its parameters, eg. `text/plain` of `text/plain; charset=utf-8`.
+fn essence(content_type: &str) -> String
+{
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase()
+}
+
+/// Recognizes the type of content from its first bytes.
+pub fn sniff(head: &[u8]) -> Option<&'static str>
+{
+    if let Some(&(_, content_type)) = MAGIC.iter().find(|&&(magic, _)| head.starts_with(magic)) {
+        return Some(content_type);
+    }
+
+    // Formats whose signature isn't right at the start.
+    if head.starts_with(b"RIFF") && head.len() >= 12 {
+        return match &head[8..12] {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        };
+    }
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return Some("video/mp4");
+    }
+    // WebM is Matroska with a doctype of its own in the EBML header.
+    if head.starts_with(b"\x1a\x45\xdf\xa3") {
+        if head.windows(4).any(|w| w == b"webm") {
+            return Some("video/webm");
+        }
+        return Some("video/x-matroska");
+    }
+
+    None
+}
+
+/// Reads up to `SNIFF_LEN` bytes from the start of `data` to detect its type with.
+/// `io::Cursor::new(head).chain(data)` reads all of it again.
+pub fn peek(data: &mut dyn Read) -> io::Result<Vec<u8>>
+{
+    let mut head = vec![0; SNIFF_LEN];
+    let mut filled = 0;
+
+    while filled < SNIFF_LEN {
+        match data.read(&mut head[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    head.truncate(filled);
+    Ok(head)
+}
@@ -1,4 +1,8 @@
+extern crate libc;
+
+use std::env;
 use std::ops::Deref;
+use std::path::PathBuf;
 
 use rocket::State;
 use rocket::request::Request;
@@ -10,7 +14,9 @@ use rand::Rng;
 
 use {DbConn, Pool};
 
-pub mod s3;
+pub mod blobs;
+pub mod mime;
+pub mod storage;
 
 /// Retrieves a database connection from the pool given a rocket request
 pub fn get_db_conn(request: &Request) -> Result<DbConn, ()>
@@ -60,6 +66,14 @@ pub fn get_path_image(filename: &str) -> String
     path_str
 }
 
+pub fn get_path_image_thumbnail(filename: &str) -> String
+{
+    let mut path_str = String::from("live/images/thumbnails/");
+    path_str += filename;
+    path_str += ".png";
+    path_str
+}
+
 pub fn get_path_file(filename: &str) -> String
 {
     let mut path_str = String::from("live/files/");
@@ -85,6 +99,54 @@ pub fn get_path_deployment(version: &str, packagename: &str) -> String
     path_str
 }
 
+/// Where the package of a deployment is uploaded to, under a key of its own. The
+/// deployment job moves it to `get_path_deployment` once the version is recorded,
+/// so a package that's being uploaded never replaces one that's in use.
+pub fn get_path_deployment_staging(staging_id: &str) -> String
+{
+    let mut path_str = String::from("/live/packages/staging/");
+    path_str += staging_id;
+    path_str += ".zip";
+    path_str
+}
+
+/// Where the data of a resumable upload is kept until it's committed, under
+/// `HORUS_UPLOAD_DIR` (`uploads/` in the working directory by default). Unlike
+/// stored objects this is always on the local disk, so an upload can only be
+/// continued on the host that started it, see `upload_host`.
+pub fn get_path_partial_upload(upload_id: &str) -> PathBuf
+{
+    let dir = env::var("HORUS_UPLOAD_DIR").unwrap_or("uploads".to_string());
+    PathBuf::from(dir).join(upload_id)
+}
+
+lazy_static! {
+    static ref UPLOAD_HOST: String = match env::var("HORUS_UPLOAD_HOST") {
+        Ok(ref host) if !host.is_empty() => host.clone(),
+        _ => hostname().expect("Couldn't read the hostname, set HORUS_UPLOAD_HOST."),
+    };
+}
+
+/// Identifies the host resumable uploads are kept on, `HORUS_UPLOAD_HOST` or the
+/// hostname if that isn't set. Hosts sharing `HORUS_UPLOAD_DIR` should set the same
+/// `HORUS_UPLOAD_HOST`.
+pub fn upload_host() -> &'static str
+{
+    &UPLOAD_HOST
+}
+
+fn hostname() -> Option<String>
+{
+    let mut name = [0u8; 256];
+    let read = unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) };
+    if read != 0 {
+        return None;
+    }
+
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8(name[..len].to_vec()).ok()
+}
+
 impl Deref for DbConn
 {
     type Target = PgConnection;
@@ -0,0 +1,338 @@
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use dbtools::get_random_char_id;
+use super::{hex, StorageBackend, StoredObject, UploadError, UrlSignature, Visibility};
+use super::sigv4::uri_encode;
+
+/// Objects are served from here by `routes::storage`.
+const URL_PREFIX: &'static str = "/storage/";
+/// Every object path starts with this directory (see `dbtools::get_path_image` and
+/// friends), nothing else under the root is ever stored or served.
+const OBJECT_DIR: &'static str = "live";
+/// Private objects are marked by an empty file at the same path under this directory.
+const PRIVATE_MARKERS: &'static str = ".private";
+/// Streamed objects are written here first and moved into place once complete.
+const PARTIAL_UPLOADS: &'static str = ".uploads";
+
+/// Stores objects as files under a root directory, for installs that can't use S3
+/// and for running offline. The server serves the files itself. With the working
+/// directory as the root, objects end up in the `live/` directories made at startup.
+pub struct LocalBackend
+{
+    root: PathBuf,
+    /// Signs the URLs of private objects. Configured rather than generated, so URLs
+    /// signed by one instance are accepted by the others and survive restarts.
+    secret: String,
+}
+
+impl LocalBackend
+{
+    pub fn new(root: PathBuf, secret: String) -> Self
+    {
+        LocalBackend {
+            root: root,
+            secret: secret,
+        }
+    }
+
+    /// Returns where the object at `path` is stored relative to `base`. Paths
+    /// outside of `OBJECT_DIR` or that could point outside of it (eg. containing
+    /// `..`) are refused.
+    fn resolve(&self, base: &Path, path: &str) -> Result<PathBuf, String>
+    {
+        let relative = Path::new(path.trim_left_matches('/'));
+        let is_plain = relative.components().all(|c| match c {
+            Component::Normal(_) => true,
+            _ => false,
+        });
+
+        if !is_plain || !relative.starts_with(OBJECT_DIR) || relative == Path::new(OBJECT_DIR) {
+            return Err(format!("Refusing to store an object at {}", path));
+        }
+
+        Ok(base.join(relative))
+    }
+
+    fn file(&self, path: &str) -> Result<PathBuf, String>
+    {
+        self.resolve(&self.root, path)
+    }
+
+    fn marker(&self, path: &str) -> Result<PathBuf, String>
+    {
+        self.resolve(&self.root.join(PRIVATE_MARKERS), path)
+    }
+
+    /// Signs the path, the expiry and the name downloads are saved as, if any.
+    fn signature(&self, path: &str, expires: i64, filename: Option<&str>) -> String
+    {
+        let mut mac = Hmac::<Sha256>::new_varkey(self.secret.as_bytes()).unwrap();
+        mac.input(path.trim_left_matches('/').as_bytes());
+        mac.input(b"\n");
+        mac.input(expires.to_string().as_bytes());
+        if let Some(filename) = filename {
+            mac.input(b"\n");
+            mac.input(filename.as_bytes());
+        }
+
+        hex(&mac.result().code())
+    }
+}
+
+impl StorageBackend for LocalBackend
+{
+    fn name(&self) -> &'static str
+    {
+        "local"
+    }
+
+    fn put(
+        &self,
+        path: &str,
+        data: &[u8],
+        _content_type: &str,
+        visibility: Visibility,
+        _filename: Option<&str>,
+    ) -> Result<(), String>
+    {
+        let file = self.file(path)?;
+        create_parent(&file)?;
+
+        File::create(&file)
+            .and_then(|mut f| f.write_all(data))
+            .map_err(|e| format!("Couldn't write {}: {}", file.display(), e))?;
+
+        self.set_visibility(path, visibility)
+    }
+
+    fn put_stream(
+        &self,
+        path: &str,
+        data: &mut dyn Read,
+        _content_type: &str,
+        visibility: Visibility,
+        _filename: Option<&str>,
+    ) -> Result<u64, UploadError>
+    {
+        let file = self.file(path).map_err(UploadError::Store)?;
+        let partial = self.root
+            .join(PARTIAL_UPLOADS)
+            .join(get_random_char_id(16));
+        create_parent(&file).map_err(UploadError::Store)?;
+        create_parent(&partial).map_err(UploadError::Store)?;
+
+        let written = write_stream(&partial, data).and_then(|written| {
+            fs::rename(&partial, &file).map(|_| written).map_err(|e| {
+                UploadError::Store(format!("Couldn't write {}: {}", file.display(), e))
+            })
+        });
+
+        if written.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+
+        let written = written?;
+        self.set_visibility(path, visibility)
+            .map(|_| written)
+            .map_err(UploadError::Store)
+    }
+
+    fn get(&self, path: &str) -> Result<Vec<u8>, String>
+    {
+        let file = self.file(path)?;
+        fs::read(&file).map_err(|e| format!("Couldn't read {}: {}", file.display(), e))
+    }
+
+    fn delete(&self, path: &str) -> Result<(), String>
+    {
+        let file = self.file(path)?;
+        fs::remove_file(&file).map_err(|e| format!("Couldn't delete {}: {}", file.display(), e))?;
+
+        self.set_visibility(path, Visibility::Public)
+    }
+
+    fn set_visibility(&self, path: &str, visibility: Visibility) -> Result<(), String>
+    {
+        let marker = self.marker(path)?;
+
+        match visibility {
+            Visibility::Private => {
+                create_parent(&marker)?;
+                File::create(&marker)
+                    .map(|_| ())
+                    .map_err(|e| format!("Couldn't make {} private: {}", path, e))
+            }
+            Visibility::Public if marker.exists() => fs::remove_file(&marker)
+                .map_err(|e| format!("Couldn't make {} public: {}", path, e)),
+            Visibility::Public => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String>
+    {
+        let prefix = prefix.trim_left_matches('/');
+        let mut objects = Vec::new();
+        let mut dirs = vec![self.root.join(OBJECT_DIR)];
+
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(format!("Couldn't list {}: {}", dir.display(), e)),
+            };
+
+            for entry in entries {
+                let entry = entry.map_err(|e| format!("Couldn't list {}: {}", dir.display(), e))?;
+                let file = entry.path();
+                let metadata = entry
+                    .metadata()
+                    .map_err(|e| format!("Couldn't read {}: {}", file.display(), e))?;
+
+                if metadata.is_dir() {
+                    dirs.push(file);
+                    continue;
+                }
+
+                // Object paths use slashes whatever the platform.
+                let path = match file.strip_prefix(&self.root) {
+                    Ok(relative) => relative
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    Err(_) => continue,
+                };
+
+                if path.starts_with(prefix) {
+                    let last_modified = metadata
+                        .modified()
+                        .map(DateTime::<Utc>::from)
+                        .map_err(|e| format!("Couldn't read {}: {}", file.display(), e))?;
+
+                    objects.push(StoredObject {
+                        path: path,
+                        size: metadata.len(),
+                        last_modified: last_modified,
+                    });
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), String>
+    {
+        let from_file = self.file(from)?;
+        let to_file = self.file(to)?;
+        create_parent(&to_file)?;
+
+        // Made private first, so the object isn't readable at `to` in between.
+        self.set_visibility(to, Visibility::Private)?;
+        fs::rename(&from_file, &to_file).map_err(|e| {
+            format!("Couldn't move {} to {}: {}", from_file.display(), to_file.display(), e)
+        })?;
+
+        self.set_visibility(from, Visibility::Public)
+    }
+
+    fn public_url(&self, path: &str) -> String
+    {
+        format!("{}{}", URL_PREFIX, path.trim_left_matches('/'))
+    }
+
+    fn signed_url(&self, path: &str, expires_in: u64) -> Result<String, String>
+    {
+        let expires = Utc::now().timestamp() + expires_in as i64;
+
+        Ok(format!(
+            "{}?expires={}&signature={}",
+            self.public_url(path),
+            expires,
+            self.signature(path, expires, None)
+        ))
+    }
+
+    /// `routes::storage` sends the object as an attachment if the URL has a filename.
+    fn download_url(&self, path: &str, filename: &str, expires_in: u64) -> Result<String, String>
+    {
+        let expires = Utc::now().timestamp() + expires_in as i64;
+
+        Ok(format!(
+            "{}?expires={}&signature={}&filename={}",
+            self.public_url(path),
+            expires,
+            self.signature(path, expires, Some(filename)),
+            uri_encode(filename, true)
+        ))
+    }
+
+    fn local_file(&self, path: &str, signature: Option<&UrlSignature>) -> Option<PathBuf>
+    {
+        let file = self.file(path).ok()?;
+        if !file.is_file() {
+            return None;
+        }
+
+        let readable = match signature {
+            Some(signature) => {
+                let expected = self.signature(path, signature.expires, signature.filename);
+                signature.expires >= Utc::now().timestamp()
+                    && constant_time_eq(signature.signature.as_bytes(), expected.as_bytes())
+            }
+            None => !self.marker(path).ok()?.exists(),
+        };
+
+        if readable {
+            Some(file)
+        } else {
+            None
+        }
+    }
+}
+
+fn create_parent(file: &Path) -> Result<(), String>
+{
+    match file.parent() {
+        Some(dir) => fs::create_dir_all(dir)
+            .map_err(|e| format!("Couldn't create {}: {}", dir.display(), e)),
+        None => Ok(()),
+    }
+}
+
+/// Copies `data` into a new file at `file`, telling failures to read apart
+/// from failures to write.
+fn write_stream(file: &Path, data: &mut dyn Read) -> Result<u64, UploadError>
+{
+    let mut out = File::create(file)
+        .map_err(|e| UploadError::Store(format!("Couldn't write {}: {}", file.display(), e)))?;
+    let mut buffer = [0; 64 * 1024];
+    let mut written = 0;
+
+    loop {
+        let read = match data.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(UploadError::Read(e)),
+        };
+
+        out.write_all(&buffer[..read])
+            .map_err(|e| UploadError::Store(format!("Couldn't write {}: {}", file.display(), e)))?;
+        written += read as u64;
+    }
+
+    Ok(written)
+}
+
+/// Compares signatures without giving away how much of them matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool
+{
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
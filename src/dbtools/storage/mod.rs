@@ -0,0 +1,226 @@
+use std::{env, fmt, io};
+use std::io::Read;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+
+pub mod local;
+pub mod s3;
+mod sigv4;
+
+pub use self::local::LocalBackend;
+pub use self::s3::{S3Backend, S3Config};
+use self::sigv4::uri_encode;
+
+
+/// Who can read a stored object without a signed URL.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Visibility
+{
+    Public,
+    Private,
+}
+
+/// Why storing a stream of data failed.
+#[derive(Debug)]
+pub enum UploadError
+{
+    /// The data couldn't be read, eg. because the client went away mid-upload.
+    Read(io::Error),
+    /// The data couldn't be stored.
+    Store(String),
+}
+
+impl fmt::Display for UploadError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self {
+            UploadError::Read(ref e) => write!(f, "Couldn't read the upload: {}", e),
+            UploadError::Store(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The query string of a signed URL, for backends that check signatures themselves.
+pub struct UrlSignature<'a>
+{
+    pub expires: i64,
+    pub signature: &'a str,
+    /// The name the object is downloaded as, see `StorageBackend::download_url`.
+    pub filename: Option<&'a str>,
+}
+
+/// An object found by `StorageBackend::list`.
+#[derive(Debug)]
+pub struct StoredObject
+{
+    pub path: String,
+    pub size: u64,
+    pub last_modified: DateTime<Utc>,
+}
+
+/// Somewhere uploaded resources are kept. Paths are relative to the root of the
+/// storage, eg. `live/images/<hash>.png`, see `dbtools::get_path_image` and friends.
+pub trait StorageBackend: Send + Sync
+{
+    /// A short name for the backend, used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Stores `data` at `path`, replacing anything already there, to be served as
+    /// `content_type` (see `dbtools::mime`). If `filename` is given, downloads of the
+    /// object are saved under that name. Backends that serve objects themselves
+    /// serve blobs as their `Blob::content_type` and other objects by the extension
+    /// of `path` instead, see `routes::storage`.
+    fn put(
+        &self,
+        path: &str,
+        data: &[u8],
+        content_type: &str,
+        visibility: Visibility,
+        filename: Option<&str>,
+    ) -> Result<(), String>;
+
+    /// Like `put`, but stores everything read from `data` without holding all of it
+    /// in memory. Returns the number of bytes stored. Nothing is stored at `path`
+    /// if `data` can't be read to the end.
+    fn put_stream(
+        &self,
+        path: &str,
+        data: &mut dyn Read,
+        content_type: &str,
+        visibility: Visibility,
+        filename: Option<&str>,
+    ) -> Result<u64, UploadError>;
+
+    /// Reads the object at `path`.
+    fn get(&self, path: &str) -> Result<Vec<u8>, String>;
+
+    /// Deletes the object at `path`.
+    fn delete(&self, path: &str) -> Result<(), String>;
+
+    /// Changes who can read the object at `path`.
+    fn set_visibility(&self, path: &str, visibility: Visibility) -> Result<(), String>;
+
+    /// Returns every object whose path starts with `prefix`, eg. `live/`. Paths
+    /// are returned without a leading slash.
+    fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String>;
+
+    /// Moves the object at `from` to `to`, replacing anything already there.
+    /// The moved object is private.
+    fn rename(&self, from: &str, to: &str) -> Result<(), String>;
+
+    /// Returns the URL of an object stored with public visibility.
+    fn public_url(&self, path: &str) -> String;
+
+    /// Returns a URL that can be used to read the object at `path`, whatever its
+    /// visibility, for the next `expires_in` seconds.
+    fn signed_url(&self, path: &str, expires_in: u64) -> Result<String, String>;
+
+    /// Like `signed_url`, but browsers save the object as `filename` rather than
+    /// showing it, even when the URL is on another origin.
+    fn download_url(&self, path: &str, filename: &str, expires_in: u64) -> Result<String, String>;
+
+    /// Backends that serve their objects through the server (see `routes::storage`)
+    /// return the file to serve for `path`, if it exists and may be read with the
+    /// given signature of a signed URL.
+    fn local_file(&self, _path: &str, _signature: Option<&UrlSignature>) -> Option<PathBuf>
+    {
+        None
+    }
+}
+
+lazy_static! {
+    static ref BACKEND: Box<dyn StorageBackend> = from_env();
+
+    /// Seconds the signed URLs handed out to clients stay valid for, from
+    /// `HORUS_SIGNED_URL_EXPIRY` (a minute by default).
+    static ref SIGNED_URL_EXPIRY: u64 = match env::var("HORUS_SIGNED_URL_EXPIRY") {
+        Err(_) => 60,
+        Ok(v) => match v.trim().parse::<u64>() {
+            // The limit of S3, applied to local storage too so either can be switched to.
+            Ok(secs) if secs >= 1 && secs <= sigv4::MAX_EXPIRY => secs,
+            _ => panic!(
+                "HORUS_SIGNED_URL_EXPIRY has to be between 1 and {} seconds, not '{}'.",
+                sigv4::MAX_EXPIRY, v
+            ),
+        },
+    };
+}
+
+/// Chooses the backend from `HORUS_STORAGE`, which is either `s3` (the default)
+/// or `local`. Local storage is kept under `HORUS_STORAGE_ROOT`, or the working
+/// directory if that isn't set, and signs its URLs with `HORUS_STORAGE_SECRET`, or
+/// Rocket's `ROCKET_SECRET_KEY` if that isn't set. See `S3Config::from_env` for
+/// configuring S3.
+fn from_env() -> Box<dyn StorageBackend>
+{
+    match env::var("HORUS_STORAGE").as_ref().map(|s| s.as_str()) {
+        Ok("local") => {
+            let root = env::var("HORUS_STORAGE_ROOT").unwrap_or(".".to_string());
+            let secret = match env::var("HORUS_STORAGE_SECRET")
+                .or_else(|_| env::var("ROCKET_SECRET_KEY"))
+            {
+                Ok(ref secret) if !secret.is_empty() => secret.clone(),
+                _ => panic!("Set HORUS_STORAGE_SECRET or ROCKET_SECRET_KEY to sign local URLs."),
+            };
+            Box::new(LocalBackend::new(PathBuf::from(root), secret))
+        }
+        Ok("s3") | Err(_) => Box::new(S3Backend::new(S3Config::from_env())),
+        Ok(other) => panic!("Unknown storage backend '{}', expected s3 or local.", other),
+    }
+}
+
+/// Picks the backend and reads the expiry of signed URLs, so a bad configuration
+/// is noticed at startup rather than on the first upload. Returns the name of the
+/// backend.
+pub fn init() -> &'static str
+{
+    ::lazy_static::initialize(&SIGNED_URL_EXPIRY);
+    BACKEND.name()
+}
+
+/// The storage backend chosen at startup.
+pub fn backend() -> &'static dyn StorageBackend
+{
+    &**BACKEND
+}
+
+/// Returns a signed URL for the object at `path` that is handed out to clients,
+/// valid for `HORUS_SIGNED_URL_EXPIRY` seconds.
+pub fn signed_url(path: &str) -> Result<String, String>
+{
+    BACKEND.signed_url(path, *SIGNED_URL_EXPIRY)
+}
+
+/// Returns a signed URL like `signed_url` that downloads the object at `path`
+/// as `filename`.
+pub fn download_url(path: &str, filename: &str) -> Result<String, String>
+{
+    BACKEND.download_url(path, filename, *SIGNED_URL_EXPIRY)
+}
+
+/// The `Content-Disposition` of a download saved as `filename`. Browsers that
+/// don't support `filename*` get a name with anything that can't be quoted replaced.
+pub fn attachment(filename: &str) -> String
+{
+    let quotable = filename
+        .chars()
+        .map(|c| match c {
+            ' '...'~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        quotable,
+        uri_encode(filename, true)
+    )
+}
+
+/// Lowercase hex, as used in signatures and for the hashes of blobs.
+pub fn hex(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
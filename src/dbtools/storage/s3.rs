@@ -0,0 +1,567 @@
+use std::env;
+use std::io::Read;
+
+use chrono::{DateTime, Utc};
+use reqwest::{self, header::Headers, Method, Response};
+
+use dbtools::mime;
+use super::{attachment, StorageBackend, StoredObject, UploadError, Visibility};
+use super::sigv4::{canonical_query, uri_encode, Signer};
+
+/// Streamed uploads are sent in parts of this size, the last part can be smaller.
+/// Only one part is held in memory at a time. S3 requires at least 5 MiB.
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Where objects are stored in S3, or in a store with the same API like MinIO.
+pub struct S3Config
+{
+    /// The scheme, host and port of the API, eg. `https://s3.eu-central-1.amazonaws.com`.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    /// Whether the bucket is addressed in the path (`<endpoint>/<bucket>/<key>`)
+    /// rather than in the host (`<bucket>.<endpoint host>/<key>`). Most
+    /// S3-compatible stores only support the former.
+    pub path_style: bool,
+    /// Public objects are linked to at `<public_url>/<key>`, eg. through a CDN.
+    pub public_url: String,
+}
+
+impl S3Config
+{
+    /// Reads the configuration from `HORUS_S3_ENDPOINT`, `HORUS_S3_BUCKET`,
+    /// `HORUS_S3_REGION`, `HORUS_S3_PATH_STYLE` and `HORUS_S3_PUBLIC_URL`. By
+    /// default objects are stored in the `horuscdn` bucket in `eu-central-1`.
+    pub fn from_env() -> Self
+    {
+        let region = env::var("HORUS_S3_REGION").unwrap_or("eu-central-1".to_string());
+        let bucket = env::var("HORUS_S3_BUCKET").unwrap_or("horuscdn".to_string());
+        let endpoint = env::var("HORUS_S3_ENDPOINT")
+            .unwrap_or(format!("https://s3.{}.amazonaws.com", region))
+            .trim_right_matches('/')
+            .to_string();
+        let path_style = env::var("HORUS_S3_PATH_STYLE")
+            .map(|v| v != "false" && v != "0")
+            .unwrap_or(true);
+
+        let mut config = S3Config {
+            endpoint: endpoint,
+            bucket: bucket,
+            region: region,
+            path_style: path_style,
+            public_url: String::new(),
+        };
+
+        config.public_url = match env::var("HORUS_S3_PUBLIC_URL") {
+            Ok(url) => url.trim_right_matches('/').to_string(),
+            Err(_) => config.url(""),
+        };
+        config
+    }
+
+    /// The scheme of the endpoint, `https` unless it says otherwise.
+    fn scheme(&self) -> &str
+    {
+        match self.endpoint.find("://") {
+            Some(i) => &self.endpoint[..i],
+            None => "https",
+        }
+    }
+
+    /// The host requests for objects are sent to, with the port if there is one.
+    fn host(&self) -> String
+    {
+        let endpoint_host = match self.endpoint.find("://") {
+            Some(i) => &self.endpoint[i + 3..],
+            None => &self.endpoint,
+        };
+
+        if self.path_style {
+            endpoint_host.to_string()
+        } else {
+            format!("{}.{}", self.bucket, endpoint_host)
+        }
+    }
+
+    /// The path of an object in requests. Object paths may or may not start
+    /// with a slash, either way they're keys from the root of the bucket.
+    fn object_path(&self, path: &str) -> String
+    {
+        let key = path.trim_left_matches('/');
+
+        if self.path_style {
+            format!("/{}/{}", self.bucket, key)
+        } else {
+            format!("/{}", key)
+        }
+    }
+
+    /// The URL of an object, without a trailing slash for the empty path.
+    fn url(&self, path: &str) -> String
+    {
+        let object_path = uri_encode(&self.object_path(path), false);
+        format!(
+            "{}://{}{}",
+            self.scheme(),
+            self.host(),
+            object_path.trim_right_matches('/')
+        )
+    }
+}
+
+/// Stores objects in an S3 bucket, using canned ACLs for their visibility.
+pub struct S3Backend
+{
+    config: S3Config,
+    signer: Signer,
+    client: reqwest::Client,
+}
+
+impl S3Backend
+{
+    /// Signs requests with the keys in `AWS_ACCESS` and `AWS_SECRET`, taken from
+    /// the environment when the server starts or else from when it was built.
+    pub fn new(config: S3Config) -> Self
+    {
+        let access_key = env::var("AWS_ACCESS").unwrap_or(::AWS_ACCESS.to_string());
+        let secret_key = env::var("AWS_SECRET").unwrap_or(::AWS_SECRET.to_string());
+
+        S3Backend {
+            signer: Signer::new(&access_key, &secret_key, &config.region),
+            config: config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Sends a signed request for the object at `path`. Responses with an error
+    /// status are turned into errors, which `action` describes.
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(String, String)],
+        headers: &[(String, String)],
+        body: Vec<u8>,
+        action: &str,
+    ) -> Result<Response, String>
+    {
+        let host = self.config.host();
+        let mut to_sign = vec![("host".to_string(), host)];
+        to_sign.extend(headers.iter().cloned());
+
+        // Requests for the bucket itself go without a trailing slash, see `url`.
+        let mut object_path = self.config.object_path(path);
+        if object_path.len() > 1 && object_path.ends_with('/') {
+            object_path.pop();
+        }
+
+        let signed_headers = self.signer.sign_request(
+            method.as_ref(),
+            &object_path,
+            query,
+            &to_sign,
+            &body,
+            Utc::now(),
+        );
+
+        // Sent by the client itself.
+        let mut request_headers = Headers::new();
+        for (name, value) in signed_headers.into_iter().filter(|&(ref n, _)| n != "host") {
+            request_headers.set_raw(name, value);
+        }
+
+        let mut url = self.config.url(path);
+        if !query.is_empty() {
+            url = format!("{}?{}", url, canonical_query(query));
+        }
+
+        let mut response = self.client
+            .request(method, &url)
+            .headers(request_headers)
+            .body(body)
+            .send()
+            .map_err(|e| format!("Couldn't {} {}: {}", action, path, e))?;
+
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            let mut message = String::new();
+            let _ = response.read_to_string(&mut message);
+            Err(format!(
+                "S3 responded with {} when trying to {} {}: {}",
+                response.status(),
+                action,
+                path,
+                message
+            ))
+        }
+    }
+
+    /// Returns a presigned URL for getting the object at `path` with `params`.
+    fn presigned_get(
+        &self,
+        path: &str,
+        params: &[(String, String)],
+        expires_in: u64,
+    ) -> Result<String, String>
+    {
+        let query = self.signer.presign(
+            "GET",
+            &self.config.host(),
+            &self.config.object_path(path),
+            params,
+            expires_in,
+            Utc::now(),
+        )?;
+
+        Ok(format!("{}?{}", self.config.url(path), query))
+    }
+
+    /// Starts a multipart upload to `path` of an object with `headers`, returning
+    /// the id of the upload.
+    fn create_multipart_upload(
+        &self,
+        path: &str,
+        headers: &[(String, String)],
+    ) -> Result<String, String>
+    {
+        let query = vec![("uploads".to_string(), String::new())];
+        let mut response = self.request(
+            Method::Post,
+            path,
+            &query,
+            headers,
+            Vec::new(),
+            "start uploading",
+        )?;
+
+        let mut body = String::new();
+        let _ = response.read_to_string(&mut body);
+        xml_value(&body, "UploadId")
+            .ok_or_else(|| format!("S3 didn't return an upload id for {}: {}", path, body))
+    }
+
+    /// Uploads `part` and everything after it in `data` as the parts of an
+    /// upload, then completes it.
+    fn upload_parts(
+        &self,
+        path: &str,
+        upload_id: &str,
+        mut part: Vec<u8>,
+        data: &mut dyn Read,
+    ) -> Result<u64, UploadError>
+    {
+        let mut etags = Vec::new();
+        let mut uploaded = 0;
+
+        while !part.is_empty() {
+            let number = etags.len() + 1;
+            let query = vec![
+                ("partNumber".to_string(), number.to_string()),
+                ("uploadId".to_string(), upload_id.to_string()),
+            ];
+
+            uploaded += part.len() as u64;
+            let response = self.request(Method::Put, path, &query, &[], part, "upload a part of")
+                .map_err(UploadError::Store)?;
+            let etag = response
+                .headers()
+                .get_raw("ETag")
+                .and_then(|raw| raw.one())
+                .map(|etag| String::from_utf8_lossy(etag).into_owned())
+                .ok_or_else(|| {
+                    UploadError::Store(format!("No ETag for part {} of {}", number, path))
+                })?;
+
+            etags.push(etag);
+            part = read_part(data)?;
+        }
+
+        let parts = etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| {
+                format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag)
+            })
+            .collect::<String>();
+        let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+        let query = vec![("uploadId".to_string(), upload_id.to_string())];
+
+        let mut response = self.request(
+            Method::Post,
+            path,
+            &query,
+            &[],
+            body.into_bytes(),
+            "finish uploading",
+        ).map_err(UploadError::Store)?;
+
+        // Completing can fail after S3 has already responded with 200.
+        let mut message = String::new();
+        let _ = response.read_to_string(&mut message);
+        if message.contains("<Error>") {
+            let message = format!("Couldn't finish uploading {}: {}", path, message);
+            return Err(UploadError::Store(message));
+        }
+
+        Ok(uploaded)
+    }
+}
+
+impl StorageBackend for S3Backend
+{
+    fn name(&self) -> &'static str
+    {
+        "s3"
+    }
+
+    fn put(
+        &self,
+        path: &str,
+        data: &[u8],
+        content_type: &str,
+        visibility: Visibility,
+        filename: Option<&str>,
+    ) -> Result<(), String>
+    {
+        let headers = object_headers(content_type, visibility, filename);
+        self.request(Method::Put, path, &[], &headers, data.to_vec(), "upload")
+            .map(|_| ())
+    }
+
+    /// Uploads that fit in one part are sent in a single request, larger ones
+    /// with a multipart upload which is aborted if anything goes wrong.
+    fn put_stream(
+        &self,
+        path: &str,
+        data: &mut dyn Read,
+        content_type: &str,
+        visibility: Visibility,
+        filename: Option<&str>,
+    ) -> Result<u64, UploadError>
+    {
+        let headers = object_headers(content_type, visibility, filename);
+        let part = read_part(data)?;
+
+        if (part.len() as u64) < PART_SIZE {
+            let size = part.len() as u64;
+            return self.request(Method::Put, path, &[], &headers, part, "upload")
+                .map(|_| size)
+                .map_err(UploadError::Store);
+        }
+
+        let upload_id = self.create_multipart_upload(path, &headers)
+            .map_err(UploadError::Store)?;
+        let uploaded = self.upload_parts(path, &upload_id, part, data);
+
+        if uploaded.is_err() {
+            let query = vec![("uploadId".to_string(), upload_id)];
+            let aborted = self.request(Method::Delete, path, &query, &[], Vec::new(), "abort");
+            if let Err(e) = aborted {
+                eprintln!("Couldn't abort the multipart upload of {}: {}", path, e);
+            }
+        }
+
+        uploaded
+    }
+
+    fn get(&self, path: &str) -> Result<Vec<u8>, String>
+    {
+        let mut response = self.request(Method::Get, path, &[], &[], Vec::new(), "download")?;
+        let mut data = Vec::new();
+
+        response
+            .read_to_end(&mut data)
+            .map_err(|e| format!("Couldn't download {}: {}", path, e))?;
+        Ok(data)
+    }
+
+    fn delete(&self, path: &str) -> Result<(), String>
+    {
+        self.request(Method::Delete, path, &[], &[], Vec::new(), "delete")
+            .map(|_| ())
+    }
+
+    /// Applies the canned ACL with a PutObjectAcl request.
+    fn set_visibility(&self, path: &str, visibility: Visibility) -> Result<(), String>
+    {
+        let acl = match visibility {
+            Visibility::Public => "public-read",
+            Visibility::Private => "private",
+        };
+
+        let query = vec![("acl".to_string(), String::new())];
+        let headers = vec![("x-amz-acl".to_string(), acl.to_string())];
+
+        self.request(Method::Put, path, &query, &headers, Vec::new(), "change the ACL of")
+            .map(|_| ())
+    }
+
+    /// Lists the bucket with ListObjectsV2, a page of up to 1000 objects at a time.
+    fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String>
+    {
+        let prefix = prefix.trim_left_matches('/');
+        let mut objects = Vec::new();
+        let mut continuation = None;
+
+        loop {
+            let mut query = vec![
+                ("list-type".to_string(), "2".to_string()),
+                ("prefix".to_string(), prefix.to_string()),
+            ];
+            if let Some(token) = continuation {
+                query.push(("continuation-token".to_string(), token));
+            }
+
+            let mut response = self.request(Method::Get, "", &query, &[], Vec::new(), "list")?;
+            let mut body = String::new();
+            response
+                .read_to_string(&mut body)
+                .map_err(|e| format!("Couldn't list {}: {}", prefix, e))?;
+
+            for contents in xml_values(&body, "Contents") {
+                let parsed = (
+                    xml_value(&contents, "Key"),
+                    xml_value(&contents, "Size").and_then(|s| s.parse::<u64>().ok()),
+                    xml_value(&contents, "LastModified")
+                        .and_then(|t| DateTime::parse_from_rfc3339(&t).ok()),
+                );
+
+                match parsed {
+                    (Some(key), Some(size), Some(last_modified)) => objects.push(StoredObject {
+                        path: xml_unescape(&key),
+                        size: size,
+                        last_modified: last_modified.with_timezone(&Utc),
+                    }),
+                    _ => return Err(format!("S3 listed an object oddly: {}", contents)),
+                }
+            }
+
+            continuation = match xml_value(&body, "IsTruncated").as_ref().map(|t| t.as_str()) {
+                Some("true") => Some(xml_value(&body, "NextContinuationToken").ok_or_else(|| {
+                    format!("S3 didn't return where to continue listing {}", prefix)
+                })?),
+                _ => return Ok(objects),
+            };
+        }
+    }
+
+    /// Copies the object within the bucket, then deletes the original.
+    fn rename(&self, from: &str, to: &str) -> Result<(), String>
+    {
+        let source = format!("/{}/{}", self.config.bucket, from.trim_left_matches('/'));
+        let headers = vec![
+            ("x-amz-acl".to_string(), "private".to_string()),
+            ("x-amz-copy-source".to_string(), uri_encode(&source, false)),
+        ];
+
+        let mut response = self.request(Method::Put, to, &[], &headers, Vec::new(), "copy to")?;
+
+        // Like completing an upload, copying can fail after a 200.
+        let mut message = String::new();
+        let _ = response.read_to_string(&mut message);
+        if message.contains("<Error>") {
+            return Err(format!("Couldn't copy {} to {}: {}", from, to, message));
+        }
+
+        self.delete(from)
+    }
+
+    fn public_url(&self, path: &str) -> String
+    {
+        format!("{}/{}", self.config.public_url, path.trim_left_matches('/'))
+    }
+
+    fn signed_url(&self, path: &str, expires_in: u64) -> Result<String, String>
+    {
+        self.presigned_get(path, &[], expires_in)
+    }
+
+    /// Has S3 send the object as an attachment, whatever it was stored with.
+    fn download_url(&self, path: &str, filename: &str, expires_in: u64) -> Result<String, String>
+    {
+        let params = vec![
+            ("response-content-disposition".to_string(), attachment(filename)),
+        ];
+        self.presigned_get(path, &params, expires_in)
+    }
+}
+
+/// The headers objects are created with. Browsers only show objects of the
+/// types `mime::is_inline` allows, and only if they have no filename. Anything
+/// else is downloaded, so an upload can't run scripts on the bucket's origin.
+fn object_headers(
+    content_type: &str,
+    visibility: Visibility,
+    filename: Option<&str>,
+) -> Vec<(String, String)>
+{
+    let disposition = match filename {
+        Some(f) => attachment(f),
+        None if mime::is_inline(content_type) => String::from("inline"),
+        None => String::from("attachment"),
+    };
+
+    // Private objects can't be accessed without a presigned URL.
+    let acl = match visibility {
+        Visibility::Public => "public-read",
+        Visibility::Private => "private",
+    };
+
+    vec![
+        ("x-amz-acl".to_string(), acl.to_string()),
+        ("content-disposition".to_string(), disposition),
+        ("content-type".to_string(), content_type.to_string()),
+    ]
+}
+
+/// Reads the next part of a streamed upload, which is empty once `data` has ended.
+fn read_part(data: &mut dyn Read) -> Result<Vec<u8>, UploadError>
+{
+    let mut part = Vec::new();
+    data.take(PART_SIZE)
+        .read_to_end(&mut part)
+        .map_err(UploadError::Read)?;
+    Ok(part)
+}
+
+/// Returns the text of the first `element` in an XML response.
+fn xml_value(xml: &str, element: &str) -> Option<String>
+{
+    let start_tag = format!("<{}>", element);
+    let start = xml.find(&start_tag)? + start_tag.len();
+    let end = xml[start..].find(&format!("</{}>", element))? + start;
+
+    Some(xml[start..end].to_string())
+}
+
+/// Returns the text of every `element` in an XML response, in order.
+fn xml_values(xml: &str, element: &str) -> Vec<String>
+{
+    let start_tag = format!("<{}>", element);
+    let end_tag = format!("</{}>", element);
+    let mut values = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&start_tag).map(|i| i + start_tag.len()) {
+        let end = match rest[start..].find(&end_tag) {
+            Some(end) => end + start,
+            None => break,
+        };
+
+        values.push(rest[start..end].to_string());
+        rest = &rest[end + end_tag.len()..];
+    }
+
+    values
+}
+
+/// Replaces the entities S3 escapes keys with in XML responses.
+fn xml_unescape(text: &str) -> String
+{
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
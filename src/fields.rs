@@ -23,6 +23,16 @@ const ROCKET_ENV: &str = dotenv!("ROCKET_ENV");
 
 pub struct FileName(pub String);
 
+/// The total size in bytes of a resumable upload, from the `Upload-Length` header.
+pub struct UploadLength(pub i64);
+
+/// How many bytes of a resumable upload a request continues from, from the
+/// `Upload-Offset` header.
+pub struct UploadOffset(pub i64);
+
+/// The size in bytes of a request body, from the `Content-Length` header.
+pub struct ContentLength(pub i64);
+
 pub trait Validatable
 {
     fn validate_fields(&self) -> Result<(), Vec<String>>;
@@ -1,5 +1,6 @@
 use chrono::NaiveDateTime;
 
+use models::job_structures::OrphanAction;
 use schema::*;
 
 #[derive(Deserialize, Serialize)]
@@ -47,3 +48,14 @@ pub struct HNewPasteForm
     pub is_expiry: bool,
     pub expiration_time: Option<NaiveDateTime>,
 }
+
+/// Options of a reconciliation queued by an admin, see `job_structures::Reconcile`.
+/// Left out options are those of the scheduled runs.
+#[derive(Deserialize)]
+pub struct ReconcileForm
+{
+    pub orphans: Option<OrphanAction>,
+    pub delete_dangling: Option<bool>,
+    pub dry_run: Option<bool>,
+    pub min_age: Option<i64>,
+}
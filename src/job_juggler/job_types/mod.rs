@@ -1,8 +1,13 @@
 use std::boxed::Box;
+use std::cell::RefCell;
 
+use diesel::{self, prelude::*};
 use diesel::pg::PgConnection;
+use serde_json;
 
 use job_juggler::JobResult;
+use models::{JobEventType, NewJob, NewJobEvent};
+use schema;
 
 /// A job that can produce logs to be stored in the database
 pub trait LoggableJob
@@ -17,6 +22,130 @@ pub trait LoggableJob
 /// A job that can be executed
 pub trait ExecutableJob
 {
+    /// Seconds the job may run for. Jobs still running after this are
+    /// marked as failed and the juggler moves on without them.
+    const MAX_RUNTIME: u64;
+
     /// Execute this job, returning the object itself at the end alongside the result.
-    fn execute(self, conn: &PgConnection) -> (Box<Self>, JobResult);
+    fn execute(self, ctx: &JobContext) -> (Box<Self>, JobResult);
+}
+
+/// The data of a progress event.
+#[derive(Serialize)]
+struct Progress<'a>
+{
+    percent: i16,
+    stage: &'a str,
+}
+
+/// Gives a running job access to the database and to the state of
+/// its row in `horus_jobs`.
+pub struct JobContext<'a>
+{
+    job_id: i32,
+    owner: i32,
+    conn: &'a PgConnection,
+    follow_ups: RefCell<Vec<NewJob>>,
+}
+
+impl<'a> JobContext<'a>
+{
+    pub fn new(job_id: i32, owner: i32, conn: &'a PgConnection) -> Self
+    {
+        JobContext {
+            job_id: job_id,
+            owner: owner,
+            conn: conn,
+            follow_ups: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn job_id(&self) -> i32
+    {
+        self.job_id
+    }
+
+    /// The user the job belongs to, follow-up jobs usually belong to them too.
+    pub fn owner(&self) -> i32
+    {
+        self.owner
+    }
+
+    pub fn connection(&self) -> &'a PgConnection
+    {
+        self.conn
+    }
+
+    /// Checks whether cancellation of the job has been requested. Long jobs should
+    /// check this between steps and return `JobResult::Cancelled` once it is set.
+    pub fn is_cancelled(&self) -> bool
+    {
+        use schema::horus_jobs::dsl::*;
+
+        horus_jobs
+            .find(self.job_id)
+            .select(cancel_requested)
+            .first::<bool>(self.conn)
+            .unwrap_or(false)
+    }
+
+    /// Adds a line to the logs of the job like `LoggableJob::log`, and also sends
+    /// it to anyone following the job instead of only storing it once the job ends.
+    pub fn log<J: LoggableJob>(&self, job: &mut J, line: &str)
+    {
+        job.log(line);
+        self.push_event(JobEventType::Log, line.to_string());
+    }
+
+    /// Reports how far along the job is, `percent` is between 0 and 100.
+    pub fn progress(&self, percent: i16, current_stage: &str)
+    {
+        use schema::horus_jobs::dsl::*;
+
+        let percent = percent.max(0).min(100);
+        let updated = diesel::update(horus_jobs.find(self.job_id))
+            .set((
+                progress.eq(Some(percent)),
+                stage.eq(Some(current_stage.to_string())),
+            ))
+            .execute(self.conn);
+
+        if let Err(e) = updated {
+            eprintln!("Couldn't store the progress of job {}: {}", self.job_id, e);
+        }
+
+        let data = Progress {
+            percent: percent,
+            stage: current_stage,
+        };
+        self.push_event(JobEventType::Progress, serde_json::to_string(&data).unwrap());
+    }
+
+    /// Enqueues `job` once this job completes, eg. a deployment could follow up
+    /// with building the delta packages of the new version. Follow-ups are
+    /// dropped if the job doesn't complete, so a retried job doesn't enqueue
+    /// them twice.
+    pub fn follow_up(&self, job: NewJob)
+    {
+        self.follow_ups.borrow_mut().push(job);
+    }
+
+    /// Takes the follow-up jobs added while the job ran.
+    pub fn take_follow_ups(&self) -> Vec<NewJob>
+    {
+        self.follow_ups.replace(Vec::new())
+    }
+
+    /// Records an event for the job. Events are only informational,
+    /// so failing to record one doesn't fail the job.
+    fn push_event(&self, event_type: JobEventType, data: String)
+    {
+        let inserted = diesel::insert_into(schema::horus_job_events::table)
+            .values(&NewJobEvent::new(self.job_id, event_type, data))
+            .execute(self.conn);
+
+        if let Err(e) = inserted {
+            eprintln!("Couldn't record an event of job {}: {}", self.job_id, e);
+        }
+    }
 }
@@ -1,252 +1,886 @@
 extern crate ctrlc;
 
-use std::{fmt, process, thread, time};
+use std::{fmt, process, thread};
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
 
 use diesel;
 use diesel::prelude::*;
 use diesel::pg::PgConnection;
+use diesel::result::Error;
+use diesel::sql_types::{BigInt, Integer, Jsonb, Nullable, Text};
+use serde::Serialize;
+use serde_json::{self, Value};
 
-use {dbtools, schema};
+use {dbtools, schema, shutdown};
 use models::{HJob, JobPriority, JobStatus, NewJob};
 use schema::horus_jobs::dsl::*;
 
 mod job_types;
-pub use self::job_types::{ExecutableJob, LoggableJob};
+mod notify;
+mod registry;
+mod schedule;
+pub use self::job_types::{ExecutableJob, JobContext, LoggableJob};
+pub use self::registry::{JobRegistry, JobType};
+use self::notify::{notify_jobs, JobListener};
+
+/// The number of jobs a juggler claims at once when its queue runs empty. Jobs
+/// waiting behind one a juggler is running are taken over by idle jugglers, so
+/// quick jobs don't wait behind a long one (eg. a deployment). Kept low as claimed
+/// jobs are loaded with their data, which can be large.
+const PREFETCH_COUNT: i64 = 4;
+/// Seconds between renewals of the lease on the jobs a juggler has claimed.
+const HEARTBEAT_INTERVAL: u64 = 10;
+/// Seconds without a heartbeat after which a claimed job is considered abandoned.
+const LEASE_DURATION: u64 = 60;
+/// Seconds before the first retry of a failed job, doubled on every further attempt.
+const RETRY_BASE_DELAY: u64 = 30;
+/// Upper bound on the time between retries, in seconds.
+const RETRY_MAX_DELAY: u64 = 3600;
+/// Seconds a job has to wait to be treated as one priority level higher. Jobs can
+/// age up to `JobPriority::System`, so a steady stream of system jobs can't starve
+/// user jobs forever.
+const AGING_INTERVAL: i64 = 10 * 60;
+/// The most jobs of one owner that may be queued or running at once, so one user
+/// with lots of jobs doesn't hold up everyone else. Doesn't apply to `GodMode` jobs.
+const MAX_ACTIVE_PER_OWNER: i64 = 4;
+/// Seconds an idle juggler waits to be notified of new jobs before looking for
+/// jobs anyway. Delayed jobs coming due don't send a notification.
+const IDLE_TIMEOUT: u64 = 30;
+
+/// The number of jobs being run by the jugglers of this process.
+static RUNNING_JOBS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Counts a job in `RUNNING_JOBS` for as long as it's held, also when the job panics.
+struct RunningJob;
+
+impl RunningJob
+{
+    fn start() -> Self
+    {
+        RUNNING_JOBS.fetch_add(1, Ordering::SeqCst);
+        RunningJob
+    }
+}
+
+impl Drop for RunningJob
+{
+    fn drop(&mut self)
+    {
+        RUNNING_JOBS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
 #[derive(Debug)]
 pub enum JobResult
 {
     Complete,
+    CompleteWithResult(Value), // stored as the result of the job, see `complete_with`
     Failed,
-    FailedWithReason(String)
+    FailedWithReason(String),
+    Cancelled, // stopped early after cancellation was requested
+}
+
+impl JobResult
+{
+    /// Completes the job with a result that clients can read back from the
+    /// job, eg. the id of something the job created.
+    pub fn complete_with<T: Serialize>(result: &T) -> JobResult
+    {
+        match serde_json::to_value(result) {
+            Ok(value) => JobResult::CompleteWithResult(value),
+            Err(e) => JobResult::FailedWithReason(format!("Couldn't serialize result: {}", e)),
+        }
+    }
 }
 
 pub struct JobJuggler
 {
+    worker_id: String,
     connection: PgConnection,
     job_queue: VecDeque<HJob>,
+    registry: JobRegistry,
+    /// Only jobs whose name is `LIKE` this are claimed, see `claim_only`.
+    name_filter: Option<String>,
+    listener: JobListener,
+    /// Dropped along with the juggler, eg. when its thread panics, which stops the
+    /// heartbeat so the supervisor can recover the jobs it had claimed.
+    alive: Option<Sender<()>>,
+}
+
+/// Starts `count` jugglers on their own threads alongside a supervisor that requeues
+/// jobs whose lease has expired. Can be called from more than one process; every
+/// juggler claims its jobs atomically. On SIGTERM or SIGINT the jugglers stop claiming
+/// jobs and the process exits once the running jobs are done, or after `grace_period`.
+pub fn start(count: usize, grace_period: Duration) -> Result<(), JobJugglerError>
+{
+    // Identifies the jugglers of this process so they can be told apart in the database.
+    let process_tag = dbtools::get_random_char_id(8);
+
+    for n in 0..count {
+        let mut juggler = JobJuggler::new(format!("{}-{}", process_tag, n))?;
+        juggler.initialize()?;
+
+        thread::spawn(move || {
+            juggler.juggle();
+        });
+    }
+
+    ctrlc::set_handler(move || {
+        if !shutdown::begin() {
+            eprintln!("Shutting down without waiting for running jobs...");
+            process::exit(1);
+        }
+
+        println!("Shutting down, waiting for running jobs to finish...");
+        JobJuggler::shutdown(&process_tag, grace_period);
+        process::exit(0);
+    }).unwrap();
+
+    thread::spawn(|| {
+        supervise();
+    });
+
+    Ok(())
 }
 
 impl JobJuggler
 {
-    pub fn new() -> Self
+    pub fn new(worker_id: String) -> Result<Self, JobJugglerError>
     {
-        JobJuggler {
-            connection: dbtools::get_db_conn_requestless().unwrap(),
+        use models::job_structures;
+
+        let mut registry = JobRegistry::new();
+        job_structures::register_jobs(&mut registry);
+
+        Self::with_registry(worker_id, registry)
+    }
+
+    /// A juggler that runs the job types in `registry`. Jobs of other types are
+    /// still claimed, and failed as there's no type to run them with.
+    pub fn with_registry(worker_id: String, registry: JobRegistry) -> Result<Self, JobJugglerError>
+    {
+        let connection = dbtools::get_db_conn_requestless()
+            .map_err(|_| JobJugglerError::new("Couldn't connect to the database.".to_string()))?;
+
+        Ok(JobJuggler {
+            worker_id: worker_id,
+            connection: connection,
             job_queue: VecDeque::new(),
-        }
+            registry: registry,
+            name_filter: None,
+            listener: JobListener::new().map_err(JobJugglerError::new)?,
+            alive: None,
+        })
+    }
+
+    /// Makes the juggler only claim jobs whose name is `LIKE` `pattern`, eg. `test:%`,
+    /// so jugglers started by tests leave the jobs of other tests alone.
+    pub fn claim_only(&mut self, pattern: &str)
+    {
+        self.name_filter = Some(pattern.to_string());
     }
 
     pub fn initialize(&mut self) -> Result<(), JobJugglerError>
     {
-        // Gets a max of 4 pending jobs (some of them have large amounts of data,
-        // so we don't want to store too much in ram) and puts them into the queue.
-        // TODO: Automatically mark jobs that are "running" as failed - again see
-        // comment below on if we plan to have more than one juggler running concurrently.
-        let start_jobs: Result<Vec<HJob>, _> = horus_jobs
-            .filter(job_status.ne(JobStatus::Complete as i32))
-            .filter(priority.ne(JobPriority::DoNotProcess as i32))
-            // If, for whatever reason, queued jobs are in there (eg. in event of crash)
-            // TODO: This will need to be changed if more than one juggler will run
-            // by associating a juggler with each queued job and running a scan if one
-            // of the job juggler threads crashes.
-            .order(job_status.eq(JobStatus::Queued as i32))
-            .order(priority.desc())
-            .order(time_queued.asc()) // oldest first
-            .limit(4)
-            .get_results::<HJob>(&self.connection);
-
-        if start_jobs.is_err() {
-            return Err(JobJugglerError::new(
-                "Couldn't get jobs from database.".to_string(),
-            ));
-        }
+        let worker_id = self.worker_id.clone();
+        let conn = dbtools::get_db_conn_requestless()
+            .map_err(|_| JobJugglerError::new("Couldn't connect to the database.".to_string()))?;
+        let (alive, juggler_gone) = mpsc::channel::<()>();
+        self.alive = Some(alive);
+
+        // Keep the lease alive on the jobs we claim for as long as we are around.
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) =
+                juggler_gone.recv_timeout(Duration::from_secs(HEARTBEAT_INTERVAL))
+            {
+                if let Err(e) = Self::heartbeat(&worker_id, &conn) {
+                    eprintln!("[{}] Couldn't renew job leases: {}", worker_id, e);
+                }
+            }
+            eprintln!("[{}] Juggler stopped, no longer renewing its leases.", worker_id);
+        });
 
-        let mut start_jobs = start_jobs.unwrap();
+        self.claim_jobs()
+    }
 
-        start_jobs.iter().for_each(|job| {
-            diesel::update(horus_jobs.find(job.id))
-                .set(job_status.eq(JobStatus::Queued as i32))
-                .execute(&self.connection)
-                .unwrap();
-        });
+    /// The execution loop that completes jobs and queues new ones perpetually. Jobs
+    /// are claimed back to back for as long as there are any, once there are none
+    /// left the juggler sleeps until it is notified of a new job.
+    pub fn juggle(mut self) -> !
+    {
+        println!("[{}] Starting juggle...", self.worker_id);
+        loop {
+            if shutdown::is_shutting_down() {
+                // The process exits once the running jobs are done.
+                thread::park();
+                continue;
+            }
 
-        while !start_jobs.is_empty() {
-            let job = start_jobs.remove(0);
-            self.job_queue.push_back(job);
+            // Anything queued from here on wakes us up below.
+            let notified = self.listener.clear();
+
+            // GodMode jobs skip the line, even ahead of jobs we've already claimed. Only
+            // newly queued jobs can be GodMode jobs we haven't seen, and an empty queue is
+            // refilled in order of priority below anyway.
+            if notified && !self.job_queue.is_empty() {
+                if let Err(e) = self.claim_god_mode_job() {
+                    eprintln!("[{}] {}", self.worker_id, e);
+                }
+            }
+
+            if self.job_queue.is_empty() {
+                if let Err(e) = self.claim_jobs() {
+                    eprintln!("[{}] {}", self.worker_id, e);
+                }
+            }
+
+            match self.job_queue.pop_front() {
+                Some(job) => self.run_job(job),
+                None => self.listener.wait(Duration::from_secs(IDLE_TIMEOUT)),
+            }
         }
+    }
 
-        Ok(())
+    /// Runs a claimed job and stores its result and logs.
+    fn run_job(&mut self, job: HJob)
+    {
+        let _running = RunningJob::start();
+        self.run_claimed_job(job);
     }
 
-    /// The execution loop that completes jobs and queues new ones perpetually.
-    pub fn juggle(mut self) -> !
+    fn run_claimed_job(&mut self, job: HJob)
     {
-        use std::time::Duration;
-        // Reset status of queued jobs
-        ctrlc::set_handler(|| {
-            println!("Resetting job status for queued jobs...");
-            Self::shutdown();
-            process::exit(0);
-        }).unwrap();
-
-        println!("Starting juggle...");
-        loop {
-            if !self.job_queue.is_empty() {
-                let current_job = self.job_queue.pop_front().unwrap();
-                let job_id = current_job.id;
-                let current_job_exec = Self::match_job_type(current_job);
+        let job_id = job.id;
+        let job_type = self.registry.get(&job.job_name);
+        let max_runtime = job_type.map(|t| t.max_runtime);
+
+        // The lease may have expired while the job sat in our queue.
+        let start_sql = format!(
+            "UPDATE horus_jobs SET job_status = {running}, attempts = attempts + 1, \
+             deadline = now() + $1 * interval '1 second', timed_out = false, \
+             progress = 0, stage = NULL \
+             WHERE id = $2 AND claimed_by = $3 AND job_status = {queued} RETURNING *",
+            running = JobStatus::Running as i32,
+            queued = JobStatus::Queued as i32,
+        );
 
-                diesel::update(horus_jobs.find(job_id))
-                    .set(job_status.eq(JobStatus::Running as i32))
-                    .execute(&self.connection)
-                    .unwrap();
+        let started = diesel::sql_query(start_sql)
+            .bind::<Nullable<BigInt>, _>(max_runtime.map(|t| t as i64))
+            .bind::<Integer, _>(job_id)
+            .bind::<Text, _>(&self.worker_id)
+            .get_result::<HJob>(&self.connection);
+
+        if started.is_err() {
+            eprintln!(
+                "[{}] Job {} was taken over, cancelled or its lease expired, skipping it.",
+                self.worker_id, job_id
+            );
+            return;
+        }
 
-                let (done_job, result) = current_job_exec.execute(&&self.connection);
+        // Idle jugglers can take over the jobs waiting behind this one.
+        if !self.job_queue.is_empty() {
+            if let Err(e) = notify_jobs(&self.connection) {
+                eprintln!("[{}] Couldn't notify jugglers of waiting jobs: {}", self.worker_id, e);
+            }
+        }
 
-                println!("Job finished with result {:?}", result);
+        let mut job = started.unwrap();
+        let watched = match job_type {
+            Some(job_type) => Self::watch(job_id, job.owner, job.job_data.take(), job_type),
+            None => Ok((
+                Err(format!("No job type is registered for job name '{}'.", job.job_name)),
+                Vec::new(),
+            )),
+        };
+
+        let mut job_logs = format!("\n=== Attempt {} of {} ===", job.attempts, job.max_attempts);
+        let mut timeout_clause = "";
+        let mut follow_ups = Vec::new();
+
+        // Jobs that can't be run at all are never retried, and neither are jobs that
+        // timed out, as they may still be running.
+        let (result, retryable) = match watched {
+            Ok((Ok((attempt_logs, result)), jobs)) => {
+                job_logs.push_str(&attempt_logs);
+                follow_ups = jobs;
+                (result, true)
+            }
+            Ok((Err(reason), _)) => (JobResult::FailedWithReason(reason), false),
+            Err(RecvTimeoutError::Timeout) => {
+                timeout_clause = ", timed_out = true";
+                let reason = format!("Timed out after {} seconds.", max_runtime.unwrap());
+                (JobResult::FailedWithReason(reason), false)
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                (JobResult::FailedWithReason("The job panicked.".to_string()), true)
+            }
+        };
 
-                let (result, failure_reason) = Self::match_job_result(result);
+        println!("[{}] Job {} finished with result {:?}", self.worker_id, job_id, result);
 
-                let mut job_logs = done_job.logs();
-                if let Some(s) = failure_reason {
-                    job_logs.push_str("\n---\nJob failed for reason:\n");
-                    job_logs.push_str(&s);
-                }
+        let (mut result, failure_reason, job_output) = Self::match_job_result(result);
 
-                // Add the result and the logs
-                diesel::update(horus_jobs.find(job_id))
-                    .set((logs.eq(job_logs), job_status.eq(result)))
-                    .execute(&self.connection)
-                    .unwrap();
+        if let Some(s) = failure_reason {
+            job_logs.push_str("\n---\nJob failed for reason:\n");
+            job_logs.push_str(&s);
+        }
+
+        // Failed jobs are retried with exponential backoff until they run out of attempts.
+        let mut retry_clause = String::new();
+        if result == JobStatus::Failed as i32 && retryable {
+            if JobContext::new(job_id, job.owner, &self.connection).is_cancelled() {
+                job_logs.push_str("\n---\nCancellation was requested, not retrying.");
+                result = JobStatus::Cancelled as i32;
+            } else if job.attempts < job.max_attempts {
+                let delay = Self::retry_delay(job.attempts);
+                job_logs.push_str(&format!("\n---\nRetrying in {} seconds.", delay));
+                // `run_after` is in UTC, like the times jobs are delayed to.
+                retry_clause = format!(
+                    ", claimed_by = NULL, heartbeat = NULL, \
+                     run_after = (now() AT TIME ZONE 'utc') + interval '{} seconds'",
+                    delay
+                );
+                result = JobStatus::Waiting as i32;
+            } else {
+                job_logs.push_str("\n---\nOut of attempts, moved to the dead letter queue.");
+                result = JobStatus::DeadLetter as i32;
             }
+        }
 
-            // Re-fill the queue if it's done
-            if self.job_queue.len() < 4 {
-                &mut self.check_for_new_job();
+        // Add the result and the logs of this attempt
+        let finish_sql = format!(
+            "UPDATE horus_jobs SET job_status = $1, logs = concat(logs, $2), job_result = $5{}{} \
+             WHERE id = $3 AND claimed_by = $4",
+            retry_clause, timeout_clause
+        );
+
+        if result != JobStatus::Complete as i32 {
+            follow_ups.clear();
+        }
+        let follow_up_count = follow_ups.len();
+
+        // The follow-ups are only enqueued if the result was stored, and vice versa.
+        let conn = &self.connection;
+        let worker_id = &self.worker_id;
+        let stored = conn.transaction::<_, Error, _>(|| {
+            let stored = diesel::sql_query(finish_sql)
+                .bind::<Integer, _>(result)
+                .bind::<Text, _>(job_logs)
+                .bind::<Integer, _>(job_id)
+                .bind::<Text, _>(worker_id)
+                .bind::<Nullable<Jsonb>, _>(job_output)
+                .execute(conn)?;
+
+            if stored != 1 {
+                return Err(Error::RollbackTransaction);
             }
 
-            // dont query too often
-            thread::sleep(Duration::from_millis(2500));
+            for follow_up in follow_ups {
+                diesel::insert_into(schema::horus_jobs::table)
+                    .values(&follow_up)
+                    .execute(conn)?;
+            }
+            Ok(())
+        });
+
+        if stored.is_err() {
+            eprintln!(
+                "[{}] Couldn't store the result of job {}, its lease may have expired.",
+                self.worker_id, job_id
+            );
+            return;
+        }
+
+        if follow_up_count > 0 {
+            println!(
+                "[{}] Job {} enqueued {} follow-up job(s).",
+                self.worker_id, job_id, follow_up_count
+            );
+            if let Err(e) = notify_jobs(conn) {
+                eprintln!("[{}] Couldn't notify jugglers of follow-up jobs: {}", self.worker_id, e);
+            }
+        }
+
+        if JobStatus::failed().contains(&result) {
+            if let Err(e) = fail_dependents(conn) {
+                eprintln!(
+                    "[{}] Couldn't fail the dependents of job {}: {}",
+                    self.worker_id, job_id, e
+                );
+            }
         }
     }
 
-    /// Enqueues one more job if there is one available
-    fn check_for_new_job(&mut self)
+    /// Runs the job on its own thread and waits for it for at most its maximum runtime,
+    /// so a job that hangs can't hold up the juggler. Threads can't be killed, so a job
+    /// that overruns is left to finish (or not) in the background. The follow-up jobs
+    /// of the job are returned alongside its result.
+    fn watch(
+        job_id: i32,
+        job_owner: i32,
+        job_data: Option<Vec<u8>>,
+        job_type: JobType,
+    ) -> Result<(Result<(String, JobResult), String>, Vec<NewJob>), RecvTimeoutError>
     {
-        // Get jobs that can be queued by priority, then oldest first
-        let new_job: Result<HJob, _> = horus_jobs
-            .filter(job_status.eq(JobStatus::Waiting as i32))
-            .filter(priority.ne(JobPriority::DoNotProcess as i32))
-            .order(priority.desc())
-            .order(time_queued.asc()) // oldest first
-            .first::<HJob>(&self.connection);
-
-        // Add to queue and set it to queued in the db so it doesn't get multiple queued.
-        if new_job.is_ok() {
-            let new_job = new_job.unwrap();
-            print!("Enqueueing new job (id={})...", new_job.id);
-            diesel::update(horus_jobs.find(new_job.id))
-                .set(job_status.eq(JobStatus::Queued as i32))
-                .execute(&self.connection)
-                .unwrap();
-            print!("done.\n");
-
-            self.job_queue.push_back(new_job);
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let result = match dbtools::get_db_conn_requestless() {
+                Ok(conn) => {
+                    let ctx = JobContext::new(job_id, job_owner, &conn);
+                    let result = job_type.run(job_data.as_ref().map(|d| d.as_slice()), &ctx);
+                    (result, ctx.take_follow_ups())
+                }
+                Err(_) => (
+                    Ok((
+                        String::new(),
+                        JobResult::FailedWithReason(
+                            "Couldn't connect to the database.".to_string(),
+                        ),
+                    )),
+                    Vec::new(),
+                ),
+            };
+
+            // The juggler has moved on if the job timed out.
+            let _ = sender.send(result);
+        });
+
+        receiver.recv_timeout(Duration::from_secs(job_type.max_runtime))
+    }
+
+    /// Seconds to wait before retrying a job that failed on the given attempt.
+    fn retry_delay(attempt: i32) -> u64
+    {
+        let exponent = (attempt.max(1) - 1).min(16) as u32;
+        (RETRY_BASE_DELAY * 2u64.pow(exponent)).min(RETRY_MAX_DELAY)
+    }
+
+    /// Atomically claims waiting jobs (by effective priority, then oldest first) until
+    /// the queue holds `PREFETCH_COUNT` jobs. Rows locked by other jugglers are skipped,
+    /// as are jobs with dependencies that haven't completed yet and jobs of owners who
+    /// already have `MAX_ACTIVE_PER_OWNER` jobs queued or running. An owner's waiting jobs
+    /// are ranked in claim order, so a single claim doesn't take more than the cap either.
+    /// Jugglers claiming at the same time can't see each other's claims, so the cap may
+    /// be exceeded briefly.
+    /// Jobs queued by a juggler that is busy running another job are taken over, the
+    /// juggler skips them when it gets to them.
+    fn claim_jobs(&mut self) -> Result<(), JobJugglerError>
+    {
+        let wanted = PREFETCH_COUNT - self.job_queue.len() as i64;
+
+        if wanted <= 0 {
+            return Ok(());
         }
+
+        // Jobs are claimed by their `effective_priority`, computed the same way here.
+        let order = format!(
+            "GREATEST(priority, LEAST(priority + floor(extract(epoch FROM now() - time_queued) \
+             / {aging}), {system})) DESC, time_queued ASC, id ASC",
+            aging = AGING_INTERVAL,
+            system = JobPriority::System as i32,
+        );
+
+        // A waiting job is claimed if its rank among the owner's runnable waiting jobs,
+        // added to the owner's active jobs, stays within the cap. Taken over jobs already
+        // count towards the cap of their owner.
+        let condition = format!(
+            "priority <> {do_not_process} AND (priority >= {god_mode} \
+             OR j.job_status = {queued} OR ( \
+                 SELECT count(*) FROM horus_jobs a \
+                 WHERE a.owner = j.owner AND a.job_status IN ({queued}, {running}) \
+             ) + ( \
+                 SELECT r.owner_rank FROM ( \
+                     SELECT w.id, row_number() OVER (ORDER BY {order}) AS owner_rank \
+                     FROM horus_jobs w \
+                     WHERE w.owner = j.owner AND w.job_status = {waiting} \
+                     AND w.priority < {god_mode} AND w.priority <> {do_not_process} \
+                     AND {runnable} \
+                 ) r WHERE r.id = j.id \
+             ) <= {max_active})",
+            do_not_process = JobPriority::DoNotProcess as i32,
+            god_mode = JobPriority::GodMode as i32,
+            queued = JobStatus::Queued as i32,
+            running = JobStatus::Running as i32,
+            waiting = JobStatus::Waiting as i32,
+            max_active = MAX_ACTIVE_PER_OWNER,
+            order = order,
+            runnable = Self::runnable("w"),
+        );
+
+        for job in self.claim(&condition, &order, wanted)? {
+            println!("[{}] Claimed job (id={}).", self.worker_id, job.id);
+            self.job_queue.push_back(job);
+        }
+
+        Ok(())
     }
 
-    /// Resets all jobs to waiting status in the event of SIGTERM
-    fn shutdown()
+    /// Claims the oldest waiting `GodMode` job and puts it in front of the queue,
+    /// regardless of how many jobs are already in it. Only one, as this juggler can't
+    /// run more than that next; the others are left to the other jugglers.
+    fn claim_god_mode_job(&mut self) -> Result<(), JobJugglerError>
     {
-        let conn = dbtools::get_db_conn_requestless().unwrap();
-        diesel::update(horus_jobs.filter(job_status.eq(JobStatus::Queued as i32)))
-            .set(job_status.eq(JobStatus::Waiting as i32))
-            .execute(&conn)
-            .unwrap();
+        let condition = format!("priority >= {}", JobPriority::GodMode as i32);
+
+        for job in self.claim(&condition, "time_queued ASC", 1)? {
+            println!("[{}] Claimed GodMode job (id={}).", self.worker_id, job.id);
+            self.job_queue.push_front(job);
+        }
+
+        Ok(())
+    }
+
+    /// Claims up to `limit` runnable jobs (aliased as `j`) matching `condition`, picked
+    /// and returned in the given order. Those are waiting jobs whose dependencies have all
+    /// completed, and jobs queued by another juggler that is running a job in the meantime.
+    /// Jobs no type is registered for are claimed too, running them fails them.
+    fn claim(&self, condition: &str, order: &str, limit: i64) -> Result<Vec<HJob>, JobJugglerError>
+    {
+        // RETURNING doesn't keep the order of the subquery, so the claimed jobs are sorted
+        // again, by the same clock.
+        let claim_sql = format!(
+            "WITH claimed AS ( \
+             UPDATE horus_jobs SET job_status = {queued}, claimed_by = $1, heartbeat = now() \
+             WHERE id IN ( \
+                 SELECT id FROM horus_jobs j \
+                 WHERE (job_status = {waiting} OR ( \
+                     job_status = {queued} AND claimed_by <> $1 AND EXISTS ( \
+                         SELECT 1 FROM horus_jobs r \
+                         WHERE r.claimed_by = j.claimed_by AND r.job_status = {running} \
+                     ) \
+                 )) AND {condition} AND {runnable} \
+                 ORDER BY {order} \
+                 LIMIT $2 \
+                 FOR UPDATE SKIP LOCKED \
+             ) RETURNING *) \
+             SELECT * FROM claimed ORDER BY {order}",
+            queued = JobStatus::Queued as i32,
+            waiting = JobStatus::Waiting as i32,
+            running = JobStatus::Running as i32,
+            condition = condition,
+            runnable = Self::runnable("j"),
+            order = order,
+        );
+
+        diesel::sql_query(claim_sql)
+            .bind::<Text, _>(&self.worker_id)
+            .bind::<BigInt, _>(limit)
+            .bind::<Nullable<Text>, _>(&self.name_filter)
+            .get_results::<HJob>(&self.connection)
+            .map_err(|e| JobJugglerError::new(format!("Couldn't claim jobs from database: {}", e)))
+    }
+
+    /// The conditions under which the job aliased as `alias` may be claimed: it's due,
+    /// its name matches the filter bound as `$3` and its dependencies have all completed.
+    fn runnable(alias: &str) -> String
+    {
+        format!(
+            "({a}.run_after IS NULL OR {a}.run_after <= (now() AT TIME ZONE 'utc')) \
+             AND ($3::text IS NULL OR {a}.job_name LIKE $3) \
+             AND NOT EXISTS ( \
+                 SELECT 1 FROM unnest({a}.depends_on) AS dep(id) \
+                 LEFT JOIN horus_jobs d ON d.id = dep.id \
+                 WHERE d.job_status IS DISTINCT FROM {complete} \
+             )",
+            a = alias,
+            complete = JobStatus::Complete as i32,
+        )
     }
 
-    /// Matches the job string to the correct type of deserialized job.
-    fn match_job_type(job: HJob) -> impl ExecutableJob + LoggableJob
+    /// Renews the lease on every job claimed by the juggler.
+    fn heartbeat(worker_id: &str, conn: &PgConnection) -> QueryResult<usize>
     {
-        use models::job_structures::*;
-
-        let data = job.job_data.unwrap();
-        match job.job_name.as_str() {
-            "thumbnail:image" => {
-                debinarize::CreateThumbnail(data.as_slice()).unwrap()
-            }, // TODO: thumbnail video
-            "deployment:deploy:win64" | "deployment:deploy:linux" | _ => {
-                debinarize::<Deployment>(data.as_slice()).unwrap()
-            },
+        let heartbeat_sql = format!(
+            "UPDATE horus_jobs SET heartbeat = now() \
+             WHERE claimed_by = $1 AND job_status IN ({queued}, {running})",
+            queued = JobStatus::Queued as i32,
+            running = JobStatus::Running as i32,
+        );
+
+        diesel::sql_query(heartbeat_sql)
+            .bind::<Text, _>(worker_id)
+            .execute(conn)
+    }
+
+    /// Releases the queued jobs of every juggler in this process in the event of SIGTERM,
+    /// then waits up to `grace_period` for the running jobs and the requests being handled
+    /// to finish. Jobs still running after that are marked as interrupted, the supervisor
+    /// puts them back in the queue.
+    fn shutdown(process_tag: &str, grace_period: Duration)
+    {
+        let conn = dbtools::get_db_conn_requestless().unwrap();
+        let worker_pattern = format!("{}-%", process_tag);
+        let release_sql = format!(
+            "UPDATE horus_jobs SET job_status = {waiting}, claimed_by = NULL \
+             WHERE job_status = {queued} AND claimed_by LIKE $1",
+            waiting = JobStatus::Waiting as i32,
+            queued = JobStatus::Queued as i32,
+        );
+
+        let release = || {
+            let released = diesel::sql_query(release_sql.as_str())
+                .bind::<Text, _>(&worker_pattern)
+                .execute(&conn);
+
+            if let Err(e) = released {
+                eprintln!("Couldn't release queued jobs: {}", e);
+            }
+        };
+
+        // Other processes can pick these up while we wait.
+        release();
+
+        // Requests that were being handled may still queue jobs, and shouldn't be cut off.
+        let deadline = Instant::now() + grace_period;
+        while (RUNNING_JOBS.load(Ordering::SeqCst) > 0 || shutdown::requests_in_flight() > 0)
+            && Instant::now() < deadline
+        {
+            thread::sleep(Duration::from_millis(250));
+        }
+
+        if shutdown::requests_in_flight() > 0 {
+            eprintln!(
+                "Exiting with {} request(s) still being handled.",
+                shutdown::requests_in_flight()
+            );
+        }
+
+        // A juggler may have claimed a job just as the shutdown began.
+        release();
+
+        let interrupt_sql = format!(
+            "UPDATE horus_jobs SET job_status = {interrupted}, claimed_by = NULL, \
+             heartbeat = NULL, \
+             logs = concat(logs, '\n---\nInterrupted by the shutdown of ', claimed_by, '.') \
+             WHERE job_status = {running} AND claimed_by LIKE $1",
+            interrupted = JobStatus::Interrupted as i32,
+            running = JobStatus::Running as i32,
+        );
+
+        match diesel::sql_query(interrupt_sql)
+            .bind::<Text, _>(&worker_pattern)
+            .execute(&conn)
+        {
+            Ok(0) => println!("All running jobs finished."),
+            Ok(n) => println!("Interrupted {} job(s) that didn't finish in time.", n),
+            Err(e) => eprintln!("Couldn't mark unfinished jobs as interrupted: {}", e),
         }
     }
 
     /// Returns the job status for the database given the job result.
     /// If it was `JobResult::FailureWithReason`, the failure message is
-    /// included in the tuple (otherwise it is `None`).
-    fn match_job_result(result: JobResult) -> (i32, Option<String>)
+    /// included in the tuple, and if it was `JobResult::CompleteWithResult`
+    /// the result is (otherwise they are `None`).
+    fn match_job_result(result: JobResult) -> (i32, Option<String>, Option<Value>)
     {
         match result {
-            JobResult::Complete => (JobStatus::Complete as i32, None),
-            JobResult::Failed => (JobStatus::Failed as i32, None),
-            JobResult::FailedWithReason(s) => (JobStatus::Failed as i32, Some(s))
+            JobResult::Complete => (JobStatus::Complete as i32, None, None),
+            JobResult::CompleteWithResult(v) => (JobStatus::Complete as i32, None, Some(v)),
+            JobResult::Failed => (JobStatus::Failed as i32, None, None),
+            JobResult::FailedWithReason(s) => (JobStatus::Failed as i32, Some(s), None),
+            JobResult::Cancelled => (JobStatus::Cancelled as i32, None, None),
         }
     }
 }
 
-/// Queues a job. Makes a quick query then returns success,
-/// then asynchronously uploads the data of the job, so you can
-/// use this in a request to make sure the job gets queued without
-/// waiting for the data to be sent to the database.
-pub fn enqueue_job(job: NewJob) -> Result<(), JobJugglerError>
+/// Periodically puts jobs whose lease has expired back into the waiting state, so
+/// that jobs claimed by a juggler that crashed (or whose process died) get run again.
+/// Running jobs that have used up their attempts go to the dead letter queue instead,
+/// and those that were asked to cancel are cancelled. Jobs interrupted by a shutdown
+/// are treated the same way. Also enqueues the jobs of
+/// recurring schedules when they come due, and fails the jobs that depend on jobs
+/// that didn't complete.
+pub fn supervise() -> !
 {
-    use schema::horus_jobs::dsl::*;
-
     let conn = dbtools::get_db_conn_requestless().unwrap();
-    let dataless = job.without_data();
 
-    let initial_insert_result = diesel::insert_into(schema::horus_jobs::table)
-        .values(&dataless)
-        .get_result::<HJob>(&conn);
+    let requeue_sql = format!(
+        "UPDATE horus_jobs SET claimed_by = NULL, heartbeat = NULL, \
+         logs = CASE \
+             WHEN job_status = {interrupted} THEN logs \
+             ELSE concat(logs, '\n---\nThe lease of ', claimed_by, ' expired.') \
+         END, \
+         job_status = CASE \
+             WHEN cancel_requested THEN {cancelled} \
+             WHEN job_status IN ({running}, {interrupted}) AND attempts >= max_attempts \
+                 THEN {dead_letter} \
+             ELSE {waiting} \
+         END \
+         WHERE job_status IN ({queued}, {running}, {interrupted}) \
+         AND (heartbeat IS NULL OR heartbeat < now() - interval '{lease} seconds')",
+        waiting = JobStatus::Waiting as i32,
+        queued = JobStatus::Queued as i32,
+        running = JobStatus::Running as i32,
+        interrupted = JobStatus::Interrupted as i32,
+        dead_letter = JobStatus::DeadLetter as i32,
+        cancelled = JobStatus::Cancelled as i32,
+        lease = LEASE_DURATION,
+    );
+
+    loop {
+        match diesel::sql_query(requeue_sql.as_str()).execute(&conn) {
+            Ok(0) => {}
+            Ok(n) => {
+                println!("Requeued {} job(s) with an expired lease.", n);
+                if let Err(e) = notify_jobs(&conn) {
+                    eprintln!("Couldn't notify jugglers of requeued jobs: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Couldn't requeue jobs with an expired lease: {}", e),
+        }
+
+        match schedule::enqueue_due_jobs(&conn) {
+            Ok(0) => {}
+            Ok(n) => println!("Enqueued {} scheduled job(s).", n),
+            Err(e) => eprintln!("Couldn't enqueue scheduled jobs: {}", e),
+        }
+
+        match fail_dependents(&conn) {
+            Ok(0) => {}
+            Ok(n) => println!("Failed {} job(s) whose dependencies didn't complete.", n),
+            Err(e) => eprintln!("Couldn't fail the dependents of failed jobs: {}", e),
+        }
+
+        thread::sleep(Duration::from_secs(LEASE_DURATION / 2));
+    }
+}
+
+/// Queues a job, returning its id so it can be polled. The row is inserted
+/// together with its data in one statement, so the jugglers never see a job
+/// without its data and a crash can't leave a half-inserted job behind. Jobs
+/// depending on jobs that don't exist are rejected.
+pub fn enqueue_job(job: NewJob) -> Result<i32, JobJugglerError>
+{
+    let conn = dbtools::get_db_conn_requestless();
 
-    if initial_insert_result.is_err() {
+    if conn.is_err() {
         return Err(JobJugglerError::new(
-            "Couldn't insert job into database.".to_string(),
+            "Couldn't connect to the database.".to_string(),
         ));
     }
+    let conn = conn.unwrap();
+
+    if !job.depends_on.is_empty() {
+        let found = horus_jobs
+            .filter(id.eq_any(job.depends_on.clone()))
+            .count()
+            .get_result::<i64>(&conn)
+            .map_err(|e| JobJugglerError::new(format!("Couldn't check dependencies: {}", e)))?;
+
+        if found != job.depends_on.len() as i64 {
+            return Err(JobJugglerError::new(format!(
+                "Some of the dependencies {:?} don't exist.",
+                job.depends_on
+            )));
+        }
+    }
 
-    // Spin up separate thread to do the work of moving the data.
-    thread::spawn(move || {
-        thread::sleep(time::Duration::from_millis(3000));
-        let mut db_obj = initial_insert_result.unwrap();
-        db_obj.job_data = job.job_data;
-        db_obj.priority = job.priority as i32;
+    let insert_result = diesel::insert_into(schema::horus_jobs::table)
+        .values(&job)
+        .returning(id)
+        .get_result::<i32>(&conn);
 
-        let update_res = diesel::update(horus_jobs.find(db_obj.id))
-            .set(&db_obj)
-            .execute(&conn);
+    match insert_result {
+        Ok(job_id) => {
+            println!("Queued job: id={}", job_id);
 
-        if update_res.is_err() {
-            eprintln!("Couldn't update job object...Writing failed.");
-            let update_res = diesel::update(horus_jobs.find(db_obj.owner))
-                .set(job_status.eq(JobStatus::Failed as i32))
-                .execute(&conn);
-            if update_res.is_err() {
-                eprintln!("Couldn't write failure to database! There may be broken jobs!");
+            // A dependency may have failed before the job was queued.
+            if !job.depends_on.is_empty() {
+                if let Err(e) = fail_dependents(&conn) {
+                    eprintln!("Couldn't check the dependencies of job {}: {}", job_id, e);
+                }
             }
+
+            // The job is queued either way, jugglers find it when they next look.
+            if let Err(e) = notify_jobs(&conn) {
+                eprintln!("Couldn't notify jugglers of job {}: {}", job_id, e);
+            }
+            Ok(job_id)
         }
-        println!(
-            "Finished queueing job: id={} => {}",
-            db_obj.id, db_obj.job_name
-        );
-    });
+        Err(e) => Err(JobJugglerError::new(format!(
+            "Couldn't insert job into database: {}",
+            e
+        ))),
+    }
+}
 
-    // Our initial insert went fine
+/// Lets the jugglers process a job that was queued with `JobPriority::DoNotProcess`
+/// while its input was still being prepared, eg. a deployment whose package is uploaded.
+pub fn release_job(job_id: i32, job_priority: JobPriority, conn: &PgConnection)
+    -> Result<(), JobJugglerError>
+{
+    diesel::update(horus_jobs.find(job_id))
+        .set(priority.eq(job_priority as i32))
+        .execute(conn)
+        .map_err(|e| JobJugglerError::new(format!("Couldn't release job {}: {}", job_id, e)))?;
+
+    if let Err(e) = notify_jobs(conn) {
+        eprintln!("Couldn't notify jugglers of job {}: {}", job_id, e);
+    }
     Ok(())
 }
 
+/// Gives up on a job that was queued with `JobPriority::DoNotProcess` because its
+/// input couldn't be prepared, so anyone following it sees why it never ran.
+pub fn abandon_job(job_id: i32, reason: &str, conn: &PgConnection) -> QueryResult<usize>
+{
+    diesel::update(horus_jobs.find(job_id))
+        .set((
+            job_status.eq(JobStatus::Failed as i32),
+            logs.eq(Some(format!("Job failed for reason:\n{}", reason))),
+        ))
+        .execute(conn)
+}
+
+/// The priority a job of `priority` is claimed by once it has waited `waited` seconds:
+/// one level higher for every `AGING_INTERVAL`, up to `JobPriority::System` at most.
+/// Jobs above that keep their priority.
+pub fn effective_priority(priority: i32, waited: i64) -> i32
+{
+    let levels = if waited > 0 { waited / AGING_INTERVAL } else { 0 };
+    let aged = (priority as i64 + levels).min(JobPriority::System as i64) as i32;
+
+    aged.max(priority)
+}
+
+/// Fails every waiting job that depends on a job that failed, was cancelled,
+/// went to the dead letter queue or was deleted, and in turn the jobs that depend
+/// on those. Returns the number of jobs failed.
+fn fail_dependents(conn: &PgConnection) -> QueryResult<usize>
+{
+    let failed = JobStatus::failed()
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let fail_sql = format!(
+        "WITH RECURSIVE doomed(id, cause) AS ( \
+             SELECT j.id, dep.id FROM horus_jobs j \
+             CROSS JOIN unnest(j.depends_on) AS dep(id) \
+             LEFT JOIN horus_jobs d ON d.id = dep.id \
+             WHERE j.job_status = {waiting} AND (d.id IS NULL OR d.job_status IN ({failed})) \
+             UNION \
+             SELECT j.id, doomed.id FROM horus_jobs j \
+             JOIN doomed ON doomed.id = ANY(j.depends_on) \
+             WHERE j.job_status = {waiting} \
+         ) \
+         UPDATE horus_jobs SET job_status = {failed_status}, claimed_by = NULL, \
+         logs = concat(logs, '\n---\nJob failed for reason:\nDependency ', doomed.cause, \
+         ' didn''t complete.') \
+         FROM doomed WHERE horus_jobs.id = doomed.id AND horus_jobs.job_status = {waiting}",
+        waiting = JobStatus::Waiting as i32,
+        failed = failed,
+        failed_status = JobStatus::Failed as i32,
+    );
+
+    diesel::sql_query(fail_sql).execute(conn)
+}
 
 #[derive(Debug)]
 pub struct JobJugglerError
@@ -269,3 +903,74 @@ impl JobJugglerError
         JobJugglerError { desc: d }
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::{effective_priority, JobJuggler, AGING_INTERVAL, RETRY_BASE_DELAY,
+                RETRY_MAX_DELAY};
+    use models::JobPriority;
+
+    #[test]
+    fn first_retry_waits_base_delay()
+    {
+        assert_eq!(JobJuggler::retry_delay(1), RETRY_BASE_DELAY);
+        // Attempts are counted from 1, anything below is treated as the first.
+        assert_eq!(JobJuggler::retry_delay(0), RETRY_BASE_DELAY);
+        assert_eq!(JobJuggler::retry_delay(-3), RETRY_BASE_DELAY);
+    }
+
+    #[test]
+    fn retry_delay_doubles()
+    {
+        assert_eq!(JobJuggler::retry_delay(2), RETRY_BASE_DELAY * 2);
+        assert_eq!(JobJuggler::retry_delay(3), RETRY_BASE_DELAY * 4);
+        assert_eq!(JobJuggler::retry_delay(5), RETRY_BASE_DELAY * 16);
+    }
+
+    #[test]
+    fn retry_delay_is_capped()
+    {
+        assert_eq!(JobJuggler::retry_delay(8), RETRY_MAX_DELAY);
+        assert_eq!(JobJuggler::retry_delay(17), RETRY_MAX_DELAY);
+        assert_eq!(JobJuggler::retry_delay(i32::max_value()), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn fresh_jobs_keep_their_priority()
+    {
+        let normal = JobPriority::Normal as i32;
+
+        assert_eq!(effective_priority(normal, 0), normal);
+        assert_eq!(effective_priority(normal, AGING_INTERVAL - 1), normal);
+        // Queued "in the future" by a clock that is ahead.
+        assert_eq!(effective_priority(normal, -AGING_INTERVAL), normal);
+    }
+
+    #[test]
+    fn waiting_jobs_age_a_level_per_interval()
+    {
+        let normal = JobPriority::Normal as i32;
+
+        assert_eq!(effective_priority(normal, AGING_INTERVAL), normal + 1);
+        assert_eq!(effective_priority(normal, AGING_INTERVAL * 5 / 2), normal + 2);
+    }
+
+    #[test]
+    fn jobs_age_up_to_system()
+    {
+        let system = JobPriority::System as i32;
+
+        assert_eq!(effective_priority(JobPriority::Normal as i32, AGING_INTERVAL * 10), system);
+        assert_eq!(effective_priority(JobPriority::High as i32, i64::max_value()), system);
+    }
+
+    #[test]
+    fn god_mode_jobs_keep_their_priority()
+    {
+        let god_mode = JobPriority::GodMode as i32;
+
+        assert_eq!(effective_priority(god_mode, 0), god_mode);
+        assert_eq!(effective_priority(god_mode, AGING_INTERVAL * 10), god_mode);
+    }
+}
@@ -0,0 +1,158 @@
+extern crate libc;
+extern crate pq_sys;
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use self::pq_sys::{ConnStatusType, ExecStatusType, PGconn};
+
+/// The channel jugglers listen on for newly queued jobs.
+const JOB_CHANNEL: &str = "horus_jobs";
+
+/// Wakes up idle jugglers when jobs are queued. Diesel can't receive
+/// notifications, so this holds a separate libpq connection to the database.
+pub struct JobListener
+{
+    conn: *mut PGconn,
+}
+
+// libpq connections can be used from any thread, as long as it's one at a time.
+unsafe impl Send for JobListener {}
+
+impl JobListener
+{
+    pub fn new() -> Result<Self, String>
+    {
+        let url = CString::new(::DATABASE_URL)
+            .map_err(|e| format!("Couldn't connect job listener: {}", e))?;
+        let listener = JobListener {
+            conn: unsafe { pq_sys::PQconnectdb(url.as_ptr()) },
+        };
+
+        if listener.conn.is_null() {
+            return Err("Couldn't connect job listener: out of memory".to_string());
+        }
+        if unsafe { pq_sys::PQstatus(listener.conn) } != ConnStatusType::CONNECTION_OK {
+            return Err(format!("Couldn't connect job listener: {}", listener.error()));
+        }
+
+        listener
+            .execute(&format!("LISTEN {}", JOB_CHANNEL))
+            .map_err(|e| format!("Couldn't listen for jobs: {}", e))?;
+        Ok(listener)
+    }
+
+    /// Discards the notifications received so far, returning whether there were
+    /// any. Called before looking for jobs, so that only jobs queued after the
+    /// last look wake the juggler up again.
+    pub fn clear(&self) -> bool
+    {
+        match self.drain() {
+            Ok(received) => received > 0,
+            Err(e) => {
+                eprintln!("Couldn't read job notifications: {}", e);
+                self.reconnect();
+                false
+            }
+        }
+    }
+
+    /// Blocks until a job is queued or the timeout passes.
+    pub fn wait(&self, timeout: Duration)
+    {
+        // Notifications that came in since the last look are already waiting.
+        if self.clear() {
+            return;
+        }
+
+        let mut socket = libc::pollfd {
+            fd: unsafe { pq_sys::PQsocket(self.conn) },
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_secs() * 1000 + u64::from(timeout.subsec_nanos() / 1_000_000);
+        let millis = millis.min(libc::c_int::max_value() as u64) as libc::c_int;
+
+        // What woke us up is read by the next `clear`.
+        if unsafe { libc::poll(&mut socket, 1, millis) } < 0 {
+            eprintln!("Couldn't wait for job notifications: {}", io::Error::last_os_error());
+            // Don't spin if waiting fails, the timeout is the fallback anyway.
+            thread::sleep(timeout);
+        }
+    }
+
+    /// Reads what the server sent, returning the number of notifications in it.
+    fn drain(&self) -> Result<usize, String>
+    {
+        if unsafe { pq_sys::PQconsumeInput(self.conn) } == 0 {
+            return Err(self.error());
+        }
+
+        let mut received = 0;
+        loop {
+            let notification = unsafe { pq_sys::PQnotifies(self.conn) };
+            if notification.is_null() {
+                return Ok(received);
+            }
+            unsafe { pq_sys::PQfreemem(notification as *mut _) };
+            received += 1;
+        }
+    }
+
+    /// Connects again after the connection was lost. Until it's back, jugglers
+    /// only look for jobs when their wait times out.
+    fn reconnect(&self)
+    {
+        unsafe { pq_sys::PQreset(self.conn) };
+
+        let listening = self.execute(&format!("LISTEN {}", JOB_CHANNEL));
+        if let Err(e) = listening {
+            eprintln!("Couldn't listen for jobs again: {}", e);
+        }
+    }
+
+    fn execute(&self, sql: &str) -> Result<(), String>
+    {
+        let sql = CString::new(sql).map_err(|e| e.to_string())?;
+
+        unsafe {
+            let result = pq_sys::PQexec(self.conn, sql.as_ptr());
+            let status = pq_sys::PQresultStatus(result);
+            pq_sys::PQclear(result);
+
+            if status == ExecStatusType::PGRES_COMMAND_OK {
+                Ok(())
+            } else {
+                Err(self.error())
+            }
+        }
+    }
+
+    /// The last error on the connection.
+    fn error(&self) -> String
+    {
+        let message = unsafe { CStr::from_ptr(pq_sys::PQerrorMessage(self.conn)) };
+        message.to_string_lossy().trim().to_string()
+    }
+}
+
+impl Drop for JobListener
+{
+    fn drop(&mut self)
+    {
+        unsafe { pq_sys::PQfinish(self.conn) };
+    }
+}
+
+/// Tells idle jugglers that there are jobs to claim. Inside a transaction
+/// the notification is only sent once the transaction commits.
+pub fn notify_jobs(conn: &PgConnection) -> QueryResult<usize>
+{
+    diesel::sql_query(format!("NOTIFY {}", JOB_CHANNEL)).execute(conn)
+}
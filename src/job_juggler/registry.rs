@@ -0,0 +1,120 @@
+use job_juggler::{ExecutableJob, JobContext, JobResult, LoggableJob};
+use models::job_structures::{debinarize, JobPayload};
+
+/// Decodes the data of a job and executes it, returning the logs
+/// of the finished job alongside its result. Jobs that can't be
+/// decoded return the reason instead.
+type JobRunner = fn(Option<&[u8]>, &JobContext) -> Result<(String, JobResult), String>;
+
+/// How to run the jobs of one registered type.
+#[derive(Clone, Copy)]
+pub struct JobType
+{
+    runner: JobRunner,
+    /// Seconds the job may run before the juggler gives up on it.
+    pub max_runtime: u64,
+}
+
+impl JobType
+{
+    /// Decodes and executes the job. Returns the reason the job couldn't
+    /// be run if its data couldn't be decoded.
+    pub fn run(&self, data: Option<&[u8]>, ctx: &JobContext) -> Result<(String, JobResult), String>
+    {
+        (self.runner)(data, ctx)
+    }
+}
+
+/// Maps job name prefixes to the types that execute them.
+pub struct JobRegistry
+{
+    types: Vec<(&'static str, JobType)>,
+}
+
+impl JobRegistry
+{
+    pub fn new() -> Self
+    {
+        JobRegistry {
+            types: Vec::new(),
+        }
+    }
+
+    /// Registers `T` as the handler for every job called `prefix` or
+    /// `prefix:<anything>`, eg. `deployment:deploy` handles `deployment:deploy:win64`.
+    pub fn register<T>(&mut self, prefix: &'static str)
+    where
+        T: ExecutableJob + LoggableJob + JobPayload,
+    {
+        self.insert(prefix, run_job::<T>, T::MAX_RUNTIME);
+    }
+
+    /// Like `register`, but jobs without data are run with `T::default()`. Used
+    /// for jobs that need no input, like those enqueued from a schedule.
+    pub fn register_default<T>(&mut self, prefix: &'static str)
+    where
+        T: ExecutableJob + LoggableJob + JobPayload + Default,
+    {
+        self.insert(prefix, run_default_job::<T>, T::MAX_RUNTIME);
+    }
+
+    /// Finds the type registered with the longest prefix matching the job name.
+    pub fn get(&self, name: &str) -> Option<JobType>
+    {
+        self.types
+            .iter()
+            .filter(|&&(prefix, _)| {
+                name == prefix
+                    || (name.starts_with(prefix) && name[prefix.len()..].starts_with(":"))
+            })
+            .max_by_key(|&&(prefix, _)| prefix.len())
+            .map(|&(_, job_type)| job_type)
+    }
+
+    fn insert(&mut self, prefix: &'static str, runner: JobRunner, max_runtime: u64)
+    {
+        if self.types.iter().any(|&(p, _)| p == prefix) {
+            panic!("A job type is already registered for {}!", prefix);
+        }
+
+        let job_type = JobType {
+            runner: runner,
+            max_runtime: max_runtime,
+        };
+        self.types.push((prefix, job_type));
+    }
+}
+
+fn run_job<T>(data: Option<&[u8]>, ctx: &JobContext) -> Result<(String, JobResult), String>
+where
+    T: ExecutableJob + LoggableJob + JobPayload,
+{
+    let data = match data {
+        Some(d) => d,
+        None => return Err("Job has no data.".to_string()),
+    };
+
+    match debinarize::<T>(data) {
+        Ok(job) => Ok(execute(job, ctx)),
+        Err(reason) => Err(format!("Couldn't decode job data: {}", reason)),
+    }
+}
+
+fn run_default_job<T>(
+    data: Option<&[u8]>,
+    ctx: &JobContext,
+) -> Result<(String, JobResult), String>
+where
+    T: ExecutableJob + LoggableJob + JobPayload + Default,
+{
+    match data {
+        Some(_) => run_job::<T>(data, ctx),
+        None => Ok(execute(T::default(), ctx)),
+    }
+}
+
+fn execute<T: ExecutableJob + LoggableJob>(job: T, ctx: &JobContext) -> (String, JobResult)
+{
+    let (done_job, result) = job.execute(ctx);
+    (done_job.logs(), result)
+}
@@ -0,0 +1,126 @@
+extern crate cron;
+
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::{self, prelude::*};
+use diesel::pg::PgConnection;
+use diesel::result::Error;
+use diesel::sql_types::Timestamp;
+
+use self::cron::Schedule;
+use super::notify::notify_jobs;
+use models::{JobPriority, JobSchedule, NewJob};
+use schema;
+
+/// Enqueues a job for every enabled schedule that has come due, then moves each
+/// schedule on to its next run. Schedules locked by another process are skipped,
+/// so every run is only enqueued once. Returns the number of jobs enqueued.
+pub fn enqueue_due_jobs(conn: &PgConnection) -> QueryResult<usize>
+{
+    let now = Utc::now().naive_utc();
+
+    conn.transaction::<_, Error, _>(|| {
+        let due = diesel::sql_query(
+            "SELECT * FROM horus_job_schedules WHERE enabled AND next_run <= $1 \
+             FOR UPDATE SKIP LOCKED",
+        ).bind::<Timestamp, _>(now)
+            .get_results::<JobSchedule>(conn)?;
+
+        let mut enqueued = 0;
+        for mut schedule in due {
+            let next = next_run_after(&schedule.cron_expression, now);
+
+            if next.is_none() {
+                eprintln!(
+                    "Disabling the schedule of {}, '{}' is not a valid cron expression.",
+                    schedule.job_name, schedule.cron_expression
+                );
+                schedule.enabled = false;
+                schedule.save_changes::<JobSchedule>(conn)?;
+                continue;
+            }
+
+            let mut job = NewJob::new(
+                schedule.owner,
+                schedule.job_name.clone(),
+                schedule.job_data.clone(),
+                JobPriority::System,
+            );
+            job.priority = schedule.priority;
+
+            diesel::insert_into(schema::horus_jobs::table)
+                .values(&job)
+                .execute(conn)?;
+
+            // Runs missed while the server was down are only made up for once.
+            schedule.last_run = Some(now);
+            schedule.next_run = next.unwrap();
+            schedule.save_changes::<JobSchedule>(conn)?;
+            enqueued += 1;
+        }
+
+        if enqueued > 0 {
+            notify_jobs(conn)?;
+        }
+
+        Ok(enqueued)
+    })
+}
+
+/// Returns the first time (in UTC) after `time` that matches the cron expression,
+/// or `None` if the expression is invalid or never matches again.
+pub fn next_run_after(cron_expression: &str, time: NaiveDateTime) -> Option<NaiveDateTime>
+{
+    let schedule = Schedule::from_str(cron_expression).ok()?;
+
+    schedule
+        .after(&DateTime::<Utc>::from_utc(time, Utc))
+        .next()
+        .map(|t| t.naive_utc())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    use super::next_run_after;
+
+    fn time(day: u32, hour: u32, minute: u32) -> NaiveDateTime
+    {
+        NaiveDate::from_ymd(2018, 8, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn finds_next_hourly_run()
+    {
+        assert_eq!(next_run_after("0 0 * * * *", time(1, 10, 15)), Some(time(1, 11, 0)));
+    }
+
+    #[test]
+    fn next_run_is_after_the_given_time()
+    {
+        assert_eq!(next_run_after("0 30 4 * * *", time(1, 4, 30)), Some(time(2, 4, 30)));
+    }
+
+    #[test]
+    fn finds_next_run_on_weekday()
+    {
+        // The 1st of August 2018 was a Wednesday.
+        assert_eq!(next_run_after("0 0 12 * * Mon", time(1, 9, 0)), Some(time(6, 12, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions()
+    {
+        assert_eq!(next_run_after("not a cron expression", time(1, 0, 0)), None);
+        assert_eq!(next_run_after("0 61 * * * *", time(1, 0, 0)), None);
+    }
+
+    #[test]
+    fn expression_in_the_past_never_runs()
+    {
+        assert_eq!(next_run_after("0 0 0 1 1 * 2017", time(1, 0, 0)), None);
+    }
+}
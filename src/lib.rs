@@ -7,6 +7,13 @@
 extern crate diesel;
 
 extern crate bincode;
+extern crate flate2;
+extern crate hmac;
+#[macro_use]
+extern crate lazy_static;
+extern crate reqwest;
+extern crate sha2;
+extern crate image;
 extern crate rand;
 extern crate bcrypt;
 extern crate serde;
@@ -48,6 +55,7 @@ pub mod fields;
 pub mod conv;
 pub mod errors;
 pub mod job_juggler; // used to manage jobs in the database.
+pub mod shutdown;
 
 static DATABASE_URL: &'static str = dotenv!("DATABASE_URL");
 static AWS_ACCESS: &'static str = dotenv!("AWS_ACCESS");
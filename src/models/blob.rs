@@ -0,0 +1,84 @@
+use chrono::{Local, NaiveDateTime};
+
+use dbtools;
+use schema::horus_blobs;
+
+/// Stored content shared by every resource of its kind and owner it was uploaded
+/// as, see `dbtools::blobs`. `ref_count` is kept up to date by the database as resources
+/// referencing the blob are inserted and deleted.
+#[derive(Debug, Identifiable, Queryable, QueryableByName, Insertable)]
+#[table_name = "horus_blobs"]
+#[primary_key(hash, kind, owner)]
+pub struct Blob
+{
+    pub hash: String, // hex SHA-256 of the content
+    pub kind: String, // see `BlobKind`
+    pub owner: i32,
+    pub filepath: String,
+    pub size: i64,
+    pub ref_count: i32,
+    pub created_at: NaiveDateTime,
+    pub content_type: String, // see `dbtools::mime`
+}
+
+/// What a blob was uploaded as. The same content uploaded as different kinds
+/// of resources is stored as a blob of each kind, under its own directory.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BlobKind
+{
+    Image,
+    Video,
+    File,
+}
+
+impl BlobKind
+{
+    /// The name in `Blob::kind`, and in the `blob_kind` of the resources.
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            BlobKind::Image => "image",
+            BlobKind::Video => "video",
+            BlobKind::File => "file",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name {
+            "image" => Some(BlobKind::Image),
+            "video" => Some(BlobKind::Video),
+            "file" => Some(BlobKind::File),
+            _ => None,
+        }
+    }
+
+    /// Where the blob of this kind with `hash` is stored for `owner`.
+    pub fn path(&self, owner: i32, hash: &str) -> String
+    {
+        let name = format!("{}/{}", owner, hash);
+        match *self {
+            BlobKind::Image => dbtools::get_path_image(&name),
+            BlobKind::Video => dbtools::get_path_video(&name),
+            BlobKind::File => dbtools::get_path_file(&name),
+        }
+    }
+}
+
+impl Blob
+{
+    /// A blob without references, they're counted once the resource is inserted.
+    pub fn new(hash: String, kind: BlobKind, owner: i32, size: i64, content_type: String) -> Self
+    {
+        Blob {
+            filepath: kind.path(owner, &hash),
+            hash: hash,
+            kind: kind.name().to_string(),
+            owner: owner,
+            size: size,
+            ref_count: 0,
+            created_at: Local::now().naive_utc(),
+            content_type: content_type,
+        }
+    }
+}
@@ -3,6 +3,8 @@ use diesel::prelude::*;
 use diesel::pg::PgConnection;
 
 use schema::horus_files;
+use models::traits::expirable::Expirable;
+use models::BlobKind;
 use models::traits::passwordable;
 
 #[derive(Queryable, Serialize, Identifiable, Insertable, AsChangeset)]
@@ -17,7 +19,9 @@ pub struct HFile
     pub is_expiry: bool,
     pub expiration_time: Option<NaiveDateTime>,
     pub download_counter: Option<i32>,
-    pub password: Option<String>
+    pub password: Option<String>,
+    pub blob_hash: Option<String>,
+    pub blob_kind: String, // see `BlobKind`
 }
 
 impl passwordable::Passwordable for HFile {
@@ -43,8 +47,26 @@ impl passwordable::Passwordable for HFile {
         self.filepath.clone()
     }
 
+    fn get_download_name(&self) -> Option<String>
+    {
+        Some(self.filename.clone())
+    }
+
+    fn get_blob(&self) -> Option<(String, BlobKind)>
+    {
+        self.blob_hash.clone().map(|hash| (hash, BlobKind::File))
+    }
+
     fn owner(&self) -> i32
     {
         self.owner
     }
 }
+
+impl Expirable for HFile
+{
+    fn expires_at(&self) -> Option<NaiveDateTime>
+    {
+        if self.is_expiry { self.expiration_time } else { None }
+    }
+}
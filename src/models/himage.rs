@@ -3,6 +3,8 @@ use diesel::prelude::*;
 use diesel::pg::PgConnection;
 
 use schema::horus_images;
+use models::traits::expirable::Expirable;
+use models::BlobKind;
 use models::traits::passwordable;
 
 #[derive(AsChangeset, Queryable, Serialize, Identifiable, Insertable)]
@@ -16,7 +18,10 @@ pub struct HImage
     pub date_added: NaiveDateTime,
     pub is_expiry: bool,
     pub expiration_time: Option<NaiveDateTime>,
-    pub password: Option<String>
+    pub password: Option<String>,
+    pub thumbnail_path: Option<String>,
+    pub blob_hash: Option<String>,
+    pub blob_kind: String, // see `BlobKind`
 }
 
 #[derive(Serialize)]
@@ -29,7 +34,10 @@ pub struct FixedDateHImage
     pub date_added: String,
     pub is_expiry: bool,
     pub expiration_time: Option<NaiveDateTime>,
-    pub password: Option<String>
+    pub password: Option<String>,
+    pub thumbnail_path: Option<String>,
+    pub blob_hash: Option<String>,
+    pub blob_kind: String,
 }
 
 impl HImage {
@@ -43,7 +51,10 @@ impl HImage {
             date_added: format!("{}", &self.date_added.format("%d %b %Y\nat %H:%M")),
             is_expiry: (&self).is_expiry,
             expiration_time: (&self).expiration_time.clone(),
-            password: (&self).password.clone()
+            password: (&self).password.clone(),
+            thumbnail_path: (&self).thumbnail_path.clone(),
+            blob_hash: (&self).blob_hash.clone(),
+            blob_kind: (&self).blob_kind.clone(),
         }
     }
 
@@ -51,6 +62,7 @@ impl HImage {
        title: Option<String>, 
        owner: i32,
        filepath: String,
+       blob_hash: Option<String>,
        date_added: NaiveDateTime,
        is_expiry: bool,
        expiration_time: Option<NaiveDateTime>)
@@ -64,7 +76,10 @@ impl HImage {
             date_added: date_added,
             is_expiry: is_expiry,
             expiration_time: expiration_time,
-            password: None
+            password: None,
+            thumbnail_path: None,
+            blob_hash: blob_hash,
+            blob_kind: BlobKind::Image.name().to_string(),
         }
     }
 }
@@ -93,9 +108,26 @@ impl passwordable::Passwordable for HImage {
         self.filepath.clone()
     }
 
+    fn get_s3_thumbnail_location(&self) -> Option<String>
+    {
+        self.thumbnail_path.clone()
+    }
+
+    fn get_blob(&self) -> Option<(String, BlobKind)>
+    {
+        self.blob_hash.clone().map(|hash| (hash, BlobKind::Image))
+    }
+
     fn owner(&self) -> i32
     {
         self.owner
     }
 }
 
+impl Expirable for HImage
+{
+    fn expires_at(&self) -> Option<NaiveDateTime>
+    {
+        if self.is_expiry { self.expiration_time } else { None }
+    }
+}
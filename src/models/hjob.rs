@@ -6,6 +6,7 @@ use diesel::pg::Pg;
 use diesel::row::Row;
 use diesel::sql_types::Integer;
 use diesel::deserialize::FromSqlRow;
+use serde_json::Value;
 
 use schema::horus_jobs;
 use super::User;
@@ -18,22 +19,49 @@ pub enum JobStatus
     Queued = 1,
     Failed = 2,
     Running = 3,
+    DeadLetter = 4, // failed too many times, won't be retried.
+    Cancelled = 5,
+    Interrupted = 6, // the server shut down while it ran, it will be run again.
     Complete = 10,
 }
 
+impl JobStatus
+{
+    /// The statuses of jobs that won't run (again).
+    pub fn finished() -> Vec<i32>
+    {
+        vec![
+            JobStatus::Failed as i32,
+            JobStatus::DeadLetter as i32,
+            JobStatus::Cancelled as i32,
+            JobStatus::Complete as i32,
+        ]
+    }
+
+    /// The statuses of jobs that won't run again and didn't complete.
+    pub fn failed() -> Vec<i32>
+    {
+        vec![
+            JobStatus::Failed as i32,
+            JobStatus::DeadLetter as i32,
+            JobStatus::Cancelled as i32,
+        ]
+    }
+}
+
 pub enum JobPriority
 {
-    DoNotProcess = -1, // the job isn't done being inserted.
+    DoNotProcess = -1, // never claimed by a juggler.
     Normal = 0,        // normal jobs made by users
     Elevated = 1,      // jobs that are time dependent but not crucial
     High = 2,          // very important jobs
     System = 3,  // system level jobs like clearing caches and things that need to be done next.
-    GodMode = 4, // Forcible override - shouldn't be used lightly.
-                 // TODO: Directly insert godmode jobs into job queue instead of database.
+    GodMode = 4, // Forcible override - shouldn't be used lightly. Claimed ahead of
+                 // everything else, even jobs already in a juggler's queue.
 }
 
-#[derive(PartialEq, Debug, Serialize, Deserialize, Identifiable, Queryable, Associations,
-         Insertable, AsChangeset)]
+#[derive(PartialEq, Debug, Serialize, Deserialize, Identifiable, Queryable, QueryableByName,
+         Associations, Insertable, AsChangeset)]
 #[table_name = "horus_jobs"]
 #[belongs_to(User, foreign_key = "owner")]
 pub struct HJob
@@ -46,6 +74,18 @@ pub struct HJob
     pub time_queued: NaiveDateTime,
     pub priority: i32,
     pub logs: Option<String>,
+    pub claimed_by: Option<String>, // the juggler holding the lease on this job
+    pub heartbeat: Option<NaiveDateTime>,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_after: Option<NaiveDateTime>, // not processed before this time (UTC)
+    pub cancel_requested: bool, // set while running, the job stops when it next checks
+    pub deadline: Option<NaiveDateTime>, // when the current attempt runs out of time
+    pub timed_out: bool,
+    pub progress: Option<i16>, // percent, reported by the job while it runs
+    pub stage: Option<String>,
+    pub job_result: Option<Value>, // set by jobs that complete with a result
+    pub depends_on: Vec<i32>, // jobs that have to complete before this one is processed
 }
 
 #[derive(Insertable)]
@@ -56,6 +96,8 @@ pub struct NewJob
     job_name: String,
     pub job_data: Option<Vec<u8>>,
     pub priority: i32,
+    pub run_after: Option<NaiveDateTime>,
+    pub depends_on: Vec<i32>,
 }
 
 impl NewJob
@@ -67,19 +109,26 @@ impl NewJob
             job_name: name,
             job_data: data,
             priority: priority as i32,
+            run_after: None,
+            depends_on: Vec::new(),
         }
     }
 
-    /// Return an instance of self without the data (used for quick insert
-    /// without the data being present). Also sets the priority to `JobPriority::DoNotProcess`.
-    pub fn without_data(&self) -> NewJob
+    /// Delays the job so it isn't processed before the given (UTC) time.
+    pub fn with_run_after(mut self, time: NaiveDateTime) -> Self
     {
-        NewJob {
-            owner: self.owner,
-            job_name: self.job_name.clone(),
-            job_data: None,
-            priority: JobPriority::DoNotProcess as i32,
-        }
+        self.run_after = Some(time);
+        self
+    }
+
+    /// Keeps the job waiting until all of the given jobs have completed.
+    /// If any of them doesn't complete, the job fails along with it.
+    pub fn with_dependencies(mut self, mut job_ids: Vec<i32>) -> Self
+    {
+        job_ids.sort();
+        job_ids.dedup();
+        self.depends_on = job_ids;
+        self
     }
 }
 
@@ -91,6 +140,10 @@ impl FromSqlRow<Integer, Pg> for JobStatus
             0 => Ok(JobStatus::Waiting),
             1 => Ok(JobStatus::Queued),
             2 => Ok(JobStatus::Failed),
+            3 => Ok(JobStatus::Running),
+            4 => Ok(JobStatus::DeadLetter),
+            5 => Ok(JobStatus::Cancelled),
+            6 => Ok(JobStatus::Interrupted),
             10 => Ok(JobStatus::Complete),
             v => Err(format!("Received bad value for JobStatus: {}", v).into()),
         }
@@ -1,6 +1,7 @@
 use chrono::NaiveDateTime;
 
 use schema::horus_pastes;
+use models::traits::expirable::Expirable;
 
 #[derive(AsChangeset, Identifiable, Serialize, Insertable, Queryable, Deserialize)]
 #[table_name = "horus_pastes"]
@@ -14,3 +15,11 @@ pub struct HPaste
     pub is_expiry: bool,
     pub expiration_time: Option<NaiveDateTime>,
 }
+
+impl Expirable for HPaste
+{
+    fn expires_at(&self) -> Option<NaiveDateTime>
+    {
+        if self.is_expiry { self.expiration_time } else { None }
+    }
+}
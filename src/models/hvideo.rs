@@ -3,6 +3,8 @@ use diesel::prelude::*;
 use diesel::pg::PgConnection;
 
 use schema::horus_videos;
+use models::traits::expirable::Expirable;
+use models::BlobKind;
 use models::traits::passwordable;
 
 #[derive(AsChangeset, Queryable, Serialize, Identifiable, Insertable)]
@@ -16,7 +18,9 @@ pub struct HVideo
     pub date_added: NaiveDateTime,
     pub is_expiry: bool,
     pub expiration_time: Option<NaiveDateTime>,
-    pub password: Option<String>
+    pub password: Option<String>,
+    pub blob_hash: Option<String>,
+    pub blob_kind: String, // see `BlobKind`
 }
 
 impl passwordable::Passwordable for HVideo {
@@ -42,9 +46,21 @@ impl passwordable::Passwordable for HVideo {
         self.filepath.clone()
     }
 
+    fn get_blob(&self) -> Option<(String, BlobKind)>
+    {
+        self.blob_hash.clone().map(|hash| (hash, BlobKind::Video))
+    }
+
     fn owner(&self) -> i32
     {
         self.owner
     }
 }
 
+impl Expirable for HVideo
+{
+    fn expires_at(&self) -> Option<NaiveDateTime>
+    {
+        if self.is_expiry { self.expiration_time } else { None }
+    }
+}
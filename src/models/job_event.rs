@@ -0,0 +1,57 @@
+use chrono::NaiveDateTime;
+
+use schema::horus_job_events;
+use super::HJob;
+
+/// Something that happened to a job while it was queued or running.
+#[derive(Debug, Serialize, Identifiable, Queryable, Associations)]
+#[table_name = "horus_job_events"]
+#[belongs_to(HJob, foreign_key = "job_id")]
+pub struct JobEvent
+{
+    pub id: i64,
+    pub job_id: i32,
+    pub event_type: String, // see `JobEventType`
+    pub event_data: String,
+    pub created_at: NaiveDateTime,
+}
+
+pub enum JobEventType
+{
+    Status,   // the new status of the job
+    Progress, // `{"percent": <0-100>, "stage": <text>}`
+    Log,      // a line of the job logs
+}
+
+impl JobEventType
+{
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            JobEventType::Status => "status",
+            JobEventType::Progress => "progress",
+            JobEventType::Log => "log",
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "horus_job_events"]
+pub struct NewJobEvent
+{
+    job_id: i32,
+    event_type: String,
+    event_data: String,
+}
+
+impl NewJobEvent
+{
+    pub fn new(job_id: i32, event_type: JobEventType, event_data: String) -> Self
+    {
+        NewJobEvent {
+            job_id: job_id,
+            event_type: event_type.name().to_string(),
+            event_data: event_data,
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use chrono::NaiveDateTime;
+
+use schema::horus_job_schedules;
+use super::User;
+
+/// A job that is enqueued every time its cron expression comes due.
+#[derive(Debug, Serialize, Identifiable, Queryable, QueryableByName, Associations,
+         AsChangeset)]
+#[table_name = "horus_job_schedules"]
+#[belongs_to(User, foreign_key = "owner")]
+pub struct JobSchedule
+{
+    pub id: i32,
+    pub owner: i32,
+    pub job_name: String,
+    pub job_data: Option<Vec<u8>>,
+    pub priority: i32,
+    pub cron_expression: String, // evaluated in UTC
+    pub next_run: NaiveDateTime,
+    pub last_run: Option<NaiveDateTime>,
+    pub enabled: bool,
+}
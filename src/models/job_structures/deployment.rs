@@ -1,27 +1,65 @@
 use std::boxed::Box;
 
 use diesel::{self, prelude::*};
-use diesel::pg::PgConnection;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 
-use job_juggler::{ExecutableJob, JobResult, LoggableJob};
+use job_juggler::{ExecutableJob, JobContext, JobResult, LoggableJob};
+use super::{decode, JobPayload};
+
+/// Where the package of a deployment is.
+#[derive(Serialize, Deserialize)]
+pub enum Package
+{
+    /// Already stored at the path, packages are streamed there as they're uploaded.
+    /// Usually a staging key the job moves the package from, see `Deployment::new`.
+    Stored(String),
+    /// Still to be stored, only deployments queued before packages were streamed.
+    Inline(Vec<u8>),
+}
 
 #[derive(Serialize, Deserialize, LoggableJob)]
 #[LogName = "log_data"]
 pub struct Deployment
 {
-    pub deployment_package: Vec<u8>,
+    pub package: Package,
     pub deployment_key_hash: String,
     pub version_string: String,
     pub platform_string: String,
     pub log_data: String,
 }
 
+/// The result of a finished deployment.
+#[derive(Serialize)]
+pub struct DeploymentResult
+{
+    pub version_id: i32,
+    pub version_string: String,
+    pub platform: String,
+}
+
+#[derive(Deserialize)]
+struct DeploymentV1
+{
+    deployment_package: Vec<u8>,
+    deployment_key_hash: String,
+    version_string: String,
+    platform_string: String,
+    log_data: String,
+}
+
 impl Deployment
 {
-    pub fn new(package: Vec<u8>, dkey_hash: String, s_version: String, s_platform: String) -> Self
+    /// `package_path` is where the package was staged, see
+    /// `dbtools::get_path_deployment_staging`.
+    pub fn new(
+        package_path: String,
+        dkey_hash: String,
+        s_version: String,
+        s_platform: String,
+    ) -> Self
     {
         Deployment {
-            deployment_package: package,
+            package: Package::Stored(package_path),
             deployment_key_hash: dkey_hash,
             version_string: s_version,
             platform_string: s_platform,
@@ -30,36 +68,96 @@ impl Deployment
     }
 }
 
+impl JobPayload for Deployment
+{
+    const TYPE_NAME: &'static str = "Deployment";
+    const VERSION: u16 = 2;
+
+    fn migrate(version: u16, data: &[u8]) -> Result<Self, String>
+    {
+        match version {
+            1 => {
+                let old: DeploymentV1 = decode(data)?;
+                Ok(Deployment {
+                    package: Package::Inline(old.deployment_package),
+                    deployment_key_hash: old.deployment_key_hash,
+                    version_string: old.version_string,
+                    platform_string: old.platform_string,
+                    log_data: old.log_data,
+                })
+            }
+            _ => Err(format!("Can't migrate {} from version {}.", Self::TYPE_NAME, version)),
+        }
+    }
+}
+
 impl ExecutableJob for Deployment
 {
-    fn execute(mut self, conn: &PgConnection) -> (Box<Self>, JobResult)
+    // Packages of older deployments can be large and are uploaded in one go.
+    const MAX_RUNTIME: u64 = 30 * 60;
+
+    fn execute(mut self, ctx: &JobContext) -> (Box<Self>, JobResult)
     {
         use dbtools;
+        use dbtools::mime;
+        use dbtools::storage::{self, Visibility};
         use models::{HorusVersion, NewHorusVersion};
 
+        let conn = ctx.connection();
         let mut tl: String;
 
-        // Get the filename and path details
-        let s3_fname = &self.platform_string.clone();
-        let s3_path = dbtools::get_path_deployment(&self.version_string, s3_fname);
+        // Packages are stored under a key of their own until the version is recorded.
+        let live_path = dbtools::get_path_deployment(&self.version_string, &self.platform_string);
+        let s3_path = match self.package {
+            Package::Stored(ref path) => path.clone(),
+            Package::Inline(_) => dbtools::get_path_deployment_staging(&ctx.job_id().to_string()),
+        };
 
-        // Send it to s3
-        tl = format!(
-            "Sending package version {} for {} to S3",
-            &self.version_string, &self.platform_string
-        );
-        &self.log(&tl);
+        if ctx.is_cancelled() {
+            ctx.log(&mut self, "Cancellation requested, stopping.");
+            return (Box::new(self), JobResult::Cancelled);
+        }
 
-        let s3_result =
-            dbtools::s3::private_resource_to_s3_named(&s3_fname, &s3_path, &self.deployment_package);
+        let stored = match self.package {
+            Package::Stored(_) => Ok(()),
+            Package::Inline(ref package) => {
+                // Send it to storage
+                ctx.progress(0, "Uploading package");
+                storage::backend().put(
+                    &s3_path,
+                    package,
+                    mime::sniff(package).unwrap_or(mime::UNKNOWN),
+                    Visibility::Private,
+                    Some(&self.platform_string),
+                )
+            }
+        };
 
-        if s3_result.is_err() {
-            &self.log("Couldn't send data to S3...aborting deployment.");
+        if let Err(e) = stored {
+            ctx.log(&mut self, &e);
+            ctx.log(&mut self, "Couldn't store package...aborting deployment.");
             return (Box::new(self), JobResult::Failed);
         }
+        self.package = Package::Stored(s3_path.clone());
 
-        &self.log("Done...result was ok.");
-        &self.log("Inserting to database...");
+        tl = format!(
+            "Package version {} for {} is stored at {}",
+            &self.version_string, &self.platform_string, &s3_path
+        );
+        ctx.log(&mut self, &tl);
+
+        // The last chance to stop, the package is in use once it's moved into place.
+        if ctx.is_cancelled() {
+            ctx.log(&mut self, "Cancellation requested, removing the package.");
+            if let Err(e) = storage::backend().delete(&s3_path) {
+                tl = format!("Couldn't remove the package: {}", e);
+                ctx.log(&mut self, &tl);
+            }
+            return (Box::new(self), JobResult::Cancelled);
+        }
+
+        ctx.log(&mut self, "Inserting to database...");
+        ctx.progress(90, "Recording version");
 
         // fixed bug with older entried
         if self.platform_string == "windows" {
@@ -68,28 +166,73 @@ impl ExecutableJob for Deployment
 
         let hversion = NewHorusVersion::new(
             self.deployment_key_hash.clone(),
-            s3_path,
+            live_path.clone(),
             self.version_string.clone(),
             self.platform_string.clone(),
         );
 
-        let db_result = diesel::insert_into(::schema::horus_versions::table)
-            .values(&hversion)
-            .get_result::<HorusVersion>(conn);
-
-        if db_result.is_err() {
-            tl = format!("{}", db_result.err().unwrap());
-            &self.log(&tl);
-            &self.log("Couldn't insert into database...aborting deployment.");
-            (Box::new(self), JobResult::Failed)
-        } else {
-            &self.log("Successfully inserted into database.");
-            tl = format!(
-                "Deployment of version {} for platform {} complete.",
-                self.version_string, self.platform_string
-            );
-            &self.log(&tl);
-            (Box::new(self), JobResult::Complete)
+        // The package is moved into place along with recording the version, which fails
+        // if the version was deployed before, so the package of a version in use is
+        // never replaced. Deployments queued before packages were staged are in place.
+        let mut moved = Ok(());
+        let db_result = conn.transaction::<_, DieselError, _>(|| {
+            let version = diesel::insert_into(::schema::horus_versions::table)
+                .values(&hversion)
+                .get_result::<HorusVersion>(conn)?;
+
+            if s3_path != live_path {
+                moved = storage::backend().rename(&s3_path, &live_path);
+            }
+
+            match moved {
+                Ok(_) => Ok(version),
+                Err(_) => Err(DieselError::RollbackTransaction),
+            }
+        });
+
+        match db_result {
+            Err(DieselError::RollbackTransaction) => {
+                tl = format!("Couldn't move the package to {}: {}", live_path, moved.unwrap_err());
+                ctx.log(&mut self, &tl);
+                (Box::new(self), JobResult::Failed)
+            }
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                tl = format!(
+                    "Version {} for {} was already deployed, removing the package.",
+                    self.version_string, self.platform_string
+                );
+                ctx.log(&mut self, &tl);
+                let reason = tl.clone();
+                if let Err(e) = storage::backend().delete(&s3_path) {
+                    tl = format!("Couldn't remove the package: {}", e);
+                    ctx.log(&mut self, &tl);
+                }
+                (Box::new(self), JobResult::FailedWithReason(reason))
+            }
+            Err(e) => {
+                tl = format!("{}", e);
+                ctx.log(&mut self, &tl);
+                ctx.log(&mut self, "Couldn't insert into database...aborting deployment.");
+                (Box::new(self), JobResult::Failed)
+            }
+            Ok(version) => {
+                tl = format!("Package moved to {}", live_path);
+                ctx.log(&mut self, &tl);
+                ctx.log(&mut self, "Successfully inserted into database.");
+                tl = format!(
+                    "Deployment of version {} for platform {} complete.",
+                    self.version_string, self.platform_string
+                );
+                ctx.log(&mut self, &tl);
+                ctx.progress(100, "Complete");
+
+                let result = DeploymentResult {
+                    version_id: version.id(),
+                    version_string: self.version_string.clone(),
+                    platform: self.platform_string.clone(),
+                };
+                (Box::new(self), JobResult::complete_with(&result))
+            }
         }
     }
 }
@@ -0,0 +1,326 @@
+use std::boxed::Box;
+use std::collections::BTreeMap;
+
+use chrono::{Duration, Local, NaiveDateTime};
+use diesel::{self, prelude::*};
+use diesel::pg::PgConnection;
+
+use dbtools::{blobs, storage};
+use job_juggler::{ExecutableJob, JobContext, JobResult, LoggableJob};
+use models::{HFile, HImage, HPaste, HVideo, JobStatus};
+use super::JobPayload;
+
+/// Seconds the events of a finished job are kept for, clients following the job
+/// stop once they got the last one.
+const EVENT_RETENTION: i64 = 24 * 60 * 60;
+
+/// Deletes expired images, videos, files and pastes along with their stored
+/// objects, then the blobs nothing references anymore and the old events of
+/// finished jobs. Deleting the rows keeps the resource counts of the owners in check.
+#[derive(Serialize, Deserialize, LoggableJob)]
+#[LogName = "log_data"]
+pub struct ReapExpired
+{
+    pub batch_size: i64,
+    pub log_data: String,
+}
+
+/// The number of resources of one kind that were and weren't reaped.
+#[derive(Default, Serialize)]
+pub struct ReapTotals
+{
+    pub reaped: usize,
+    pub failed: usize,
+}
+
+type Reaper = fn(&mut ReapExpired, NaiveDateTime, &PgConnection) -> QueryResult<ReapTotals>;
+
+impl Default for ReapExpired
+{
+    fn default() -> Self
+    {
+        ReapExpired {
+            batch_size: 100,
+            log_data: String::new(),
+        }
+    }
+}
+
+impl JobPayload for ReapExpired
+{
+    const TYPE_NAME: &'static str = "ReapExpired";
+}
+
+impl ExecutableJob for ReapExpired
+{
+    // Every reaped resource is a round trip to storage.
+    const MAX_RUNTIME: u64 = 30 * 60;
+
+    fn execute(mut self, ctx: &JobContext) -> (Box<Self>, JobResult)
+    {
+        let conn = ctx.connection();
+        // Expiration times are stored the same way, see `conv::get_dt_from_duration`.
+        let now = Local::now().naive_utc();
+        let mut tl = format!("Reaping resources that expired before {}", now);
+        ctx.log(&mut self, &tl);
+
+        let reapers: Vec<(&str, Reaper)> = vec![
+            ("images", Self::reap::<HImage>),
+            ("videos", Self::reap::<HVideo>),
+            ("files", Self::reap::<HFile>),
+            ("pastes", Self::reap::<HPaste>),
+            ("blobs", Self::reap_blobs),
+            ("job events", Self::reap_job_events),
+        ];
+
+        let mut error = None;
+        let mut results = BTreeMap::new();
+        let kind_count = reapers.len();
+        for (n, (kind, reap)) in reapers.into_iter().enumerate() {
+            if ctx.is_cancelled() {
+                ctx.log(&mut self, "Cancellation requested, stopping.");
+                return (Box::new(self), JobResult::Cancelled);
+            }
+
+            tl = format!("Reaping {}", kind);
+            ctx.progress((n * 100 / kind_count) as i16, &tl);
+
+            match reap(&mut self, now, conn) {
+                Ok(totals) => {
+                    tl = format!(
+                        "Reaped {} expired {}, {} couldn't be reaped.",
+                        totals.reaped, kind, totals.failed
+                    );
+                    ctx.log(&mut self, &tl);
+                    results.insert(kind, totals);
+                }
+                Err(e) => {
+                    tl = format!("Couldn't query expired {}: {}", kind, e);
+                    ctx.log(&mut self, &tl);
+                    error = Some(tl);
+                }
+            }
+        }
+
+        match error {
+            Some(reason) => (Box::new(self), JobResult::FailedWithReason(reason)),
+            None => (Box::new(self), JobResult::complete_with(&results)),
+        }
+    }
+}
+
+impl ReapExpired
+{
+    /// Reaps the expired resources of one kind. Batches are walked in id order
+    /// so resources that can't be reaped don't come back in the next batch.
+    fn reap<T: Expiring>(
+        &mut self,
+        now: NaiveDateTime,
+        conn: &PgConnection,
+    ) -> QueryResult<ReapTotals>
+    {
+        let mut totals = ReapTotals::default();
+        let mut last_id = String::new();
+
+        loop {
+            let batch = T::expired(now, &last_id, self.batch_size, conn)?;
+
+            for resource in batch.iter() {
+                let reaped = self.delete_objects(&resource.objects())
+                    && self.deleted(resource.delete(conn));
+
+                if reaped {
+                    totals.reaped += 1;
+                } else {
+                    totals.failed += 1;
+                }
+            }
+
+            match batch.last() {
+                Some(resource) if batch.len() as i64 == self.batch_size => {
+                    last_id = resource.id().to_string()
+                }
+                _ => return Ok(totals),
+            }
+        }
+    }
+
+    /// Releases unreferenced blobs, including those of the resources reaped before.
+    fn reap_blobs(&mut self, _now: NaiveDateTime, conn: &PgConnection) -> QueryResult<ReapTotals>
+    {
+        let mut totals = ReapTotals::default();
+
+        for (hash, kind, owner) in blobs::unreferenced(conn)? {
+            match blobs::release(&hash, kind, owner, conn) {
+                Ok(true) => totals.reaped += 1,
+                // Referenced again in the meantime.
+                Ok(false) => {}
+                Err(e) => {
+                    let tl = format!("Couldn't release {} blob {}: {}", kind.name(), hash, e);
+                    self.log(&tl);
+                    totals.failed += 1;
+                }
+            }
+        }
+
+        Ok(totals)
+    }
+
+    /// Deletes the events of jobs that have finished, once they're older than
+    /// `EVENT_RETENTION`. Jobs that haven't finished keep all of theirs.
+    fn reap_job_events(
+        &mut self,
+        now: NaiveDateTime,
+        conn: &PgConnection,
+    ) -> QueryResult<ReapTotals>
+    {
+        use schema::{horus_job_events, horus_jobs};
+
+        let finished = horus_jobs::table
+            .filter(horus_jobs::job_status.eq_any(JobStatus::finished()))
+            .select(horus_jobs::id);
+        let cutoff = now - Duration::seconds(EVENT_RETENTION);
+
+        let reaped = diesel::delete(
+            horus_job_events::table
+                .filter(horus_job_events::created_at.lt(cutoff))
+                .filter(horus_job_events::job_id.eq_any(finished)),
+        ).execute(conn)?;
+
+        Ok(ReapTotals {
+            reaped: reaped,
+            failed: 0,
+        })
+    }
+
+    /// Deletes the stored objects, returning false if any of them couldn't be deleted.
+    fn delete_objects(&mut self, paths: &Vec<String>) -> bool
+    {
+        for path in paths {
+            if let Err(e) = storage::backend().delete(path) {
+                let tl = format!("Couldn't delete {} from storage: {}", path, e);
+                self.log(&tl);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Logs the error of a row deletion, returning true if it went through.
+    fn deleted(&mut self, result: QueryResult<usize>) -> bool
+    {
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                let tl = format!("Couldn't delete row: {}", e);
+                self.log(&tl);
+                false
+            }
+        }
+    }
+}
+
+/// A kind of resource that expires, see `ReapExpired::reap`.
+trait Expiring: Sized
+{
+    /// Up to `limit` expired resources with an id after `after`, in id order.
+    fn expired(
+        now: NaiveDateTime,
+        after: &str,
+        limit: i64,
+        conn: &PgConnection,
+    ) -> QueryResult<Vec<Self>>;
+
+    fn id(&self) -> &str;
+
+    /// The stored objects that are deleted along with the resource.
+    fn objects(&self) -> Vec<String>;
+
+    fn delete(&self, conn: &PgConnection) -> QueryResult<usize>;
+}
+
+/// Implements the parts of `Expiring` that are the same for every table.
+macro_rules! expiring_rows {
+    ($table:ident) => {
+        fn expired(
+            now: NaiveDateTime,
+            after: &str,
+            limit: i64,
+            conn: &PgConnection,
+        ) -> QueryResult<Vec<Self>>
+        {
+            use schema::$table::dsl::*;
+
+            $table
+                .filter(is_expiry.eq(true))
+                .filter(expiration_time.lt(now))
+                .filter(id.gt(after))
+                .order(id.asc())
+                .limit(limit)
+                .get_results::<Self>(conn)
+        }
+
+        fn id(&self) -> &str
+        {
+            &self.id
+        }
+
+        fn delete(&self, conn: &PgConnection) -> QueryResult<usize>
+        {
+            diesel::delete(self).execute(conn)
+        }
+    };
+}
+
+impl Expiring for HImage
+{
+    expiring_rows!(horus_images);
+
+    fn objects(&self) -> Vec<String>
+    {
+        let mut paths = owned_object(&self.filepath, &self.blob_hash);
+        paths.extend(self.thumbnail_path.clone());
+        paths
+    }
+}
+
+impl Expiring for HVideo
+{
+    expiring_rows!(horus_videos);
+
+    fn objects(&self) -> Vec<String>
+    {
+        owned_object(&self.filepath, &self.blob_hash)
+    }
+}
+
+impl Expiring for HFile
+{
+    expiring_rows!(horus_files);
+
+    fn objects(&self) -> Vec<String>
+    {
+        owned_object(&self.filepath, &self.blob_hash)
+    }
+}
+
+impl Expiring for HPaste
+{
+    expiring_rows!(horus_pastes);
+
+    // Pastes live in the database only.
+    fn objects(&self) -> Vec<String>
+    {
+        vec![]
+    }
+}
+
+/// The object a resource owns. Blobs are shared, they're released by `reap_blobs`
+/// once nothing references them.
+fn owned_object(filepath: &str, blob_hash: &Option<String>) -> Vec<String>
+{
+    match *blob_hash {
+        Some(_) => vec![],
+        None => vec![filepath.to_string()],
+    }
+}
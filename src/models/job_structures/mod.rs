@@ -1,31 +1,26 @@
-use bincode::{self, Config};
-use serde::{Deserialize, Serialize};
+use job_juggler::JobRegistry;
 
 mod deployment;
+mod expiry;
+mod payload;
+mod reconcile;
+mod thumbnail;
+mod uploads;
 
-pub use self::deployment::Deployment;
-pub use self::thumbnail::CreateImageThumbnail;
+pub use self::deployment::{Deployment, DeploymentResult, Package};
+pub use self::expiry::{ReapExpired, ReapTotals};
+pub use self::payload::{binarize, debinarize, decode, JobPayload};
+pub use self::reconcile::{OrphanAction, Reconcile, ReconcileResult};
+pub use self::thumbnail::{CreateImageThumbnail, ThumbnailResult};
+pub use self::uploads::ReapStaleUploads;
 
-/// Turn a struct into a serialized vector of bytes to be passed
-/// as job_data to the database.
-pub fn binarize<T: Serialize>(to_binarize: &T) -> Vec<u8>
+/// Registers every job type with the name prefix of the jobs it executes.
+/// New job types only need to be added here to be picked up by the juggler.
+pub fn register_jobs(registry: &mut JobRegistry)
 {
-    let mut conf: Config = bincode::config();
-    conf.no_limit();
-    let result: bincode::Result<Vec<u8>> = conf.serialize(to_binarize);
-    result.unwrap()
-}
-
-/// Turn a binary vector into a struct of type T. May fail.
-pub fn debinarize<'a, T: Deserialize<'a>>(to_debinarize: &'a [u8]) -> Option<T>
-{
-    let mut conf: Config = bincode::config();
-    conf.no_limit();
-    let deserialized: bincode::Result<T> = conf.deserialize(to_debinarize);
-    if deserialized.is_err() {
-        println!("Deserialization error: {}", &deserialized.err().unwrap());
-        None
-    } else {
-        Some(deserialized.unwrap())
-    }
+    registry.register::<Deployment>("deployment:deploy");
+    registry.register::<CreateImageThumbnail>("thumbnail:image");
+    registry.register_default::<ReapExpired>("maintenance:reap_expired");
+    registry.register_default::<ReapStaleUploads>("maintenance:reap_uploads");
+    registry.register_default::<Reconcile>("maintenance:reconcile");
 }
@@ -0,0 +1,287 @@
+use std::borrow::Cow;
+use std::io::{Cursor, Read, Write};
+
+use bincode::{self, Config};
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Starts the data of every job written with an envelope. Data written before
+/// envelopes existed starts with a bincode length instead, which would have to
+/// be absurdly large to look like this.
+const ENVELOPE_MAGIC: &'static [u8] = b"HORUSJOB";
+/// Payloads larger than this many bytes are compressed.
+const COMPRESSION_THRESHOLD: usize = 4096;
+
+/// The data of a job as stored in `horus_jobs.job_data`.
+pub trait JobPayload: Serialize + DeserializeOwned
+{
+    /// Identifies the type of the payload, so data of one job type is never
+    /// decoded as another.
+    const TYPE_NAME: &'static str;
+
+    /// Has to be bumped whenever fields are added, removed or renamed, with a
+    /// `migrate` for the previous versions so pending jobs can still be decoded.
+    const VERSION: u16 = 1;
+
+    /// Decodes the (decompressed) payload of an older version of the type. Old
+    /// versions are best decoded with a copy of the old struct using `decode`.
+    fn migrate(version: u16, _data: &[u8]) -> Result<Self, String>
+    {
+        Err(format!(
+            "Can't migrate {} from version {}.",
+            Self::TYPE_NAME,
+            version
+        ))
+    }
+}
+
+/// Describes the payload that follows it.
+#[derive(Serialize, Deserialize)]
+struct EnvelopeHeader
+{
+    type_name: String,
+    version: u16,
+    compressed: bool,
+}
+
+/// Turn a struct into a serialized vector of bytes to be passed
+/// as job_data to the database, wrapped in an envelope.
+pub fn binarize<T: JobPayload>(to_binarize: &T) -> Vec<u8>
+{
+    let mut payload = encode(to_binarize);
+    let compressed = payload.len() > COMPRESSION_THRESHOLD;
+
+    if compressed {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&payload).unwrap();
+        payload = encoder.finish().unwrap();
+    }
+
+    let header = EnvelopeHeader {
+        type_name: T::TYPE_NAME.to_string(),
+        version: T::VERSION,
+        compressed: compressed,
+    };
+
+    let mut data = ENVELOPE_MAGIC.to_vec();
+    data.append(&mut encode(&header));
+    data.append(&mut payload);
+    data
+}
+
+/// Turn job data into a struct of type T, migrating it if it was written by an
+/// older version of the type. Returns the reason if it can't be decoded.
+pub fn debinarize<T: JobPayload>(to_debinarize: &[u8]) -> Result<T, String>
+{
+    if !to_debinarize.starts_with(ENVELOPE_MAGIC) {
+        // Written before envelopes, every type had its first layout back then.
+        return decode_version::<T>(1, to_debinarize);
+    }
+
+    let mut cursor = Cursor::new(&to_debinarize[ENVELOPE_MAGIC.len()..]);
+    let header: EnvelopeHeader = config(to_debinarize.len())
+        .deserialize_from(&mut cursor)
+        .map_err(|e| format!("Couldn't decode the job envelope: {}", e))?;
+
+    if header.type_name != T::TYPE_NAME {
+        return Err(format!(
+            "The job data holds a {}, not a {}.",
+            header.type_name,
+            T::TYPE_NAME
+        ));
+    }
+
+    let position = cursor.position() as usize;
+    let mut payload = Cow::Borrowed(&cursor.into_inner()[position..]);
+
+    if header.compressed {
+        let mut decompressed = Vec::new();
+        DeflateDecoder::new(&payload[..])
+            .read_to_end(&mut decompressed)
+            .map_err(|e| format!("Couldn't decompress the job data: {}", e))?;
+        payload = Cow::Owned(decompressed);
+    }
+
+    decode_version::<T>(header.version, &payload)
+}
+
+/// Decodes a bare payload without an envelope, for use in `JobPayload::migrate`.
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, String>
+{
+    config(data.len())
+        .deserialize(data)
+        .map_err(|e| format!("Deserialization error: {}", e))
+}
+
+fn decode_version<T: JobPayload>(version: u16, data: &[u8]) -> Result<T, String>
+{
+    if version == T::VERSION {
+        decode(data)
+    } else if version < T::VERSION {
+        println!(
+            "Migrating {} from version {} to {}.",
+            T::TYPE_NAME,
+            version,
+            T::VERSION
+        );
+        T::migrate(version, data)
+    } else {
+        Err(format!(
+            "The job data holds version {} of {}, this server only knows up to {}.",
+            version,
+            T::TYPE_NAME,
+            T::VERSION
+        ))
+    }
+}
+
+fn encode<T: Serialize>(to_encode: &T) -> Vec<u8>
+{
+    let mut conf: Config = bincode::config();
+    conf.no_limit();
+    conf.serialize(to_encode).unwrap()
+}
+
+/// Nothing decoded from `len` bytes can be larger than them, so corrupted
+/// lengths are caught before anything is allocated for them.
+fn config(len: usize) -> Config
+{
+    let mut conf: Config = bincode::config();
+    conf.limit(len as u64);
+    conf
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct NoteV1
+    {
+        text: String,
+    }
+
+    impl JobPayload for NoteV1
+    {
+        const TYPE_NAME: &'static str = "Note";
+    }
+
+    /// `NoteV1` after a field was added to it.
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Note
+    {
+        text: String,
+        pinned: bool,
+    }
+
+    impl JobPayload for Note
+    {
+        const TYPE_NAME: &'static str = "Note";
+        const VERSION: u16 = 2;
+
+        fn migrate(version: u16, data: &[u8]) -> Result<Self, String>
+        {
+            match version {
+                1 => decode::<NoteV1>(data).map(|old| Note {
+                    text: old.text,
+                    pinned: false,
+                }),
+                _ => Err(format!("Can't migrate Note from version {}.", version)),
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Other
+    {
+        text: String,
+    }
+
+    impl JobPayload for Other
+    {
+        const TYPE_NAME: &'static str = "Other";
+    }
+
+    fn note(text: &str) -> Note
+    {
+        Note {
+            text: text.to_string(),
+            pinned: true,
+        }
+    }
+
+    #[test]
+    fn small_payloads_are_stored_as_is()
+    {
+        let data = binarize(&note("short"));
+
+        assert!(data.starts_with(ENVELOPE_MAGIC));
+        assert!(data.ends_with(&encode(&note("short"))));
+        assert_eq!(debinarize::<Note>(&data), Ok(note("short")));
+    }
+
+    #[test]
+    fn large_payloads_are_compressed()
+    {
+        let text = "x".repeat(COMPRESSION_THRESHOLD * 2);
+        let data = binarize(&note(&text));
+
+        assert!(data.len() < COMPRESSION_THRESHOLD);
+        assert_eq!(debinarize::<Note>(&data), Ok(note(&text)));
+    }
+
+    #[test]
+    fn payloads_at_the_threshold_are_not_compressed()
+    {
+        // The length of the text and the flag take 9 bytes of the payload.
+        let text = "x".repeat(COMPRESSION_THRESHOLD - 9);
+        assert_eq!(encode(&note(&text)).len(), COMPRESSION_THRESHOLD);
+
+        let data = binarize(&note(&text));
+        assert!(data.ends_with(&encode(&note(&text))));
+    }
+
+    #[test]
+    fn migrates_older_versions()
+    {
+        let old = NoteV1 {
+            text: "old".to_string(),
+        };
+        let migrated = debinarize::<Note>(&binarize(&old)).unwrap();
+
+        assert_eq!(migrated.text, "old");
+        assert!(!migrated.pinned);
+    }
+
+    #[test]
+    fn migrates_data_without_envelope()
+    {
+        let old = NoteV1 {
+            text: "bare".to_string(),
+        };
+        let migrated = debinarize::<Note>(&encode(&old)).unwrap();
+
+        assert_eq!(migrated.text, "bare");
+        assert!(!migrated.pinned);
+    }
+
+    #[test]
+    fn rejects_newer_versions()
+    {
+        assert!(debinarize::<NoteV1>(&binarize(&note("new"))).is_err());
+    }
+
+    #[test]
+    fn rejects_other_types()
+    {
+        let other = Other {
+            text: "other".to_string(),
+        };
+
+        assert!(debinarize::<Note>(&binarize(&other)).is_err());
+    }
+}
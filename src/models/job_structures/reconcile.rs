@@ -0,0 +1,422 @@
+use std::boxed::Box;
+use std::collections::HashSet;
+use std::fmt;
+
+use chrono::{Duration, Utc};
+use diesel::{self, prelude::*};
+use diesel::pg::PgConnection;
+
+use dbtools::storage::{self, StoredObject};
+use job_juggler::{ExecutableJob, JobContext, JobResult, LoggableJob};
+use models::JobStatus;
+use super::{debinarize, Deployment, JobPayload, Package};
+
+/// Every object path starts with this, see `dbtools::get_path_image` and friends.
+const LIVE_DIR: &'static str = "live/";
+/// Quarantined objects are moved here, keeping the rest of their path.
+const QUARANTINE_DIR: &'static str = "live/quarantine/";
+/// How many orphans and dangling rows are listed in the result. All of them are logged.
+const REPORT_LIMIT: usize = 1000;
+
+/// What's done with stored objects nothing references.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanAction
+{
+    Report,
+    /// Moved to `live/quarantine/` and made private, to be looked at before
+    /// they're deleted by hand. Later runs leave them alone.
+    Quarantine,
+    Delete,
+}
+
+/// Compares the objects stored under `live/` with the images, videos, files,
+/// blobs and versions pointing at them. Reports orphans, objects nothing points
+/// at, and dangling rows, rows whose object is missing. Orphans can be
+/// quarantined or deleted and dangling rows deleted, unless it's a dry run.
+#[derive(Serialize, Deserialize, LoggableJob)]
+#[LogName = "log_data"]
+pub struct Reconcile
+{
+    pub orphans: OrphanAction,
+    pub delete_dangling: bool,
+    pub dry_run: bool,
+    /// Seconds since an object was stored for it to be an orphan. Uploads are
+    /// stored before the rows pointing at them are committed.
+    pub min_age: i64,
+    pub log_data: String,
+}
+
+/// What a reconciliation found, and what it fixed unless it was a dry run.
+#[derive(Default, Serialize)]
+pub struct ReconcileResult
+{
+    pub dry_run: bool,
+    pub objects: usize,
+    pub orphan_count: usize,
+    pub orphan_bytes: u64,
+    pub orphans: Vec<String>, // at most `REPORT_LIMIT`
+    pub dangling_count: usize,
+    pub dangling: Vec<String>, // at most `REPORT_LIMIT`
+    pub fixed: usize,
+    pub failed: usize,
+}
+
+/// A row, or a column of one, that points at a stored object.
+enum Reference
+{
+    Image(String),
+    Thumbnail(String), // of the image with the id
+    Video(String),
+    File(String),
+    Version(i32, String),
+    Blob(String, String, i32), // hash, kind and owner
+}
+
+impl fmt::Display for Reference
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self {
+            Reference::Image(ref id) => write!(f, "image {}", id),
+            Reference::Thumbnail(ref id) => write!(f, "thumbnail of image {}", id),
+            Reference::Video(ref id) => write!(f, "video {}", id),
+            Reference::File(ref id) => write!(f, "file {}", id),
+            Reference::Version(_, ref name) => write!(f, "version {}", name),
+            Reference::Blob(ref hash, ref kind, owner) => {
+                write!(f, "{} blob {} of user {}", kind, hash, owner)
+            }
+        }
+    }
+}
+
+impl Reference
+{
+    /// Deletes the dangling row, or clears the thumbnail of an image. Returns
+    /// whether anything changed.
+    fn remove(&self, conn: &PgConnection) -> QueryResult<bool>
+    {
+        use schema::{horus_blobs, horus_files, horus_images, horus_versions, horus_videos};
+
+        let changed = match *self {
+            Reference::Image(ref id) => {
+                diesel::delete(horus_images::table.find(id)).execute(conn)?
+            }
+            Reference::Thumbnail(ref id) => diesel::update(horus_images::table.find(id))
+                .set(horus_images::thumbnail_path.eq(None::<String>))
+                .execute(conn)?,
+            Reference::Video(ref id) => {
+                diesel::delete(horus_videos::table.find(id)).execute(conn)?
+            }
+            Reference::File(ref id) => diesel::delete(horus_files::table.find(id)).execute(conn)?,
+            Reference::Version(id, _) => {
+                diesel::delete(horus_versions::table.filter(horus_versions::id.eq(id)))
+                    .execute(conn)?
+            }
+            // Only once the resources referencing it are gone, they come first.
+            Reference::Blob(ref hash, ref kind, owner) => diesel::delete(
+                horus_blobs::table
+                    .find((hash, kind, owner))
+                    .filter(horus_blobs::ref_count.le(0)),
+            ).execute(conn)?,
+        };
+
+        Ok(changed > 0)
+    }
+}
+
+impl Default for Reconcile
+{
+    /// Scheduled runs only report.
+    fn default() -> Self
+    {
+        Reconcile {
+            orphans: OrphanAction::Report,
+            delete_dangling: false,
+            dry_run: true,
+            min_age: 60 * 60,
+            log_data: String::new(),
+        }
+    }
+}
+
+impl JobPayload for Reconcile
+{
+    const TYPE_NAME: &'static str = "Reconcile";
+}
+
+impl ExecutableJob for Reconcile
+{
+    // Listing a large bucket takes a request per thousand objects.
+    const MAX_RUNTIME: u64 = 60 * 60;
+
+    fn execute(mut self, ctx: &JobContext) -> (Box<Self>, JobResult)
+    {
+        let conn = ctx.connection();
+        let cutoff = Utc::now() - Duration::seconds(self.min_age);
+        let mut tl = format!(
+            "Reconciling {} storage with the database{}",
+            storage::backend().name(),
+            if self.dry_run { ", dry run" } else { "" }
+        );
+        ctx.log(&mut self, &tl);
+
+        // Rows are read before listing, so objects stored while listing can't
+        // make rows look dangling. Objects of rows inserted meanwhile are younger
+        // than `min_age`.
+        ctx.progress(0, "Reading the database");
+        let read = references(conn).and_then(|references| {
+            pending_packages(conn).map(|packages| (references, packages))
+        });
+        let (references, packages) = match read {
+            Ok(read) => read,
+            Err(e) => {
+                tl = format!("Couldn't query what's referenced: {}", e);
+                ctx.log(&mut self, &tl);
+                return (Box::new(self), JobResult::FailedWithReason(tl));
+            }
+        };
+
+        ctx.progress(20, "Listing storage");
+        let objects = match storage::backend().list(LIVE_DIR) {
+            Ok(objects) => objects,
+            Err(e) => {
+                tl = format!("Couldn't list storage: {}", e);
+                ctx.log(&mut self, &tl);
+                return (Box::new(self), JobResult::FailedWithReason(tl));
+            }
+        };
+
+        let mut result = ReconcileResult {
+            dry_run: self.dry_run,
+            objects: objects.len(),
+            ..ReconcileResult::default()
+        };
+
+        let referenced = references
+            .iter()
+            .map(|&(_, ref path)| path.as_str())
+            .chain(packages.iter().map(|path| path.as_str()))
+            .collect::<HashSet<_>>();
+        let stored = objects
+            .iter()
+            .map(|object| object.path.as_str())
+            .collect::<HashSet<_>>();
+
+        ctx.progress(60, "Looking for orphans");
+        for object in objects.iter() {
+            if ctx.is_cancelled() {
+                ctx.log(&mut self, "Cancellation requested, stopping.");
+                return (Box::new(self), JobResult::Cancelled);
+            }
+
+            let orphan = !referenced.contains(object.path.as_str())
+                && !object.path.starts_with(QUARANTINE_DIR)
+                && object.last_modified < cutoff;
+            if !orphan {
+                continue;
+            }
+
+            result.orphan_count += 1;
+            result.orphan_bytes += object.size;
+            if result.orphans.len() < REPORT_LIMIT {
+                result.orphans.push(object.path.clone());
+            }
+
+            match self.handle_orphan(object) {
+                Some(true) => result.fixed += 1,
+                Some(false) => result.failed += 1,
+                None => {}
+            }
+        }
+
+        ctx.progress(80, "Looking for dangling rows");
+        for &(ref reference, ref path) in references.iter() {
+            if ctx.is_cancelled() {
+                ctx.log(&mut self, "Cancellation requested, stopping.");
+                return (Box::new(self), JobResult::Cancelled);
+            }
+
+            if stored.contains(path.as_str()) {
+                continue;
+            }
+
+            let dangling = format!("{} points at missing {}", reference, path);
+            result.dangling_count += 1;
+            if result.dangling.len() < REPORT_LIMIT {
+                result.dangling.push(dangling.clone());
+            }
+
+            match self.handle_dangling(reference, dangling, conn) {
+                Some(true) => result.fixed += 1,
+                Some(false) => result.failed += 1,
+                None => {}
+            }
+        }
+
+        tl = format!(
+            "Found {} orphans ({} bytes) and {} dangling rows among {} objects. \
+             Fixed {}, {} couldn't be fixed.",
+            result.orphan_count,
+            result.orphan_bytes,
+            result.dangling_count,
+            result.objects,
+            result.fixed,
+            result.failed
+        );
+        ctx.log(&mut self, &tl);
+        (Box::new(self), JobResult::complete_with(&result))
+    }
+}
+
+impl Reconcile
+{
+    /// Reports the orphan and quarantines or deletes it if asked to. Returns
+    /// whether that worked, or None if nothing was to be done.
+    fn handle_orphan(&mut self, object: &StoredObject) -> Option<bool>
+    {
+        let quarantined = format!("{}{}", QUARANTINE_DIR, &object.path[LIVE_DIR.len()..]);
+        let (action, done) = match self.orphans {
+            OrphanAction::Report => {
+                let tl = format!("Orphan: {} ({} bytes)", object.path, object.size);
+                self.log(&tl);
+                return None;
+            }
+            OrphanAction::Quarantine if self.dry_run => ("Would quarantine", None),
+            OrphanAction::Delete if self.dry_run => ("Would delete", None),
+            OrphanAction::Quarantine => (
+                "Quarantined",
+                Some(storage::backend().rename(&object.path, &quarantined)),
+            ),
+            OrphanAction::Delete => ("Deleted", Some(storage::backend().delete(&object.path))),
+        };
+
+        let tl = match done {
+            Some(Err(ref e)) => format!("Couldn't handle orphan {}: {}", object.path, e),
+            _ => format!("{} orphan {} ({} bytes)", action, object.path, object.size),
+        };
+        self.log(&tl);
+        done.map(|done| done.is_ok())
+    }
+
+    /// Reports the dangling row and deletes it if asked to. Returns whether that
+    /// worked, or None if nothing was to be done.
+    fn handle_dangling(
+        &mut self,
+        reference: &Reference,
+        dangling: String,
+        conn: &PgConnection,
+    ) -> Option<bool>
+    {
+        if !self.delete_dangling || self.dry_run {
+            let tl = if self.delete_dangling {
+                format!("Would remove: {}", dangling)
+            } else {
+                format!("Dangling: {}", dangling)
+            };
+            self.log(&tl);
+            return None;
+        }
+
+        let (tl, removed) = match reference.remove(conn) {
+            Ok(true) => (format!("Removed: {}", dangling), true),
+            // A blob that's still referenced, or a row that changed in the meantime.
+            Ok(false) => (format!("Left in place: {}", dangling), false),
+            Err(e) => (format!("Couldn't remove {}: {}", reference, e), false),
+        };
+        self.log(&tl);
+        Some(removed)
+    }
+}
+
+/// Everything in the database that points at a stored object, with the path of
+/// the object. Blobs come last, they can only be removed once nothing references
+/// them.
+fn references(conn: &PgConnection) -> QueryResult<Vec<(Reference, String)>>
+{
+    use schema::{horus_blobs, horus_files, horus_images, horus_versions, horus_videos};
+
+    let mut references = Vec::new();
+
+    let images = horus_images::table
+        .select((
+            horus_images::id,
+            horus_images::filepath,
+            horus_images::thumbnail_path,
+        ))
+        .get_results::<(String, String, Option<String>)>(conn)?;
+    for (id, filepath, thumbnail_path) in images {
+        references.push((Reference::Image(id.clone()), filepath));
+        if let Some(thumbnail_path) = thumbnail_path {
+            references.push((Reference::Thumbnail(id), thumbnail_path));
+        }
+    }
+
+    let videos = horus_videos::table
+        .select((horus_videos::id, horus_videos::filepath))
+        .get_results::<(String, String)>(conn)?;
+    references.extend(videos.into_iter().map(|(id, path)| (Reference::Video(id), path)));
+
+    let files = horus_files::table
+        .select((horus_files::id, horus_files::filepath))
+        .get_results::<(String, String)>(conn)?;
+    references.extend(files.into_iter().map(|(id, path)| (Reference::File(id), path)));
+
+    let versions = horus_versions::table
+        .select((
+            horus_versions::id,
+            horus_versions::platform,
+            horus_versions::version_string,
+            horus_versions::aws_bucket_path,
+        ))
+        .get_results::<(i32, String, String, String)>(conn)?;
+    references.extend(versions.into_iter().map(|(id, platform, version, path)| {
+        let name = format!("{} for {}", version, platform.trim_right());
+        (Reference::Version(id, name), path)
+    }));
+
+    let blobs = horus_blobs::table
+        .select((
+            horus_blobs::hash,
+            horus_blobs::kind,
+            horus_blobs::owner,
+            horus_blobs::filepath,
+        ))
+        .get_results::<(String, String, i32, String)>(conn)?;
+    references.extend(
+        blobs
+            .into_iter()
+            .map(|(hash, kind, owner, path)| (Reference::Blob(hash, kind, owner), path)),
+    );
+
+    // Some paths are stored with a leading slash, see `dbtools::get_path_deployment`.
+    for reference in references.iter_mut() {
+        reference.1 = reference.1.trim_left_matches('/').to_string();
+    }
+
+    Ok(references)
+}
+
+/// The packages of deployments that are yet to run, or to be retried. They're
+/// stored before the versions pointing at them exist.
+fn pending_packages(conn: &PgConnection) -> QueryResult<Vec<String>>
+{
+    use schema::horus_jobs::dsl::*;
+
+    let pending = horus_jobs
+        .filter(job_name.like("deployment:deploy%"))
+        .filter(job_status.ne_all(JobStatus::finished()))
+        .select(job_data)
+        .get_results::<Option<Vec<u8>>>(conn)?;
+
+    Ok(pending
+        .into_iter()
+        .filter_map(|data| match debinarize::<Deployment>(&data?) {
+            Ok(Deployment {
+                package: Package::Stored(path),
+                ..
+            }) => Some(path.trim_left_matches('/').to_string()),
+            _ => None,
+        })
+        .collect())
+}
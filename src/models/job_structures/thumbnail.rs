@@ -1,28 +1,184 @@
 use std::boxed::Box;
 
 use diesel::{self, prelude::*};
-use diesel::pg::PgConnection;
+use image::{self, ImageFormat, ImageOutputFormat};
 
-use job_juggler::{ExecutableJob, JobResult, LoggableJob};
+use dbtools;
+use job_juggler::{ExecutableJob, JobContext, JobResult, LoggableJob};
+use super::{decode, JobPayload};
+
+/// Thumbnails are scaled (preserving aspect ratio) to fit in a square of this size.
+const THUMBNAIL_MAX_SIZE: u32 = 400;
 
 #[derive(Serialize, Deserialize, LoggableJob)]
 #[LogName = "log_data"]
-pub struct CreateImageThumbnail {
+pub struct CreateImageThumbnail
+{
     pub image_id: String,
-    pub image_data: Vec<u8>,
-    pub log_data: String
+    /// Where the full image is stored.
+    pub image_path: String,
+    pub log_data: String,
+}
+
+/// Version 1 carried the image itself, which had already been stored by then.
+#[derive(Deserialize)]
+struct CreateImageThumbnailV1
+{
+    image_id: String,
+    #[allow(dead_code)]
+    image_data: Vec<u8>,
+    log_data: String,
+}
+
+/// The result of a finished thumbnail job.
+#[derive(Serialize)]
+pub struct ThumbnailResult
+{
+    pub thumbnail_path: String,
 }
 
-impl ExecutableJob for CreateImageThumbnail {
-    fn execute(self, conn: &PgConnection) -> (Box<Self>, JobResult) {
-        
-        // We only need imagemagick for one command, so we run it directly.
+impl CreateImageThumbnail
+{
+    pub fn new(image_id: String, image_path: String) -> Self
+    {
+        CreateImageThumbnail {
+            image_id: image_id,
+            image_path: image_path,
+            log_data: String::new(),
+        }
+    }
+
+    /// The name of the thumbnail job for the given image. The image id is
+    /// part of the name so the job can be found again from the image.
+    pub fn job_name(image_id: &str) -> String
+    {
+        format!("thumbnail:image:{}", image_id)
+    }
+}
+
+impl JobPayload for CreateImageThumbnail
+{
+    const TYPE_NAME: &'static str = "CreateImageThumbnail";
+    const VERSION: u16 = 2;
+
+    fn migrate(version: u16, data: &[u8]) -> Result<Self, String>
+    {
+        match version {
+            1 => {
+                let old: CreateImageThumbnailV1 = decode(data)?;
+                Ok(CreateImageThumbnail {
+                    image_path: dbtools::get_path_image(&old.image_id),
+                    image_id: old.image_id,
+                    log_data: old.log_data,
+                })
+            }
+            _ => Err(format!("Can't migrate {} from version {}.", Self::TYPE_NAME, version)),
+        }
     }
 }
 
-/// A job that can be executed
-pub trait ExecutableJob
+impl ExecutableJob for CreateImageThumbnail
 {
-    /// Execute this job, returning the object itself at the end alongside the result.
-    fn execute(self, conn: &PgConnection) -> (Box<Self>, JobResult);
+    const MAX_RUNTIME: u64 = 2 * 60;
+
+    fn execute(mut self, ctx: &JobContext) -> (Box<Self>, JobResult)
+    {
+        use dbtools::storage::{self, Visibility};
+        use schema::horus_images::dsl::*;
+
+        let conn = ctx.connection();
+        let mut tl = format!("Creating thumbnail for image {}", &self.image_id);
+        ctx.log(&mut self, &tl);
+
+        let image_data = match storage::backend().get(&self.image_path) {
+            Ok(data) => data,
+            Err(e) => {
+                ctx.log(&mut self, &e);
+                ctx.log(&mut self, "Couldn't read image...aborting thumbnail.");
+                return (Box::new(self), JobResult::Failed);
+            }
+        };
+
+        let full_image = image::load_from_memory_with_format(&image_data, ImageFormat::PNG);
+
+        if full_image.is_err() {
+            tl = format!("{}", full_image.err().unwrap());
+            ctx.log(&mut self, &tl);
+            ctx.log(&mut self, "Couldn't decode image...aborting thumbnail.");
+            return (Box::new(self), JobResult::Failed);
+        }
+
+        let thumbnail = full_image
+            .unwrap()
+            .thumbnail(THUMBNAIL_MAX_SIZE, THUMBNAIL_MAX_SIZE);
+        let mut thumbnail_data: Vec<u8> = Vec::new();
+
+        if let Err(e) = thumbnail.write_to(&mut thumbnail_data, ImageOutputFormat::PNG) {
+            tl = format!("{}", e);
+            ctx.log(&mut self, &tl);
+            ctx.log(&mut self, "Couldn't encode thumbnail...aborting thumbnail.");
+            return (Box::new(self), JobResult::Failed);
+        }
+
+        tl = format!(
+            "Thumbnail is {}x{} ({} bytes), storing it",
+            thumbnail.width(),
+            thumbnail.height(),
+            thumbnail_data.len()
+        );
+        ctx.log(&mut self, &tl);
+
+        if ctx.is_cancelled() {
+            ctx.log(&mut self, "Cancellation requested, stopping.");
+            return (Box::new(self), JobResult::Cancelled);
+        }
+        ctx.progress(50, "Uploading thumbnail");
+
+        let thumb_path = dbtools::get_path_image_thumbnail(&self.image_id);
+        let stored = storage::backend().put(
+            &thumb_path,
+            &thumbnail_data,
+            "image/png",
+            Visibility::Public,
+            None,
+        );
+
+        if let Err(e) = stored {
+            ctx.log(&mut self, &e);
+            ctx.log(&mut self, "Couldn't store thumbnail...aborting thumbnail.");
+            return (Box::new(self), JobResult::Failed);
+        }
+
+        ctx.log(&mut self, "Done...result was ok.");
+        ctx.log(&mut self, "Updating image in database...");
+
+        let db_result = diesel::update(horus_images.find(&self.image_id))
+            .set(thumbnail_path.eq(Some(thumb_path.clone())))
+            .execute(conn);
+
+        match db_result {
+            Err(e) => {
+                tl = format!("{}", e);
+                ctx.log(&mut self, &tl);
+                ctx.log(&mut self, "Couldn't update image in database...aborting thumbnail.");
+                (Box::new(self), JobResult::Failed)
+            }
+            Ok(0) => {
+                // The image was deleted while the job was waiting.
+                ctx.log(&mut self, "Image no longer exists, removing thumbnail.");
+                if storage::backend().delete(&thumb_path).is_err() {
+                    ctx.log(&mut self, "Couldn't remove thumbnail from storage.");
+                }
+                (Box::new(self), JobResult::Complete)
+            }
+            Ok(_) => {
+                tl = format!("Thumbnail for image {} stored at {}", &self.image_id, &thumb_path);
+                ctx.log(&mut self, &tl);
+                let result = ThumbnailResult {
+                    thumbnail_path: thumb_path,
+                };
+                (Box::new(self), JobResult::complete_with(&result))
+            }
+        }
+    }
 }
@@ -0,0 +1,115 @@
+use std::boxed::Box;
+use std::fs;
+use std::io::ErrorKind;
+
+use chrono::{Duration, Local};
+use diesel::{self, prelude::*};
+
+use dbtools;
+use job_juggler::{ExecutableJob, JobContext, JobResult, LoggableJob};
+use models::UploadSession;
+use super::{JobPayload, ReapTotals};
+
+/// Deletes resumable uploads of this host that haven't been continued for a
+/// while, along with the data that was sent for them. Uploads started on other
+/// hosts are left for them, their data isn't here.
+#[derive(Serialize, Deserialize, LoggableJob)]
+#[LogName = "log_data"]
+pub struct ReapStaleUploads
+{
+    /// Seconds since an upload was last continued for it to be stale.
+    pub max_idle: i64,
+    pub log_data: String,
+}
+
+impl Default for ReapStaleUploads
+{
+    fn default() -> Self
+    {
+        ReapStaleUploads {
+            max_idle: 24 * 60 * 60,
+            log_data: String::new(),
+        }
+    }
+}
+
+impl JobPayload for ReapStaleUploads
+{
+    const TYPE_NAME: &'static str = "ReapStaleUploads";
+}
+
+impl ExecutableJob for ReapStaleUploads
+{
+    const MAX_RUNTIME: u64 = 10 * 60;
+
+    fn execute(mut self, ctx: &JobContext) -> (Box<Self>, JobResult)
+    {
+        use schema::horus_upload_sessions::dsl::*;
+
+        let conn = ctx.connection();
+        let cutoff = Local::now().naive_utc() - Duration::seconds(self.max_idle);
+        let mut tl = format!("Reaping uploads idle since before {}", cutoff);
+        ctx.log(&mut self, &tl);
+
+        let stale = horus_upload_sessions
+            .filter(last_activity.lt(cutoff))
+            .filter(upload_host.eq(dbtools::upload_host()).or(upload_host.eq("")))
+            .get_results::<UploadSession>(conn);
+
+        let stale = match stale {
+            Ok(stale) => stale,
+            Err(e) => {
+                tl = format!("Couldn't query stale uploads: {}", e);
+                ctx.log(&mut self, &tl);
+                return (Box::new(self), JobResult::FailedWithReason(tl));
+            }
+        };
+
+        let mut totals = ReapTotals::default();
+        for session in stale.iter() {
+            if ctx.is_cancelled() {
+                ctx.log(&mut self, "Cancellation requested, stopping.");
+                return (Box::new(self), JobResult::Cancelled);
+            }
+
+            // Only reaped if it wasn't continued in the meantime.
+            let deleted = diesel::delete(
+                horus_upload_sessions
+                    .find(&session.id)
+                    .filter(last_activity.lt(cutoff)),
+            ).execute(conn);
+
+            let partial = dbtools::get_path_partial_upload(&session.id);
+            let reaped = match deleted {
+                Ok(0) => continue,
+                Ok(_) => match fs::remove_file(&partial) {
+                    Ok(_) => true,
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => true,
+                    Err(e) => {
+                        tl = format!("Couldn't remove {}: {}", partial.display(), e);
+                        ctx.log(&mut self, &tl);
+                        false
+                    }
+                },
+                Err(e) => {
+                    tl = format!("Couldn't delete upload {}: {}", session.id, e);
+                    ctx.log(&mut self, &tl);
+                    false
+                }
+            };
+
+            if reaped {
+                totals.reaped += 1;
+            } else {
+                totals.failed += 1;
+            }
+        }
+
+        tl = format!(
+            "Reaped {} stale uploads, {} couldn't be reaped.",
+            totals.reaped, totals.failed
+        );
+        ctx.log(&mut self, &tl);
+        (Box::new(self), JobResult::complete_with(&totals))
+    }
+}
@@ -11,7 +11,11 @@ mod himage;
 mod hvideo;
 mod hpaste;
 mod hfile;
+mod blob;
 mod hjob;
+mod job_event;
+mod job_schedule;
+mod upload_session;
 
 pub use self::horus_version::{HorusVersion, NewHorusVersion};
 pub use self::deployment_key::DeploymentKey;
@@ -24,4 +28,8 @@ pub use self::himage::{HImage, FixedDateHImage};
 pub use self::hvideo::HVideo;
 pub use self::hpaste::HPaste;
 pub use self::hfile::HFile;
+pub use self::blob::{Blob, BlobKind};
 pub use self::hjob::{HJob, JobPriority, JobStatus, NewJob};
+pub use self::job_event::{JobEvent, JobEventType, NewJobEvent};
+pub use self::job_schedule::JobSchedule;
+pub use self::upload_session::{UploadSession, UploadType};
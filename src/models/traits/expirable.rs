@@ -0,0 +1,27 @@
+use chrono::{Local, NaiveDateTime};
+
+/// Trait for resources that can be given an expiration time.
+pub trait Expirable
+{
+    /// Gets the time the resource expires at, `None` if it never expires.
+    fn expires_at(&self) -> Option<NaiveDateTime>;
+
+    /// Checks whether the expiration time of the resource has passed.
+    fn is_expired(&self) -> bool
+    {
+        // Expiration times are stored as UTC, see `conv::get_dt_from_duration`.
+        match self.expires_at() {
+            Some(t) => t <= Local::now().naive_utc(),
+            None => false,
+        }
+    }
+}
+
+/// Resources that are only known by their traits, eg. `Box<dyn Passwordable>`.
+impl<T: Expirable + ?Sized> Expirable for Box<T>
+{
+    fn expires_at(&self) -> Option<NaiveDateTime>
+    {
+        (**self).expires_at()
+    }
+}
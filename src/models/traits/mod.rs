@@ -1 +1,2 @@
+pub mod expirable;
 pub mod passwordable;
@@ -1,8 +1,11 @@
 use bcrypt::{hash, verify, DEFAULT_COST};
 use diesel::pg::PgConnection;
 
+use models::BlobKind;
+use super::expirable::Expirable;
+
 /// Trait for items that can have a password applied to them.
-pub trait Passwordable {
+pub trait Passwordable: Expirable {
     /// Set the password on the object.
     /// The string contains an error if there is one.
     fn set_password(&mut self, password: Option<String>, conn: &PgConnection) -> Option<String>;
@@ -24,6 +27,24 @@ pub trait Passwordable {
     /// Gets the path of this resource on s3 
     fn get_s3_location(&self) -> String;
 
+    /// Gets the path of the thumbnail for this resource on s3, if it has one.
+    fn get_s3_thumbnail_location(&self) -> Option<String>
+    {
+        None
+    }
+
+    /// Gets the name the resource is downloaded as, if it's downloaded rather than shown.
+    fn get_download_name(&self) -> Option<String>
+    {
+        None
+    }
+
+    /// Gets the hash and kind of the blob the resource is stored as, if it's stored
+    /// as one. Blobs can be shared with other resources, see `dbtools::blobs`.
+    fn get_blob(&self) -> Option<(String, BlobKind)>
+    {
+        None
+    }
 
     /// Gets the owner of te object
     fn owner(&self) -> i32;
@@ -0,0 +1,128 @@
+use chrono::{Local, NaiveDateTime};
+
+use dbtools;
+use schema::horus_upload_sessions;
+use super::User;
+
+/// A resumable upload that's still being sent, see `routes::upload`. Its data is
+/// kept at `dbtools::get_path_partial_upload` on the host that started it until
+/// it's committed.
+#[derive(Debug, Identifiable, Queryable, Insertable, AsChangeset, Associations)]
+#[table_name = "horus_upload_sessions"]
+#[belongs_to(User, foreign_key = "owner")]
+pub struct UploadSession
+{
+    pub id: String,
+    pub owner: i32,
+    pub upload_type: String, // see `UploadType`
+    pub upload_length: i64,
+    pub upload_offset: i64,
+    pub filename: Option<String>,                // files
+    pub expiration_time: Option<NaiveDateTime>, // files
+    pub version_string: Option<String>,         // deployments
+    pub platform_string: Option<String>,        // deployments
+    pub deployment_key: Option<String>,         // deployments
+    pub created_at: NaiveDateTime,
+    pub last_activity: NaiveDateTime,
+    pub upload_host: String, // see `dbtools::upload_host`, empty for any host
+}
+
+/// What an upload becomes once it's committed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UploadType
+{
+    File,
+    Deployment,
+}
+
+impl UploadType
+{
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            UploadType::File => "file",
+            UploadType::Deployment => "deployment",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name {
+            "file" => Some(UploadType::File),
+            "deployment" => Some(UploadType::Deployment),
+            _ => None,
+        }
+    }
+}
+
+impl UploadSession
+{
+    fn new(id: String, owner: i32, upload_type: UploadType, upload_length: i64) -> Self
+    {
+        let now = Local::now().naive_utc();
+
+        UploadSession {
+            id: id,
+            owner: owner,
+            upload_type: upload_type.name().to_string(),
+            upload_length: upload_length,
+            upload_offset: 0,
+            filename: None,
+            expiration_time: None,
+            version_string: None,
+            platform_string: None,
+            deployment_key: None,
+            created_at: now,
+            last_activity: now,
+            upload_host: dbtools::upload_host().to_string(),
+        }
+    }
+
+    /// A session for an upload that becomes an `HFile`.
+    pub fn for_file(
+        id: String,
+        owner: i32,
+        upload_length: i64,
+        filename: String,
+        expiration_time: Option<NaiveDateTime>,
+    ) -> Self
+    {
+        let mut session = Self::new(id, owner, UploadType::File, upload_length);
+        session.filename = Some(filename);
+        session.expiration_time = expiration_time;
+        session
+    }
+
+    /// A session for an upload that becomes the package of a deployment.
+    pub fn for_deployment(
+        id: String,
+        owner: i32,
+        upload_length: i64,
+        version_string: String,
+        platform_string: String,
+        deployment_key: String,
+    ) -> Self
+    {
+        let mut session = Self::new(id, owner, UploadType::Deployment, upload_length);
+        session.version_string = Some(version_string);
+        session.platform_string = Some(platform_string);
+        session.deployment_key = Some(deployment_key);
+        session
+    }
+
+    pub fn upload_type(&self) -> Option<UploadType>
+    {
+        UploadType::from_name(&self.upload_type)
+    }
+
+    pub fn is_complete(&self) -> bool
+    {
+        self.upload_offset == self.upload_length
+    }
+
+    /// Whether the data of this upload is on this host.
+    pub fn is_local(&self) -> bool
+    {
+        self.upload_host.is_empty() || self.upload_host == dbtools::upload_host()
+    }
+}
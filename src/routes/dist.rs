@@ -1,17 +1,30 @@
+use std::io::{self, Read};
+
 use bcrypt::{hash, verify, DEFAULT_COST};
 use diesel::{self, prelude::*};
+use diesel::pg::PgConnection;
 use rand::{self, Rng};
 
 use rocket::http::Status;
 use rocket::data::Data;
 use rocket::response::{status, Failure, Redirect};
 
-use {dbtools, DbConn};
+use DbConn;
+use dbtools;
+use dbtools::storage::{self, Visibility};
 use schema::{self, deployment_keys::dsl::*};
-use fields::{Authentication, PrivilegeLevel};
+use fields::{Authentication, ContentLength, PrivilegeLevel};
 use models::{DeploymentKey, HorusVersion, JobPriority, LicenseKey, NewJob};
 use models::job_structures::{self, Deployment};
-use job_juggler;
+use job_juggler::{self, JobContext};
+use routes::upload;
+
+/// The stage of a deployment job while its package is stored.
+const UPLOAD_STAGE: &str = "Uploading package";
+/// How much of the progress of a deployment storing its package makes up.
+const UPLOAD_PERCENT: u64 = 80;
+/// How many bytes of a package of unknown size are stored between progress reports.
+const UPLOAD_REPORT_BYTES: u64 = 16 * 1024 * 1024;
 
 #[get("/version")]
 pub fn version_legacy() -> Redirect
@@ -62,7 +75,7 @@ pub fn get_latest(plat: String, conn: DbConn, _auth: Authentication) -> Result<R
     }
 
     let ver = ver.unwrap();
-    let url = dbtools::s3::get_s3_presigned_url(ver.aws_path());
+    let url = storage::signed_url(&ver.aws_path());
 
     if url.is_err() {
         Err(Failure(Status::ServiceUnavailable))
@@ -112,49 +125,181 @@ pub fn enable_deployment(
     Ok(status::Custom(Status::Ok, ()))
 }
 
-/// Returns HTTP created with an integer id for the deployment.
+/// Returns HTTP accepted with the integer id of the deployment job,
+/// which can be polled at `/jobs/poll/<id>`.
 #[post("/deploy/new/<platform>/<version>", format = "application/octet-stream",
        data = "<update_package>")]
 pub fn deploy(
     platform: String,
     version: String,
     update_package: Data,
+    length: Option<ContentLength>,
     depkey: DeploymentKey, // encompasses license key
 ) -> Result<status::Custom<String>, Failure>
 {
-    use std::io::Read;
+    if !is_valid_version(&version) {
+        return Err(Failure(Status::BadRequest));
+    }
+
+    let job_id = queue_deployment(
+        &mut update_package.open(),
+        length.map(|l| l.0 as u64),
+        version,
+        platform,
+        depkey.hash(),
+        depkey.get_owner(),
+    )?;
 
+    Ok(status::Custom(Status::Accepted, job_id.to_string()))
+}
+
+pub fn is_valid_version(version: &str) -> bool
+{
     // not more than xxx.xxx.xxx not less than x.x.x
     // TODO: Regex this.
-    if version.len() > 11 || version.len() < 5 {
-        return Err(Failure(Status::BadRequest));
-    }
+    version.len() <= 11 && version.len() >= 5
+}
+
+/// Whether a package was deployed as the version for the platform already. Versions
+/// can't be deployed twice, clients may have downloaded the package of one.
+pub fn version_exists(version: &str, platform: &str, conn: &PgConnection) -> Result<bool, Failure>
+{
+    use schema::horus_versions;
+
+    let existing = horus_versions::table
+        .find((platform, version))
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|e| {
+            eprintln!("Couldn't look up version {} for {}: {}", version, platform, e);
+            Failure(Status::InternalServerError)
+        })?;
 
-    let file_data: Vec<u8> = update_package.open().bytes().map(|x| x.unwrap()).collect();
+    Ok(existing > 0)
+}
 
-    let deployment_data = Deployment::new(file_data, depkey.hash(), version, platform.clone());
+/// Stores the package and queues the job that deploys it, returning the id of
+/// the job. Also used to commit resumable uploads. `length` is the size of the
+/// package if it's known, storing it is reported as the progress of the job.
+/// Fails with `409 Conflict` if the version was deployed already.
+pub fn queue_deployment(
+    package: &mut dyn Read,
+    length: Option<u64>,
+    version: String,
+    platform: String,
+    depkey_hash: String,
+    owner: i32,
+) -> Result<i32, Failure>
+{
+    let conn = dbtools::get_db_conn_requestless().map_err(|_| {
+        eprintln!("Couldn't connect to queue the deployment of {}.", version);
+        Failure(Status::ServiceUnavailable)
+    })?;
+
+    if version_exists(&version, &platform, &conn)? {
+        return Err(Failure(Status::Conflict));
+    }
+
+    // Staged under a key of its own, the job moves it into place.
+    let package_path = dbtools::get_path_deployment_staging(&dbtools::get_random_char_id(32));
+    let deployment_data = Deployment::new(
+        package_path.clone(),
+        depkey_hash,
+        version,
+        platform.clone(),
+    );
     let deployment_data = job_structures::binarize(&deployment_data);
 
-    // Create job with file data.
+    // The job is held back until the package is stored, so it can be followed meanwhile.
     let new_job = NewJob::new(
-        depkey.get_owner(),
+        owner,
         "deployment:deploy:".to_string() + &platform,
         Some(deployment_data),
-        JobPriority::System,
+        JobPriority::DoNotProcess,
     );
 
-    let queue_result = job_juggler::enqueue_job(new_job);
+    let job_id = job_juggler::enqueue_job(new_job).map_err(|e| {
+        eprintln!("Couldn't queue deployment: {}", e);
+        Failure(Status::InternalServerError)
+    })?;
+
+    let ctx = JobContext::new(job_id, owner, &conn);
+
+    ctx.progress(0, UPLOAD_STAGE);
+    let stored = upload::store(
+        &package_path,
+        &mut UploadProgress::new(package, length, &ctx),
+        Visibility::Private,
+        Some(&platform),
+    );
 
-    if queue_result.is_err() {
-        return Err(Failure(Status::InternalServerError));
+    if let Err(failure) = stored {
+        if let Err(e) = job_juggler::abandon_job(job_id, "Couldn't store the package.", &conn) {
+            eprintln!("Couldn't fail deployment job {}: {}", job_id, e);
+        }
+        return Err(failure);
     }
 
-    queue_result.unwrap();
+    job_juggler::release_job(job_id, JobPriority::System, &conn).map_err(|e| {
+        eprintln!("Couldn't queue deployment: {}", e);
+        Failure(Status::InternalServerError)
+    })?;
+
+    Ok(job_id)
+}
 
-    Ok(status::Custom(
-        Status::Accepted,
-        "Job queued for processing.".to_string(),
-    ))
+/// Reports how much of a package was read while it's stored as the progress of
+/// its deployment job.
+struct UploadProgress<'a, 'c: 'a>
+{
+    package: &'a mut dyn Read,
+    length: Option<u64>,
+    read: u64,
+    reported: u64,
+    ctx: &'a JobContext<'c>,
+}
+
+impl<'a, 'c> UploadProgress<'a, 'c>
+{
+    fn new(package: &'a mut dyn Read, length: Option<u64>, ctx: &'a JobContext<'c>) -> Self
+    {
+        UploadProgress {
+            package: package,
+            length: if length == Some(0) { None } else { length },
+            read: 0,
+            reported: 0,
+            ctx: ctx,
+        }
+    }
+}
+
+impl<'a, 'c> Read for UploadProgress<'a, 'c>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        let read = self.package.read(buf)?;
+        self.read += read as u64;
+
+        // Only whole percents are reported, every event is a write to the database.
+        match self.length {
+            Some(length) => {
+                let percent = self.read.min(length) * UPLOAD_PERCENT / length;
+                if percent > self.reported {
+                    self.reported = percent;
+                    self.ctx.progress(percent as i16, UPLOAD_STAGE);
+                }
+            }
+            None => {
+                if self.read - self.reported >= UPLOAD_REPORT_BYTES {
+                    self.reported = self.read;
+                    let stage = format!("{} ({} MiB)", UPLOAD_STAGE, self.read / (1024 * 1024));
+                    self.ctx.progress(0, &stage);
+                }
+            }
+        }
+
+        Ok(read)
+    }
 }
 
 /// Verifies if a key is correct and returns its database object if so.
@@ -1,44 +1,58 @@
-use std::path::{Path, PathBuf};
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
 use diesel;
 use chrono::{Local, NaiveDateTime};
 #[allow(unused_imports)]
 use diesel::prelude::*;
-use rocket::request::Request;
-use rocket::response::{status, Failure, NamedFile, Responder, Response};
-use rocket::http::{ContentType, Status};
+use diesel::pg::PgConnection;
+use rocket::response::{status, Failure, NamedFile};
+use rocket::http::Status;
 use rocket::data::Data;
 use rocket_contrib::{Json, Template};
 
-use models::HFile;
+use models::{BlobKind, HFile};
 use fields::{Authentication, PrivilegeLevel};
 use DbConn;
-use {conv, dbtools};
+use {contexts, conv, dbtools};
+use dbtools::storage;
 use fields::FileName;
-
-pub struct DownloadableFile
-{
-    pub afile: NamedFile,
-    pub name: FileName,
-}
+use routes::http_errors::unexpired;
+use routes::upload::{self, Format};
 
 #[get("/<file_id>")]
-pub fn get(file_id: String, conn: DbConn) -> Option<Template>
+pub fn get(file_id: String, conn: DbConn) -> Result<Template, Failure>
 {
     use schema::horus_files::dsl::*;
 
     let hfile = horus_files.find(&file_id).get_result::<HFile>(&*conn);
 
     if hfile.is_err() {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
-    let mut hfile = hfile.unwrap();
+    let mut hfile = unexpired(hfile.unwrap())?;
     // TODO, make this an async javascript change, visiting the page doesn't imply they click dl.
     hfile.download_counter = Some(hfile.download_counter.unwrap() + 1);
     hfile.save_changes::<HFile>(&*conn).unwrap();
 
-    Some(Template::render("show_file", &hfile))
+    // Blobs are stored without a name and may be on another origin, where the
+    // `download` attribute is ignored. Files with a password are downloaded through
+    // `password::check` instead.
+    let src = if hfile.password.is_none() {
+        storage::download_url(&hfile.filepath, &hfile.filename).map_err(|e| {
+            eprintln!("Couldn't sign a download of {}: {}", hfile.filepath, e);
+            Failure(Status::ServiceUnavailable)
+        })?
+    } else {
+        storage::backend().public_url(&hfile.filepath)
+    };
+
+    let context = contexts::ShowFile {
+        src: src,
+        item: hfile,
+    };
+
+    Ok(Template::render("show_file", &context))
 }
 
 #[get("/<uid>/list/<page>")]
@@ -109,45 +123,56 @@ pub fn new_file(
     conn: DbConn,
 ) -> Result<status::Created<()>, Failure>
 {
-    use schema::horus_files;
-    let fid: String = dbtools::get_random_char_id(8);
-    let pathstr = dbtools::get_path_file(&fid);
-
-    let hfile = HFile {
-        id: fid.clone(),
-        owner: auth.get_userid(),
-        filename: file_name.0,
-        filepath: pathstr.clone(),
-        date_added: Local::now().naive_utc(),
-        is_expiry: expire_time.is_some(),
-        expiration_time: expire_time,
-        download_counter: None, // defaults 0
-        password: None
-    };
-
-    let file_data: Vec<u8> = file_data.open().bytes().map(|x| x.unwrap()).collect();
-
-    // No need to decode as we are getting raw bytes through an octet-stream, no base64
-    let s3result = dbtools::s3::resource_to_s3_named(&hfile.filename, &pathstr, &file_data);
-
-    if s3result.is_err() {
-        return Err(Failure(Status::ServiceUnavailable));
-    }
-
-    let result = diesel::insert_into(horus_files::table)
-        .values(&hfile)
-        .get_result::<HFile>(&*conn);
+    let result = create_file(
+        &mut file_data.open(),
+        file_name,
+        expire_time,
+        auth.get_userid(),
+        &conn,
+    )?;
 
-    if result.is_err() {
-        return Err(Failure(Status::BadRequest));
-    }
-    let result = result.unwrap();
     Ok(status::Created(
         String::from("/file/") + result.id.as_str(),
         None,
     ))
 }
 
+/// Stores `file_data` and creates the file for it, also used to commit resumable uploads.
+pub fn create_file(
+    file_data: &mut dyn Read,
+    file_name: FileName,
+    expire_time: Option<NaiveDateTime>,
+    owner: i32,
+    conn: &PgConnection,
+) -> Result<HFile, Failure>
+{
+    use schema::horus_files;
+    let fid: String = dbtools::get_random_char_id(8);
+
+    // No need to decode as we are getting raw bytes through an octet-stream, no base64.
+    // The blob can be shared by files with other names, so it's stored without one.
+    let format = Format::Any(Some(&file_name.0));
+    upload::store_blob(file_data, format, BlobKind::File, owner, conn, |blob| {
+        let hfile = HFile {
+            id: fid,
+            owner: owner,
+            filename: file_name.0.clone(),
+            filepath: blob.filepath.clone(),
+            date_added: Local::now().naive_utc(),
+            is_expiry: expire_time.is_some(),
+            expiration_time: expire_time,
+            download_counter: None, // defaults 0
+            password: None,
+            blob_hash: Some(blob.hash.clone()),
+            blob_kind: blob.kind.clone(),
+        };
+
+        diesel::insert_into(horus_files::table)
+            .values(&hfile)
+            .get_result::<HFile>(conn)
+    })
+}
+
 #[delete("/<file_id>")]
 pub fn delete(
     file_id: String,
@@ -171,10 +196,14 @@ pub fn delete(
 
 fn delete_internal(hfile: HFile, conn: DbConn) -> Result<status::Custom<()>, Failure>
 {
-    let s3result = dbtools::s3::delete_s3_object(&hfile.filepath);
+    // Files stored before blobs own their object, blobs are released below.
+    if hfile.blob_hash.is_none() {
+        let deleted = storage::backend().delete(&hfile.filepath);
 
-    if s3result.is_err() {
-        return Err(Failure(Status::ServiceUnavailable));
+        if let Err(e) = deleted {
+            eprintln!("Couldn't delete file: {}", e);
+            return Err(Failure(Status::ServiceUnavailable));
+        }
     }
 
     let result = diesel::delete(&hfile).execute(&*conn);
@@ -186,28 +215,11 @@ fn delete_internal(hfile: HFile, conn: DbConn) -> Result<status::Custom<()>, Fai
         return Err(Failure(Status::InternalServerError));
     }
 
-    Ok(status::Custom(Status::Ok, ()))
-}
-
-impl Responder<'static> for DownloadableFile
-{
-    fn respond_to(self, _: &Request) -> Result<Response<'static>, Status>
-    {
-        let mut response = Response::new();
-        if let Some(ext) = self.afile.path().extension() {
-            let ext_string = ext.to_string_lossy().to_lowercase();
-            if let Some(content_type) = ContentType::from_extension(&ext_string) {
-                response.set_header(content_type);
-            }
-        }
-
-        let mut cdstr = String::from("attachment; filename=\"");
-        cdstr += self.name.0.as_str();
-        cdstr += "\"";
-        response.set_raw_header("content-disposition", cdstr);
-        response.set_streamed_body(self.afile.take_file());
-        Ok(response)
+    if let Some(ref hash) = hfile.blob_hash {
+        upload::release_blob(hash, BlobKind::File, hfile.owner, &conn);
     }
+
+    Ok(status::Custom(Status::Ok, ()))
 }
 
 // This is not to do with files as user-uploaded bits. This
@@ -1,9 +1,24 @@
 use rocket::Request;
+use rocket::http::Status;
+use rocket::response::Failure;
 use rocket_contrib::Template;
 
+use models::traits::expirable::Expirable;
+
+/// The picture on the error pages, served with the other static assets so it's
+/// there whichever storage backend is used.
+const ERROR_IMAGE: &'static str = "/static/errors/horus404.png";
+
 #[derive(Serialize)]
 struct Context404 {
-    uri: String
+    uri: String,
+    image_src: String,
+}
+
+#[derive(Serialize)]
+struct Context410 {
+    uri: String,
+    image_src: String,
 }
 
 #[error(404)]
@@ -11,8 +26,32 @@ fn not_found(req: &Request) -> Template
 {
 
     let context = Context404 {
-        uri: req.uri().as_str().to_string()
+        uri: req.uri().as_str().to_string(),
+        image_src: ERROR_IMAGE.to_string(),
     };
 
     Template::render("errors/404", &context)
 }
+
+#[error(410)]
+fn gone(req: &Request) -> Template
+{
+    let context = Context410 {
+        uri: req.uri().as_str().to_string(),
+        image_src: ERROR_IMAGE.to_string(),
+    };
+
+    Template::render("errors/410", &context)
+}
+
+/// Passes the resource through if it hasn't expired, otherwise fails
+/// with `410 Gone`. Every route serving a resource should go through this,
+/// as expired resources can outlive their expiration time until they are reaped.
+pub fn unexpired<T: Expirable>(resource: T) -> Result<T, Failure>
+{
+    if resource.is_expired() {
+        Err(Failure(Status::Gone))
+    } else {
+        Ok(resource)
+    }
+}
@@ -1,37 +1,36 @@
-extern crate base64;
-
-use std::path::Path;
-use std::io::Read;
-
 use chrono::{Local, NaiveDateTime};
 #[allow(unused_imports)]
 use diesel::{self, prelude::*};
-use rocket::response::{status, Failure, NamedFile};
+use diesel::pg::PgConnection;
+use rocket::response::{status, Failure, Redirect};
 use rocket::data::Data;
 use rocket::http::Status;
 use rocket_contrib::{Json, Template};
 
 use DbConn;
 use dbtools;
+use dbtools::storage;
+use job_juggler;
 use {contexts, conv};
-use models::HImage;
+use models::{BlobKind, HImage, JobPriority, JobStatus, NewJob};
+use models::job_structures::{self, CreateImageThumbnail};
 use fields::{Authentication, PrivilegeLevel};
 use forms::HImageChangesetForm;
+use routes::http_errors::unexpired;
+use routes::upload::{self, DataUrlReader, Format};
 
 #[get("/<image_id>")]
-pub fn show(image_id: String, conn: DbConn) -> Option<Template>
+pub fn show(image_id: String, conn: DbConn) -> Result<Template, Failure>
 {
     use schema::horus_images::dsl::*;
 
     let image = horus_images.find(&image_id).get_result::<HImage>(&*conn);
 
     if image.is_err() {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
-    let image = image.unwrap();
-    let mut uri = String::from("https://s3.eu-central-1.amazonaws.com/horuscdn/live/images/");
-    uri += &image.id.clone();
-    uri += ".png";
+    let image = unexpired(image.unwrap())?;
+    let uri = storage::backend().public_url(&image.filepath);
 
     // Todo: Get this string programatically
     // And add width/height numbers, title, etc.
@@ -45,31 +44,81 @@ pub fn show(image_id: String, conn: DbConn) -> Option<Template>
     let context = contexts::ShowImage {
         password: image.password.is_some(),
         item: image.with_displayable_date(),
+        src: uri,
         meta_tag: Some(metatag),
     };
 
-    Some(Template::render("show_image", &context))
+    Ok(Template::render("show_image", &context))
 }
 
+/// Redirects to the image in storage. Images behind a password are only
+/// linked to through `password::check`, once it has been entered.
 #[get("/full/<image_id>")]
-pub fn full(image_id: String, conn: DbConn) -> Option<NamedFile>
+pub fn full(image_id: String, conn: DbConn) -> Result<Redirect, Failure>
 {
     use schema::horus_images::dsl::*;
     let image = horus_images.find(image_id).get_result::<HImage>(&*conn);
 
     if image.is_err() {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
-    let image = image.unwrap();
+    let image = unexpired(image.unwrap())?;
 
-    let image_path = Path::new(&image.filepath);
-    NamedFile::open(image_path).ok()
+    if image.password.is_some() {
+        return Err(Failure(Status::NotFound));
+    }
+
+    Ok(Redirect::to(&storage::backend().public_url(&image.filepath)))
 }
 
+/// Redirects to the thumbnail of the image. The full image is only
+/// served in its place while the thumbnail job is still pending.
 #[get("/thumb/<image_id>")]
-pub fn thumb(image_id: String, conn: DbConn) -> Option<NamedFile>
+pub fn thumb(image_id: String, conn: DbConn) -> Result<Redirect, Failure>
 {
-    full(image_id, conn)
+    use schema::horus_images::dsl::*;
+    let image = horus_images.find(image_id).get_result::<HImage>(&*conn);
+
+    if image.is_err() {
+        return Err(Failure(Status::NotFound));
+    }
+    let image = unexpired(image.unwrap())?;
+
+    // Thumbnails share the visibility of the image.
+    if image.password.is_some() {
+        return Err(Failure(Status::NotFound));
+    }
+
+    if let Some(ref path) = image.thumbnail_path {
+        return Ok(Redirect::to(&storage::backend().public_url(path)));
+    }
+
+    if thumbnail_job_pending(&image.id, &*conn) {
+        Ok(Redirect::to(&storage::backend().public_url(&image.filepath)))
+    } else {
+        Err(Failure(Status::NotFound))
+    }
+}
+
+/// Returns true if the thumbnail job of the image has yet to finish.
+fn thumbnail_job_pending(image_id: &str, conn: &PgConnection) -> bool
+{
+    use schema::horus_jobs::dsl::*;
+
+    let pending = horus_jobs
+        .filter(job_name.eq(CreateImageThumbnail::job_name(image_id)))
+        .filter(job_status.eq_any(vec![
+            JobStatus::Waiting as i32,
+            JobStatus::Queued as i32,
+            JobStatus::Running as i32,
+        ]))
+        .count()
+        .get_result::<i64>(conn);
+
+    match pending {
+        Ok(count) => count > 0,
+        Err(_) => false,
+    }
 }
 
 /// `list` returns a paginated JSON array of HImage objects.
@@ -128,10 +177,20 @@ pub fn delete(
 
 fn delete_internal(image: HImage, conn: DbConn) -> Result<status::Custom<()>, Failure>
 {
-    let s3result = dbtools::s3::delete_s3_object(&image.filepath);
+    // Images stored before blobs own their object, blobs are released below.
+    if image.blob_hash.is_none() {
+        let deleted = storage::backend().delete(&image.filepath);
+
+        if let Err(e) = deleted {
+            eprintln!("Couldn't delete image: {}", e);
+            return Err(Failure(Status::ServiceUnavailable));
+        }
+    }
 
-    if s3result.is_err() {
-        return Err(Failure(Status::ServiceUnavailable));
+    if let Some(ref thumb_path) = image.thumbnail_path {
+        if storage::backend().delete(thumb_path).is_err() {
+            eprintln!("Couldn't delete thumbnail of image {} from storage.", image.id);
+        }
     }
 
     let result = diesel::delete(&image).execute(&*conn);
@@ -144,6 +203,10 @@ fn delete_internal(image: HImage, conn: DbConn) -> Result<status::Custom<()>, Fa
         return Err(Failure(Status::InternalServerError));
     }
 
+    if let Some(ref hash) = image.blob_hash {
+        upload::release_blob(hash, BlobKind::Image, image.owner, &conn);
+    }
+
     Ok(status::Custom(Status::Ok, ()))
 }
 
@@ -158,45 +221,33 @@ fn new_img(
     use schema::horus_images;
     let iid: String = dbtools::get_random_char_id(8);
 
-    let pathstr = dbtools::get_path_image(&iid);
-
-    let image = HImage::new(iid.clone(),
-            Some(title),
-            auth.get_userid(),
-            pathstr.clone(),
-            Local::now().naive_utc(),
-            exp.is_some(),
-            exp);
-
     // SAVE THE FILE THEN INSERT DB
-    let img_data: Vec<u8> = img_data.open().bytes().map(|x| x.unwrap()).collect();
-
-    // Removes the prefix
-    let raw_img_data = base64::decode(&img_data[22..]);
-
-    if raw_img_data.is_err() {
-        println!("decode error");
-        return Err(Failure(Status::BadRequest));
-    }
-
-    let raw_img_data = raw_img_data.unwrap();
-
-    let s3result = dbtools::s3::resource_to_s3(&pathstr, &raw_img_data);
-
-    if s3result.is_err() {
-        return Err(Failure(Status::ServiceUnavailable));
-    }
-
-    let result = diesel::insert_into(horus_images::table)
-        .values(&image)
-        .get_result::<HImage>(&*conn);
-
-    if result.is_err() {
-        return Err(Failure(Status::InternalServerError));
-    }
-
-    let result = result.unwrap();
-    create_thumbnail_job(&image.id, &raw_img_data, image.owner);
+    // Skips the "data:image/png;base64," prefix
+    let mut raw_img_data = DataUrlReader::new(img_data.open(), 22);
+    let png = Format::Exactly("image/png");
+    let result = upload::store_blob(
+        &mut raw_img_data,
+        png,
+        BlobKind::Image,
+        auth.get_userid(),
+        &conn,
+        |blob| {
+            let image = HImage::new(iid,
+                    Some(title),
+                    blob.owner,
+                    blob.filepath.clone(),
+                    Some(blob.hash.clone()),
+                    Local::now().naive_utc(),
+                    exp.is_some(),
+                    exp);
+
+            diesel::insert_into(horus_images::table)
+                .values(&image)
+                .get_result::<HImage>(&*conn)
+        },
+    )?;
+
+    create_thumbnail_job(&result.id, &result.filepath, result.owner);
 
     Ok(status::Created(
         String::from("/image/") + result.id.as_str(),
@@ -204,22 +255,21 @@ fn new_img(
     ))
 }
 
-fn create_thumbnail_job(image_id: &str, image_data: &Vec<u8>, owner: i32)
+fn create_thumbnail_job(image_id: &str, image_path: &str, owner: i32)
 {
-    use models::NewJob, JobPriority;
-    // TODO
+    let thumbnail_data = CreateImageThumbnail::new(image_id.to_string(), image_path.to_string());
+    let new_job = NewJob::new(
+        owner,
+        CreateImageThumbnail::job_name(image_id),
+        Some(job_structures::binarize(&thumbnail_data)),
+        JobPriority::Normal,
+    );
 
-    let new_job = NewJob::new(owner,
-                              "thumbnail:image",
-                              Some(image_data),
-                              JobPriority::Normal);
     let queue_result = job_juggler::enqueue_job(new_job);
 
     if queue_result.is_err() {
         eprintln!("Could not enqueue thumbnail job: {}", queue_result.err().unwrap());
     }
-
-    queue_result.unwrap();
 }
 
 #[post("/new", format = "image/png", data = "<img_data>")]
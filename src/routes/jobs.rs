@@ -1,14 +1,30 @@
-use diesel::prelude::*;
-use rocket::response::Failure;
-use rocket::http::Status;
+use std::io::Cursor;
+
+use chrono::NaiveDateTime;
+use diesel::{self, prelude::*};
+use diesel::pg::PgConnection;
+use diesel::sql_types::Integer;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{status, Failure, Responder, Response};
+use rocket::http::{ContentType, Status};
+use rocket::Outcome;
 use rocket_contrib::Json;
+use serde_json::Value;
 
-use models::{HJob, JobStatus, User};
+use models::{HJob, JobEvent, JobPriority, JobStatus, NewJob, User};
+use models::job_structures::{self, Reconcile};
 use fields::{Authentication, PrivilegeLevel};
+use forms::ReconcileForm;
+use job_juggler;
 use schema::horus_jobs::dsl::*;
 use schema::horus_users::dsl::*;
 use DbConn;
 
+/// Milliseconds the client waits before asking for more job events.
+const EVENT_RETRY: u64 = 1000;
+/// The most events returned by one request for job events.
+const EVENT_BATCH: i64 = 100;
+
 #[derive(Serialize)]
 pub struct ListJob
 {
@@ -18,7 +34,71 @@ pub struct ListJob
     priority: i32,
 }
 
-// NONE OF THESE ARE IMPLEMENTED
+/// A job as listed to admins, across all users.
+#[derive(Serialize, Queryable)]
+pub struct AdminListJob
+{
+    id: i32,
+    owner: i32,
+    job_name: String,
+    job_status: i32,
+    priority: i32,
+    time_queued: NaiveDateTime,
+    attempts: i32,
+}
+
+#[derive(Serialize, Queryable)]
+pub struct JobDetail
+{
+    id: i32,
+    owner: i32,
+    job_name: String,
+    job_status: i32,
+    priority: i32,
+    time_queued: NaiveDateTime,
+    attempts: i32,
+    max_attempts: i32,
+    run_after: Option<NaiveDateTime>,
+    depends_on: Vec<i32>,
+    cancel_requested: bool,
+    progress: Option<i16>,
+    stage: Option<String>,
+    job_result: Option<Value>,
+    logs: Option<String>,
+}
+
+/// The state of a job as returned by `retrieve_job_state`.
+#[derive(Serialize, Queryable)]
+pub struct JobState
+{
+    job_status: i32,
+    progress: Option<i16>,
+    job_result: Option<Value>,
+}
+
+/// A job that overran its maximum runtime.
+#[derive(Serialize, Queryable)]
+pub struct StuckJob
+{
+    id: i32,
+    owner: i32,
+    job_name: String,
+    job_status: i32,
+    claimed_by: Option<String>,
+    heartbeat: Option<NaiveDateTime>,
+    deadline: Option<NaiveDateTime>,
+    timed_out: bool,
+}
+
+/// Filters for the admin job listing, eg. `?status=4&name=thumbnail`.
+/// `name` matches the start of the job name.
+#[derive(FromForm, Default)]
+pub struct JobFilter
+{
+    status: Option<i32>,
+    name: Option<String>,
+}
+
 #[get("/active/<uid>")]
 pub fn list_active_jobs(
     uid: i32,
@@ -39,8 +119,7 @@ pub fn list_active_jobs(
     let user = user.unwrap();
 
     let result = HJob::belonging_to(&user)
-        .filter(job_status.ne(JobStatus::Failed as i32))
-        .filter(job_status.ne(JobStatus::Complete as i32))
+        .filter(job_status.ne_all(JobStatus::finished()))
         .select((
             ::schema::horus_jobs::dsl::id,
             job_name,
@@ -123,13 +202,340 @@ pub fn retrieve_job_status(
 {
     let status = poll_job(job_id, auth.get_userid(), conn);
     match status {
+        None => Err(Failure(Status::NotFound)),
+        Some(v) => Ok(Json(v.job_status)),
+    }
+}
+
+/// Like `retrieve_job_status`, but along with the progress and result of the job.
+#[get("/state/<job_id>")]
+pub fn retrieve_job_state(
+    job_id: i32,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Json<JobState>, Failure>
+{
+    let state = poll_job(job_id, auth.get_userid(), conn);
+    match state {
         None => Err(Failure(Status::NotFound)),
         Some(v) => Ok(Json(v)),
     }
 }
 
-/// Poll a job's status. Returns `None` if error.
-fn poll_job(job_id: i32, owner_id: i32, conn: DbConn) -> Option<i32>
+/// Lists the jobs of every user, newest first. Admins only.
+#[get("/admin/<page>?<filter>")]
+pub fn admin_list_jobs(
+    page: u32,
+    filter: JobFilter,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Json<Vec<AdminListJob>>, Failure>
+{
+    if auth.get_privilege_level() == PrivilegeLevel::User {
+        return Err(Failure(Status::Unauthorized));
+    }
+
+    let mut query = horus_jobs
+        .select((
+            ::schema::horus_jobs::dsl::id,
+            owner,
+            job_name,
+            job_status,
+            priority,
+            time_queued,
+            attempts,
+        ))
+        .into_boxed();
+
+    if let Some(wanted_status) = filter.status {
+        query = query.filter(job_status.eq(wanted_status));
+    }
+
+    if let Some(name) = filter.name {
+        query = query.filter(job_name.like(format!("{}%", name)));
+    }
+
+    let result = query
+        .order(time_queued.desc())
+        .offset((page * 24) as i64)
+        .limit(24)
+        .get_results::<AdminListJob>(&*conn);
+
+    match result {
+        Ok(values) => Ok(Json(values)),
+        Err(_) => Err(Failure(Status::InternalServerError)),
+    }
+}
+
+#[get("/admin/<page>", rank = 2)]
+pub fn admin_list_jobs_unfiltered(
+    page: u32,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Json<Vec<AdminListJob>>, Failure>
+{
+    admin_list_jobs(page, JobFilter::default(), auth, conn)
+}
+
+/// Lists jobs that overran their maximum runtime, most overdue first. These are
+/// running jobs past their deadline and jobs that were failed for timing out, whose
+/// threads may still be stuck on the server. Admins only.
+#[get("/admin/stuck/<page>")]
+pub fn stuck_jobs(
+    page: u32,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Json<Vec<StuckJob>>, Failure>
+{
+    use diesel::dsl::now;
+
+    if auth.get_privilege_level() == PrivilegeLevel::User {
+        return Err(Failure(Status::Unauthorized));
+    }
+
+    let result = horus_jobs
+        .filter(
+            timed_out.eq(true).or(job_status
+                .eq(JobStatus::Running as i32)
+                .and(deadline.lt(now.nullable()))),
+        )
+        .select((
+            ::schema::horus_jobs::dsl::id,
+            owner,
+            job_name,
+            job_status,
+            claimed_by,
+            heartbeat,
+            deadline,
+            timed_out,
+        ))
+        .order(deadline.asc())
+        .offset((page * 24) as i64)
+        .limit(24)
+        .get_results::<StuckJob>(&*conn);
+
+    match result {
+        Ok(values) => Ok(Json(values)),
+        Err(_) => Err(Failure(Status::InternalServerError)),
+    }
+}
+
+/// Returns the job along with its logs.
+#[get("/<job_id>")]
+pub fn job_detail(
+    job_id: i32,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Json<JobDetail>, Failure>
+{
+    let job = horus_jobs
+        .find(job_id)
+        .select((
+            ::schema::horus_jobs::dsl::id,
+            owner,
+            job_name,
+            job_status,
+            priority,
+            time_queued,
+            attempts,
+            max_attempts,
+            run_after,
+            depends_on,
+            cancel_requested,
+            progress,
+            stage,
+            job_result,
+            logs,
+        ))
+        .first::<JobDetail>(&*conn);
+
+    if job.is_err() {
+        return Err(Failure(Status::NotFound));
+    }
+    let job = job.unwrap();
+
+    if auth.get_userid() != job.owner && auth.get_privilege_level() == PrivilegeLevel::User {
+        return Err(Failure(Status::Unauthorized));
+    }
+
+    Ok(Json(job))
+}
+
+/// Queues a reconciliation of storage with the database, see `Reconcile`. Returns
+/// HTTP accepted with the id of the job. Admins only.
+#[post("/admin/reconcile", format = "application/json", data = "<options>")]
+pub fn reconcile(
+    options: Json<ReconcileForm>,
+    auth: Authentication,
+) -> Result<status::Custom<String>, Failure>
+{
+    if auth.get_privilege_level() == PrivilegeLevel::User {
+        return Err(Failure(Status::Unauthorized));
+    }
+
+    let defaults = Reconcile::default();
+    let options = options.into_inner();
+    let reconcile_data = Reconcile {
+        orphans: options.orphans.unwrap_or(defaults.orphans),
+        delete_dangling: options.delete_dangling.unwrap_or(defaults.delete_dangling),
+        dry_run: options.dry_run.unwrap_or(defaults.dry_run),
+        min_age: options.min_age.unwrap_or(defaults.min_age),
+        log_data: String::new(),
+    };
+
+    let new_job = NewJob::new(
+        auth.get_userid(),
+        "maintenance:reconcile".to_string(),
+        Some(job_structures::binarize(&reconcile_data)),
+        JobPriority::System,
+    );
+
+    match job_juggler::enqueue_job(new_job) {
+        Ok(job_id) => Ok(status::Custom(Status::Accepted, job_id.to_string())),
+        Err(e) => {
+            eprintln!("Couldn't queue reconciliation: {}", e);
+            Err(Failure(Status::InternalServerError))
+        }
+    }
+}
+
+/// Cancels a job. Jobs that haven't started, or are waiting to be run again after
+/// being interrupted, are cancelled straight away (200 OK), running jobs are asked to stop and are cancelled once they do (202 Accepted).
+/// Jobs that have already finished can't be cancelled (409 Conflict).
+#[post("/cancel/<job_id>")]
+pub fn cancel_job(
+    job_id: i32,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<status::Custom<()>, Failure>
+{
+    let job_owner = horus_jobs
+        .find(job_id)
+        .select(owner)
+        .first::<i32>(&*conn);
+
+    if job_owner.is_err() {
+        return Err(Failure(Status::NotFound));
+    }
+
+    if auth.get_userid() != job_owner.unwrap()
+        && auth.get_privilege_level() == PrivilegeLevel::User
+    {
+        return Err(Failure(Status::Unauthorized));
+    }
+
+    match cancel(job_id, &*conn) {
+        Ok(Some(response_status)) => Ok(status::Custom(response_status, ())),
+        Ok(None) => Err(Failure(Status::Conflict)),
+        Err(_) => Err(Failure(Status::InternalServerError)),
+    }
+}
+
+/// Cancels the job if it hasn't started or was interrupted, otherwise flags it for
+/// cancellation if it is running. Returns the response status, or `None` if the job has finished.
+fn cancel(job_id: i32, conn: &PgConnection) -> QueryResult<Option<Status>>
+{
+    let cancel_sql = format!(
+        "UPDATE horus_jobs SET job_status = {cancelled}, claimed_by = NULL, heartbeat = NULL, \
+         logs = concat(logs, '\n---\nCancelled while waiting to run.') \
+         WHERE id = $1 AND job_status IN ({waiting}, {queued}, {interrupted})",
+        cancelled = JobStatus::Cancelled as i32,
+        waiting = JobStatus::Waiting as i32,
+        queued = JobStatus::Queued as i32,
+        interrupted = JobStatus::Interrupted as i32,
+    );
+
+    let cancelled = diesel::sql_query(cancel_sql)
+        .bind::<Integer, _>(job_id)
+        .execute(conn)?;
+
+    if cancelled > 0 {
+        return Ok(Some(Status::Ok));
+    }
+
+    // The juggler running the job stops it when the job next checks for cancellation.
+    let flagged = diesel::update(
+        horus_jobs
+            .find(job_id)
+            .filter(job_status.eq(JobStatus::Running as i32)),
+    ).set(cancel_requested.eq(true))
+        .execute(conn)?;
+
+    if flagged > 0 {
+        Ok(Some(Status::Accepted))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The id of the last event the client received, sent by `EventSource` when it reconnects.
+pub struct LastEventId(Option<i64>);
+
+/// A batch of job events in the `text/event-stream` format.
+pub struct EventStream
+{
+    events: Vec<JobEvent>,
+    finished: bool,
+}
+
+/// Streams the status changes, progress and log lines of a job as Server-Sent Events.
+/// Each request returns the events after `Last-Event-ID` right away, and `EventSource`
+/// reconnects by itself `EVENT_RETRY` milliseconds later. Waiting for events instead
+/// would hold a worker thread and a database connection for every follower. Once the
+/// job has finished and every event has been sent, this returns 204 No Content, which
+/// stops `EventSource` from reconnecting.
+#[get("/events/<job_id>")]
+pub fn job_events(
+    job_id: i32,
+    last_event: LastEventId,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<EventStream, Failure>
+{
+    use schema::horus_job_events;
+
+    let job_owner = horus_jobs
+        .find(job_id)
+        .select(owner)
+        .first::<i32>(&*conn);
+
+    if job_owner.is_err() {
+        return Err(Failure(Status::NotFound));
+    }
+
+    if auth.get_userid() != job_owner.unwrap()
+        && auth.get_privilege_level() == PrivilegeLevel::User
+    {
+        return Err(Failure(Status::Unauthorized));
+    }
+
+    let last_event_id = last_event.0.unwrap_or(0);
+
+    // The status is read first, so every event of a finished job is visible below.
+    let status = horus_jobs
+        .find(job_id)
+        .select(job_status)
+        .first::<i32>(&*conn);
+
+    let events = horus_job_events::table
+        .filter(horus_job_events::job_id.eq(job_id))
+        .filter(horus_job_events::id.gt(last_event_id))
+        .order(horus_job_events::id.asc())
+        .limit(EVENT_BATCH)
+        .get_results::<JobEvent>(&*conn);
+
+    if status.is_err() || events.is_err() {
+        return Err(Failure(Status::InternalServerError));
+    }
+
+    Ok(EventStream {
+        events: events.unwrap(),
+        finished: JobStatus::finished().contains(&status.unwrap()),
+    })
+}
+
+/// Poll a job's status, progress and result. Returns `None` if error.
+fn poll_job(job_id: i32, owner_id: i32, conn: DbConn) -> Option<JobState>
 {
     let user = horus_users.find(owner_id).first::<User>(&*conn);
 
@@ -140,8 +546,8 @@ fn poll_job(job_id: i32, owner_id: i32, conn: DbConn) -> Option<i32>
     let user = user.unwrap();
     let result = HJob::belonging_to(&user)
         .find(job_id)
-        .select(job_status)
-        .first::<i32>(&*conn);
+        .select((job_status, progress, job_result))
+        .first::<JobState>(&*conn);
 
     if result.is_err() {
         None
@@ -149,3 +555,46 @@ fn poll_job(job_id: i32, owner_id: i32, conn: DbConn) -> Option<i32>
         Some(result.unwrap())
     }
 }
+
+impl<'a, 'r> FromRequest<'a, 'r> for LastEventId
+{
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<LastEventId, Self::Error>
+    {
+        let last_id = request
+            .headers()
+            .get_one("Last-Event-ID")
+            .and_then(|v| v.trim().parse::<i64>().ok());
+
+        Outcome::Success(LastEventId(last_id))
+    }
+}
+
+impl Responder<'static> for EventStream
+{
+    fn respond_to(self, _: &Request) -> Result<Response<'static>, Status>
+    {
+        if self.events.is_empty() && self.finished {
+            return Response::build().status(Status::NoContent).ok();
+        }
+
+        let mut body = format!("retry: {}\n\n", EVENT_RETRY);
+        for event in self.events {
+            body += &format!("id: {}\nevent: {}\n", event.id, event.event_type);
+            // Every line of the data needs its own field.
+            for line in event.event_data.split('\n') {
+                body += "data: ";
+                body += line.trim_right_matches('\r');
+                body += "\n";
+            }
+            body += "\n";
+        }
+
+        Response::build()
+            .header(ContentType::new("text", "event-stream"))
+            .raw_header("Cache-Control", "no-cache")
+            .sized_body(Cursor::new(body))
+            .ok()
+    }
+}
@@ -8,10 +8,12 @@ use rocket_contrib::Template;
 use DbConn;
 use models::{AuthToken, HFile, HImage, HPaste, HVideo, LicenseKey, SessionToken, User};
 use fields::Authentication;
-use contexts::{FileList, ImageList, PasteList, VideoList};
+use contexts::{FileList, ImageList, ListItem, PasteList, VideoList};
 use contexts::{ManageImage, ManagePaste, ManageVideo, ManageFile};
 use contexts::ShowAccount;
+use models::traits::expirable::Expirable;
 use schema;
+use dbtools::storage;
 use errors::AuthTokenError;
 
 #[derive(FromForm)]
@@ -162,7 +164,11 @@ pub fn my_images(page: u32, auth: Authentication, conn: DbConn) -> Option<Templa
         .get_results::<HImage>(&*conn);
 
     let images = images.unwrap().iter().map(move |img|{
-        img.with_displayable_date()
+        ListItem {
+            item: img.with_displayable_date(),
+            expired: img.is_expired(),
+            src: None,
+        }
     }).collect();
 
     let name = horus_users
@@ -201,7 +207,13 @@ pub fn my_files(page: u32, auth: Authentication, conn: DbConn) -> Option<Templat
         return None;
     }
 
-    let files = files.unwrap();
+    let files = files.unwrap().into_iter().map(|file| {
+        ListItem {
+            expired: file.is_expired(),
+            item: file,
+            src: None,
+        }
+    }).collect();
 
     let name = horus_users
         .find(auth.get_userid())
@@ -239,7 +251,13 @@ pub fn my_pastes(page: u32, auth: Authentication, conn: DbConn) -> Option<Templa
         return None;
     }
 
-    let pastes = pastes.unwrap();
+    let pastes = pastes.unwrap().into_iter().map(|paste| {
+        ListItem {
+            expired: paste.is_expired(),
+            item: paste,
+            src: None,
+        }
+    }).collect();
 
     let name = horus_users
         .find(auth.get_userid())
@@ -273,7 +291,13 @@ pub fn my_videos(page: u32, auth: Authentication, conn: DbConn) -> Option<Templa
         .offset((page * 24) as i64)
         .get_results::<HVideo>(&*conn);
 
-    let videos = videos.unwrap();
+    let videos = videos.unwrap().into_iter().map(|video| {
+        ListItem {
+            expired: video.is_expired(),
+            src: Some(storage::backend().public_url(&video.filepath)),
+            item: video,
+        }
+    }).collect();
     let name = horus_users
         .find(auth.get_userid())
         .get_result::<User>(&*conn)
@@ -293,18 +317,18 @@ pub fn my_videos(page: u32, auth: Authentication, conn: DbConn) -> Option<Templa
 }
 
 #[get("/video/<video_id>")]
-pub fn video(video_id: String, conn: DbConn, auth: Authentication) -> Option<Template>
+pub fn video(video_id: String, conn: DbConn, auth: Authentication) -> Result<Template, Failure>
 {
     use schema::horus_videos::dsl::*;
     let video = horus_videos.find(video_id).get_result::<HVideo>(&*conn);
 
     if video.is_err() {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
     let video = video.unwrap();
 
     if auth.get_userid() != video.owner {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
 
     let mut ititle = String::new();
@@ -315,39 +339,46 @@ pub fn video(video_id: String, conn: DbConn, auth: Authentication) -> Option<Tem
     }
 
     let path = if video.password == None {
-        None
+        storage::backend().public_url(&video.filepath)
     } else {
-        Some(::dbtools::s3::get_s3_presigned_url(video.filepath).unwrap())
+        storage::signed_url(&video.filepath).map_err(|e| {
+            eprintln!("Couldn't sign the URL of a video: {}", e);
+            Failure(Status::ServiceUnavailable)
+        })?
     };
 
     let context = ManageVideo {
+        expired: video.is_expired(),
         id: video.id,
         title: ititle.clone(),
         page_title: ititle,
         is_expiry: video.is_expiry,
+        expiration_time: video.expiration_time,
         date_added: format!("{}", video.date_added),
         editable: true,
         password: video.password,
-        vid_src: path
+        vid_src: path,
     };
 
-    Some(Template::render("manage_video", &context))
+    Ok(Template::render("manage_video", &context))
 }
 
 #[get("/image/<image_id>")]
-pub fn image(image_id: String, conn: DbConn, auth: Authentication) -> Option<Template>
+pub fn image(image_id: String, conn: DbConn, auth: Authentication) -> Result<Template, Failure>
 {
     use schema::horus_images::dsl::*;
     let image = horus_images.find(image_id).get_result::<HImage>(&*conn);
 
     if image.is_err() {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
 
-    let mut image = image.unwrap().with_displayable_date();
+    let image = image.unwrap();
+    let expired = image.is_expired();
+    let mut image = image.with_displayable_date();
 
     if auth.get_userid() != image.owner {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
 
     if image.title.is_none() {
@@ -355,9 +386,12 @@ pub fn image(image_id: String, conn: DbConn, auth: Authentication) -> Option<Tem
     }
 
     let path = if image.password == None {
-        None
+        storage::backend().public_url(&image.filepath)
     } else {
-        Some(::dbtools::s3::get_s3_presigned_url(image.filepath).unwrap())
+        storage::signed_url(&image.filepath).map_err(|e| {
+            eprintln!("Couldn't sign the URL of an image: {}", e);
+            Failure(Status::ServiceUnavailable)
+        })?
     };
 
     let context = ManageImage {
@@ -365,13 +399,15 @@ pub fn image(image_id: String, conn: DbConn, auth: Authentication) -> Option<Tem
         title: image.title.clone().unwrap(),
         page_title: image.title.clone().unwrap(),
         is_expiry: image.is_expiry,
+        expiration_time: image.expiration_time,
+        expired: expired,
         date_added: image.date_added,
         password: image.password,
         img_src: path,
         editable: true,
     };
 
-    Some(Template::render("manage_image", &context))
+    Ok(Template::render("manage_image", &context))
 }
 
 #[get("/file/<file_id>")]
@@ -394,6 +430,8 @@ pub fn file(file_id: String, conn: DbConn, auth: Authentication) -> Option<Templ
         filename: file.filename.clone(),
         page_title: file.filename.clone(),
         is_expiry: file.is_expiry,
+        expiration_time: file.expiration_time,
+        expired: file.is_expired(),
         date_added: format!("{}", file.date_added.format("%d %b %Y\nat %H:%M")),
         password: file.password.clone(),
         editable: false
@@ -426,6 +464,7 @@ pub fn paste(paste_id: String, conn: DbConn, auth: Authentication) -> Option<Tem
         id: paste.id.clone(),
         title: paste_title.clone(),
         page_title: paste_title,
+        expired: paste.is_expired(),
         paste: paste,
         editable: true,
     };
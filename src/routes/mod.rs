@@ -12,6 +12,8 @@ pub mod key;
 pub mod dist;
 pub mod http_errors;
 pub mod password;
+pub mod storage;
+pub mod upload;
 
 pub mod meta
 {
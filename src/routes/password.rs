@@ -7,9 +7,11 @@ use rocket::response::{Failure, status};
 use rocket::http::Status;
 use rocket::Outcome;
 
-use dbtools::s3;
+use dbtools::blobs;
+use dbtools::storage::{self, Visibility};
 use fields::Authentication;
 use models::traits::passwordable::Passwordable;
+use routes::http_errors::unexpired;
 use DbConn;
 
 /// The list of types that can be passworded derived from a request header
@@ -31,12 +33,15 @@ pub fn check(
     let resource = get_passwordable_resource_by_id(res_id, res_type, &*conn);
 
     let resource = match resource {
-        Some(r) => r,
+        Some(r) => unexpired(r)?,
         _       => return Err(Failure(Status::NotFound))
     };
 
     if resource.check_password(submitted_password, &*conn) {
-        let signed_location = s3::get_s3_presigned_url(resource.get_s3_location());
+        let signed_location = match resource.get_download_name() {
+            Some(name) => storage::download_url(&resource.get_s3_location(), &name),
+            None => storage::signed_url(&resource.get_s3_location()),
+        };
 
         match signed_location {
             Ok(link) => Ok(status::Custom(Status::Ok, link)),
@@ -78,17 +83,33 @@ pub fn set(
         return Err(Failure(Status::InternalServerError));
     }
 
-    let s3_result = if submitted_password == None {
-        s3::publicize_s3_resource(&resource.get_s3_location())
+    let visibility = if submitted_password == None {
+        Visibility::Public
     } else {
-        s3::privatize_s3_resource(&resource.get_s3_location())
+        Visibility::Private
+    };
+
+    // Blobs can be shared by resources of the owner, they stay public while any of them is.
+    let stored_visibility = match resource.get_blob() {
+        Some((hash, kind)) => blobs::visibility(&hash, kind, resource.owner(), &*conn).map_err(|e| {
+            eprintln!("Error checking references of blob {}: {}", hash, e);
+            Failure(Status::InternalServerError)
+        })?,
+        None => visibility,
     };
 
+    let mut locations = vec![(resource.get_s3_location(), stored_visibility)];
+    if let Some(thumbnail) = resource.get_s3_thumbnail_location() {
+        locations.push((thumbnail, visibility));
+    }
 
-    match s3_result {
-        Ok(()) => Ok(status::Accepted(None)),
-        Err(_) => Err(Failure(Status::InternalServerError))
+    for (location, visibility) in locations {
+        if storage::backend().set_visibility(&location, visibility).is_err() {
+            return Err(Failure(Status::InternalServerError));
+        }
     }
+
+    Ok(status::Accepted(None))
 }
 
 fn get_passwordable_resource_by_id(
@@ -9,18 +9,19 @@ use {contexts, conv};
 use fields::Authentication;
 use models::HPaste;
 use forms::{HNewPasteForm, HPasteChangesetForm};
+use routes::http_errors::unexpired;
 use schema::horus_pastes::dsl::*;
 
 #[get("/<paste_id>")]
-pub fn show(paste_id: String, conn: DbConn) -> Option<Template>
+pub fn show(paste_id: String, conn: DbConn) -> Result<Template, Failure>
 {
     let paste = horus_pastes.find(paste_id).first::<HPaste>(&*conn);
 
     if paste.is_err() {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
 
-    let paste = paste.unwrap();
+    let paste = unexpired(paste.unwrap())?;
     let mut metatag = String::from("<meta property=\"og:type\" content=\"article\" />");
     metatag += "<meta property=\"article:published_time\" content=\"";
     metatag += format!("{}", &paste.date_added).as_str();
@@ -31,7 +32,7 @@ pub fn show(paste_id: String, conn: DbConn) -> Option<Template>
         meta_tag: Some(metatag),
     };
 
-    Some(Template::render("show_paste", &context))
+    Ok(Template::render("show_paste", &context))
 }
 
 #[get("/<uid>/list/<page>")]
@@ -0,0 +1,106 @@
+use std::path::PathBuf;
+
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use rocket::request::Request;
+use rocket::response::{NamedFile, Responder, Response};
+use rocket::http::{ContentType, Status};
+
+use DbConn;
+use dbtools::mime;
+use dbtools::storage::{self, UrlSignature};
+
+/// The query string of a signed URL, see `StorageBackend::signed_url` and
+/// `StorageBackend::download_url`.
+#[derive(FromForm)]
+pub struct SignedQuery
+{
+    expires: i64,
+    signature: String,
+    filename: Option<String>,
+}
+
+/// An object of the local storage backend. It's sent as an attachment if it's
+/// downloaded under a name, or isn't of a type browsers may show (see
+/// `mime::is_inline`).
+pub struct StoredFile
+{
+    file: NamedFile,
+    /// The type of a blob, see `Blob::content_type`. Other objects go by their
+    /// extension.
+    content_type: Option<String>,
+    filename: Option<String>,
+}
+
+/// Serves an object of the local storage backend through a signed URL.
+#[get("/<path..>?<query>")]
+pub fn signed(path: PathBuf, query: SignedQuery, conn: DbConn) -> Option<StoredFile>
+{
+    let path = path.to_str()?;
+    let signature = UrlSignature {
+        expires: query.expires,
+        signature: &query.signature,
+        filename: query.filename.as_ref().map(|f| f.as_str()),
+    };
+    let file = storage::backend().local_file(path, Some(&signature))?;
+
+    Some(StoredFile {
+        file: NamedFile::open(file).ok()?,
+        content_type: blob_type(path, &conn),
+        filename: query.filename.clone(),
+    })
+}
+
+/// Serves a public object of the local storage backend. Private objects and
+/// objects of other backends aren't found.
+#[get("/<path..>", rank = 2)]
+pub fn public(path: PathBuf, conn: DbConn) -> Option<StoredFile>
+{
+    let path = path.to_str()?;
+    let file = storage::backend().local_file(path, None)?;
+
+    Some(StoredFile {
+        file: NamedFile::open(file).ok()?,
+        content_type: blob_type(path, &conn),
+        filename: None,
+    })
+}
+
+/// The type the blob stored at `path` was detected as, if it's a blob.
+fn blob_type(path: &str, conn: &PgConnection) -> Option<String>
+{
+    use schema::horus_blobs;
+
+    horus_blobs::table
+        .filter(horus_blobs::filepath.eq(path))
+        .select(horus_blobs::content_type)
+        .first::<String>(conn)
+        .ok()
+}
+
+impl Responder<'static> for StoredFile
+{
+    fn respond_to(self, request: &Request) -> Result<Response<'static>, Status>
+    {
+        let extension_type = self.file
+            .path()
+            .extension()
+            .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy().to_lowercase()))
+            .map(|content_type| content_type.to_string());
+        let content_type = self.content_type.or(extension_type);
+
+        let disposition = match (self.filename, content_type.as_ref()) {
+            (Some(ref filename), _) => storage::attachment(filename),
+            (None, Some(content_type)) if mime::is_inline(content_type) => "inline".to_string(),
+            (None, _) => "attachment".to_string(),
+        };
+
+        let mut response = self.file.respond_to(request)?;
+        if let Some(parsed) = content_type.and_then(|c| ContentType::parse_flexible(&c)) {
+            response.set_header(parsed);
+        }
+        response.set_raw_header("Content-Disposition", disposition);
+        response.set_raw_header("X-Content-Type-Options", "nosniff");
+        Ok(response)
+    }
+}
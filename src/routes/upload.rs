@@ -0,0 +1,638 @@
+extern crate base64;
+extern crate libc;
+
+use std::cmp;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+
+use chrono::{Local, NaiveDateTime};
+use diesel::{self, prelude::*};
+use diesel::pg::PgConnection;
+use rocket::data::Data;
+use rocket::http::Status;
+use rocket::response::{Failure, Response};
+
+use DbConn;
+use conv;
+use dbtools;
+use dbtools::blobs::{self, BlobError};
+use dbtools::mime;
+use dbtools::storage::{self, UploadError, Visibility};
+use fields::{Authentication, FileName, UploadLength, UploadOffset};
+use models::{Blob, BlobKind, DeploymentKey, HFile, UploadSession, UploadType};
+use routes::{dist, files};
+use schema::horus_upload_sessions;
+
+/// Base64 is decoded in chunks of this many characters.
+const BASE64_CHUNK: usize = 64 * 1024;
+/// The version of the tus protocol (https://tus.io/protocols/resumable-upload.html)
+/// resumable uploads follow. Commits are an addition to it, uploads aren't turned
+/// into anything before.
+const TUS_VERSION: &'static str = "1.0.0";
+
+/// What the content of an upload has to be.
+pub enum Format<'a>
+{
+    /// The type the route declares, uploads of anything else are rejected.
+    Exactly(&'static str),
+    /// Anything, the filename helps detecting what if it's given.
+    Any(Option<&'a str>),
+}
+
+impl<'a> Format<'a>
+{
+    /// Detects the type of an upload from its first bytes, failing with
+    /// `415 Unsupported Media Type` if it isn't the one required.
+    fn detect(&self, head: &[u8]) -> Result<String, Failure>
+    {
+        match *self {
+            Format::Exactly(required) => match mime::sniff(head) {
+                Some(detected) if detected == required => Ok(detected.to_string()),
+                detected => {
+                    eprintln!("Rejected upload of {:?} as {}", detected, required);
+                    Err(Failure(Status::UnsupportedMediaType))
+                }
+            },
+            Format::Any(filename) => Ok(mime::detect(head, filename)),
+        }
+    }
+}
+
+/// Streams an uploaded body to storage at `path`. Bodies that can't be read to
+/// the end, eg. because the connection broke, fail with `400 Bad Request`, and
+/// bodies that can't be stored with `503 Service Unavailable`.
+pub fn store(
+    path: &str,
+    data: &mut dyn Read,
+    visibility: Visibility,
+    filename: Option<&str>,
+) -> Result<u64, Failure>
+{
+    let head = mime::peek(data).map_err(|e| upload_failure(path, UploadError::Read(e)))?;
+    let content_type = Format::Any(filename).detect(&head)?;
+
+    storage::backend()
+        .put_stream(
+            path,
+            &mut io::Cursor::new(head).chain(data),
+            &content_type,
+            visibility,
+            filename,
+        )
+        .map_err(|e| upload_failure(path, e))
+}
+
+/// Stores an uploaded body of `format` as a blob of `kind` and `owner`, unless they
+/// uploaded the same content as that kind before, and inserts the resource that
+/// references the blob with `reference`. Fails like `store` and `Format::detect`, or with
+/// `500 Internal Server Error` if the resource can't be inserted.
+pub fn store_blob<T, F>(
+    data: &mut dyn Read,
+    format: Format,
+    kind: BlobKind,
+    owner: i32,
+    conn: &PgConnection,
+    reference: F,
+) -> Result<T, Failure>
+where
+    F: FnOnce(&Blob) -> QueryResult<T>,
+{
+    let spooled = blobs::spool(data).map_err(|e| upload_failure("the spool", e))?;
+    let content_type = format.detect(&spooled.head)?;
+
+    match blobs::store(&spooled, kind, owner, &content_type, conn, reference) {
+        Ok(result) => Ok(result),
+        Err(BlobError::Upload(e)) => Err(upload_failure(&spooled.hash, e)),
+        Err(BlobError::Storage(e)) => {
+            eprintln!("Couldn't store blob {}: {}", spooled.hash, e);
+            Err(Failure(Status::ServiceUnavailable))
+        }
+        Err(e) => {
+            eprintln!("Couldn't store blob {}: {}", spooled.hash, e);
+            Err(Failure(Status::InternalServerError))
+        }
+    }
+}
+
+/// Releases the blob of a deleted resource. Failing to is only logged, the expiry
+/// reaper releases blobs nothing references.
+pub fn release_blob(blob_hash: &str, kind: BlobKind, owner: i32, conn: &PgConnection)
+{
+    if let Err(e) = blobs::release(blob_hash, kind, owner, conn) {
+        eprintln!("Couldn't release blob {}: {}", blob_hash, e);
+    }
+}
+
+fn upload_failure(destination: &str, error: UploadError) -> Failure
+{
+    match error {
+        UploadError::Read(e) => {
+            eprintln!("Couldn't read the upload to {}: {}", destination, e);
+            Failure(Status::BadRequest)
+        }
+        UploadError::Store(e) => {
+            eprintln!("Couldn't store the upload to {}: {}", destination, e);
+            Failure(Status::ServiceUnavailable)
+        }
+    }
+}
+
+/// Decodes a base64 data URL (eg. `data:image/png;base64,...`) as it's read,
+/// so uploads sent as one don't have to be decoded in memory. Invalid base64
+/// is reported as an `InvalidData` read error.
+pub struct DataUrlReader<R: Read>
+{
+    inner: R,
+    /// The length of the `data:<type>;base64,` prefix that's still to be skipped.
+    prefix_len: usize,
+    /// Base64 that was read but not decoded yet, never more than 3 characters
+    /// between reads.
+    encoded: Vec<u8>,
+    decoded: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> DataUrlReader<R>
+{
+    pub fn new(inner: R, prefix_len: usize) -> Self
+    {
+        DataUrlReader {
+            inner: inner,
+            prefix_len: prefix_len,
+            encoded: Vec::new(),
+            decoded: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+
+    /// Reads and decodes the next chunk of base64.
+    fn fill(&mut self) -> io::Result<()>
+    {
+        if self.prefix_len > 0 {
+            let mut prefix = vec![0; self.prefix_len];
+            self.inner.read_exact(&mut prefix)?;
+            self.prefix_len = 0;
+        }
+
+        let mut chunk = vec![0; BASE64_CHUNK];
+        let read = loop {
+            match self.inner.read(&mut chunk) {
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+
+        self.encoded.extend_from_slice(&chunk[..read]);
+        self.finished = read == 0;
+
+        // Only whole groups of 4 characters can be decoded until the end.
+        let decodable = if self.finished {
+            self.encoded.len()
+        } else {
+            self.encoded.len() / 4 * 4
+        };
+
+        self.decoded = base64::decode(&self.encoded[..decodable])
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        self.encoded.drain(..decodable);
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DataUrlReader<R>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        while self.position == self.decoded.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+
+        let count = cmp::min(buf.len(), self.decoded.len() - self.position);
+        buf[..count].copy_from_slice(&self.decoded[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+// Resumable uploads: a session is started with the `Upload-Length` of the whole
+// upload, its data is sent with PATCH requests that continue from the
+// `Upload-Offset` the session is at, which HEAD returns after a broken connection.
+// Once all of it was sent, committing the session creates the file or deployment.
+
+/// Starts a resumable upload of a file, like `files::new`.
+#[post("/file")]
+pub fn new_file(
+    length: UploadLength,
+    file_name: FileName,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Response<'static>, Failure>
+{
+    new_file_session(length, file_name, None, auth, conn)
+}
+
+/// Starts a resumable upload of a file that expires, like `files::new_exp`.
+#[post("/file/<expt>/<expd>")]
+pub fn new_file_exp(
+    expt: String,
+    expd: usize,
+    length: UploadLength,
+    file_name: FileName,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Response<'static>, Failure>
+{
+    let exp = conv::get_dt_from_duration(expt, expd as isize)
+        .map_err(|_| Failure(Status::BadRequest))?;
+    new_file_session(length, file_name, Some(exp), auth, conn)
+}
+
+fn new_file_session(
+    length: UploadLength,
+    file_name: FileName,
+    exp: Option<NaiveDateTime>,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Response<'static>, Failure>
+{
+    let session = UploadSession::for_file(
+        dbtools::get_random_char_id(32),
+        auth.get_userid(),
+        length.0,
+        file_name.0,
+        exp,
+    );
+
+    start_session(session, &conn)
+}
+
+/// Starts a resumable upload of a deployment package, like `dist::deploy`.
+#[post("/deploy/<platform>/<version>")]
+pub fn new_deployment(
+    platform: String,
+    version: String,
+    length: UploadLength,
+    depkey: DeploymentKey,
+    conn: DbConn,
+) -> Result<Response<'static>, Failure>
+{
+    if !dist::is_valid_version(&version) {
+        return Err(Failure(Status::BadRequest));
+    }
+
+    // Checked again when committing, but there's no use in uploading it before then.
+    if dist::version_exists(&version, &platform, &conn)? {
+        return Err(Failure(Status::Conflict));
+    }
+
+    let session = UploadSession::for_deployment(
+        dbtools::get_random_char_id(32),
+        depkey.get_owner(),
+        length.0,
+        version,
+        platform,
+        depkey.hash(),
+    );
+
+    start_session(session, &conn)
+}
+
+/// Returns how much of an upload was received in `Upload-Offset`.
+#[head("/<upload_id>")]
+pub fn status(
+    upload_id: String,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Response<'static>, Failure>
+{
+    let session = find_session(&upload_id, &auth, &conn)?;
+
+    Ok(Response::build()
+        .status(Status::Ok)
+        .raw_header("Upload-Offset", session.upload_offset.to_string())
+        .raw_header("Upload-Length", session.upload_length.to_string())
+        .raw_header("Cache-Control", "no-store")
+        .raw_header("Tus-Resumable", TUS_VERSION)
+        .finalize())
+}
+
+/// Continues an upload from `Upload-Offset`, which has to be where it's at.
+/// Whatever was received is kept if the connection breaks. Requests continuing
+/// an upload another request is still writing to fail with `409 Conflict`.
+#[patch("/<upload_id>", format = "application/offset+octet-stream", data = "<chunk>")]
+pub fn append(
+    upload_id: String,
+    offset: UploadOffset,
+    chunk: Data,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Response<'static>, Failure>
+{
+    let session = find_session(&upload_id, &auth, &conn)?;
+    let partial = dbtools::get_path_partial_upload(&session.id);
+    let mut file = OpenOptions::new().write(true).open(&partial).map_err(|e| {
+        eprintln!("Couldn't open {}: {}", partial.display(), e);
+        Failure(Status::ServiceUnavailable)
+    })?;
+
+    // The partial upload stays locked while the chunk is written, so two requests
+    // continuing from the same offset can't both write to it. The session is read
+    // again once it's locked, as the offset may have moved in the meantime.
+    lock_partial(&file, &session.id)?;
+    let session = find_session(&upload_id, &auth, &conn)?;
+    let (new_offset, read_error) = append_locked(&session, offset.0, &mut file, chunk, &conn)?;
+
+    if let Some(e) = read_error {
+        eprintln!("Upload {} was cut off at {}: {}", upload_id, new_offset, e);
+        return Err(Failure(Status::BadRequest));
+    }
+
+    Ok(Response::build()
+        .status(Status::NoContent)
+        .raw_header("Upload-Offset", new_offset.to_string())
+        .raw_header("Tus-Resumable", TUS_VERSION)
+        .finalize())
+}
+
+/// Writes `chunk` to the locked partial upload at `offset`, which is checked again
+/// now that no other request can move it. Returns the new offset, and the error
+/// reading the chunk if it was cut off, in which case what was read is kept.
+fn append_locked(
+    session: &UploadSession,
+    offset: i64,
+    file: &mut File,
+    chunk: Data,
+    conn: &PgConnection,
+) -> Result<(i64, Option<io::Error>), Failure>
+{
+    if offset != session.upload_offset {
+        return Err(Failure(Status::Conflict));
+    }
+
+    file.seek(SeekFrom::Start(offset as u64)).map_err(|e| {
+        eprintln!("Couldn't seek in upload {}: {}", session.id, e);
+        Failure(Status::ServiceUnavailable)
+    })?;
+
+    // One byte more than fits is read to notice uploads that are too long.
+    let remaining = (session.upload_length - session.upload_offset) as u64;
+    let (written, read_error) = write_chunk(file, &mut chunk.open().take(remaining + 1))?;
+
+    if written > remaining {
+        let _ = file.set_len(offset as u64);
+        return Err(Failure(Status::PayloadTooLarge));
+    }
+
+    // Only moved from where the chunk was written at. The session is gone if it was
+    // committed, abandoned or reaped in the meantime.
+    let new_offset = offset + written as i64;
+    let target = horus_upload_sessions::table
+        .find(&session.id)
+        .filter(horus_upload_sessions::upload_offset.eq(offset));
+    let updated = diesel::update(target)
+        .set((
+            horus_upload_sessions::upload_offset.eq(new_offset),
+            horus_upload_sessions::last_activity.eq(Local::now().naive_utc()),
+        ))
+        .execute(conn);
+
+    match updated {
+        Ok(0) => Err(Failure(Status::NotFound)),
+        Ok(_) => Ok((new_offset, read_error)),
+        Err(e) => {
+            eprintln!("Couldn't update upload {}: {}", session.id, e);
+            Err(Failure(Status::InternalServerError))
+        }
+    }
+}
+
+/// Turns a completely received upload into what it was started for. Files are
+/// created like `files::new` and deployments are queued like `dist::deploy`,
+/// answering the same way they do.
+#[post("/<upload_id>/commit")]
+pub fn commit(
+    upload_id: String,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Response<'static>, Failure>
+{
+    let session = find_session(&upload_id, &auth, &conn)?;
+
+    if !session.is_complete() {
+        return Err(Failure(Status::Conflict));
+    }
+
+    let partial = dbtools::get_path_partial_upload(&session.id);
+    let mut data = File::open(&partial).map_err(|e| {
+        eprintln!("Couldn't open {}: {}", partial.display(), e);
+        Failure(Status::ServiceUnavailable)
+    })?;
+
+    // The session is deleted before creating the result, so an upload is only
+    // committed once. Storing the result takes a while, so nothing is kept locked
+    // meanwhile; the session is put back if it fails, to be committed again.
+    let deleted = diesel::delete(horus_upload_sessions::table.find(&session.id))
+        .execute(&*conn)
+        .map_err(|e| {
+            eprintln!("Couldn't commit upload {}: {}", session.id, e);
+            Failure(Status::InternalServerError)
+        })?;
+
+    if deleted == 0 {
+        return Err(Failure(Status::Conflict));
+    }
+
+    let committed = create_from_session(&session, &mut data, &conn);
+    if committed.is_err() {
+        let restored = diesel::insert_into(horus_upload_sessions::table)
+            .values(&session)
+            .execute(&*conn);
+
+        if let Err(e) = restored {
+            eprintln!("Couldn't restore upload {}: {}", session.id, e);
+        }
+    }
+
+    let response = match committed? {
+        Committed::File(hfile) => Response::build()
+            .status(Status::Created)
+            .raw_header("Location", String::from("/file/") + hfile.id.as_str())
+            .finalize(),
+        Committed::Deployment(job_id) => Response::build()
+            .status(Status::Accepted)
+            .sized_body(io::Cursor::new(job_id.to_string()))
+            .finalize(),
+    };
+
+    if let Err(e) = fs::remove_file(&partial) {
+        eprintln!("Couldn't remove {}: {}", partial.display(), e);
+    }
+
+    Ok(response)
+}
+
+/// Abandons an upload.
+#[delete("/<upload_id>")]
+pub fn terminate(
+    upload_id: String,
+    auth: Authentication,
+    conn: DbConn,
+) -> Result<Response<'static>, Failure>
+{
+    let session = find_session(&upload_id, &auth, &conn)?;
+    let deleted = diesel::delete(horus_upload_sessions::table.find(&session.id)).execute(&*conn);
+
+    if let Err(e) = deleted {
+        eprintln!("Couldn't delete upload {}: {}", session.id, e);
+        return Err(Failure(Status::InternalServerError));
+    }
+
+    let partial = dbtools::get_path_partial_upload(&session.id);
+    if let Err(e) = fs::remove_file(&partial) {
+        eprintln!("Couldn't remove {}: {}", partial.display(), e);
+    }
+
+    Ok(Response::build()
+        .status(Status::NoContent)
+        .raw_header("Tus-Resumable", TUS_VERSION)
+        .finalize())
+}
+
+/// What a committed upload became.
+enum Committed
+{
+    File(HFile),
+    Deployment(i32), // the id of the deployment job
+}
+
+/// Makes room for the data of a new session and records it.
+fn start_session(session: UploadSession, conn: &PgConnection) -> Result<Response<'static>, Failure>
+{
+    let partial = dbtools::get_path_partial_upload(&session.id);
+    let created = match partial.parent() {
+        Some(dir) => fs::create_dir_all(dir).and_then(|_| File::create(&partial)),
+        None => File::create(&partial),
+    };
+
+    if let Err(e) = created {
+        eprintln!("Couldn't create {}: {}", partial.display(), e);
+        return Err(Failure(Status::ServiceUnavailable));
+    }
+
+    let inserted = diesel::insert_into(horus_upload_sessions::table)
+        .values(&session)
+        .execute(conn);
+
+    if let Err(e) = inserted {
+        eprintln!("Couldn't start upload: {}", e);
+        let _ = fs::remove_file(&partial);
+        return Err(Failure(Status::InternalServerError));
+    }
+
+    Ok(Response::build()
+        .status(Status::Created)
+        .raw_header("Location", String::from("/upload/") + session.id.as_str())
+        .raw_header("Tus-Resumable", TUS_VERSION)
+        .finalize())
+}
+
+/// Finds an upload of the authenticated user. Fails with `421 Misdirected Request`
+/// if it was started on another host.
+fn find_session(
+    upload_id: &str,
+    auth: &Authentication,
+    conn: &PgConnection,
+) -> Result<UploadSession, Failure>
+{
+    let session = horus_upload_sessions::table
+        .find(upload_id)
+        .get_result::<UploadSession>(conn)
+        .map_err(|_| Failure(Status::NotFound))?;
+
+    if session.owner != auth.get_userid() {
+        return Err(Failure(Status::Unauthorized));
+    }
+
+    // What was sent so far is on the disk of the host that started the upload.
+    if !session.is_local() {
+        return Err(Failure(Status::MisdirectedRequest));
+    }
+
+    Ok(session)
+}
+
+/// Locks the partial upload in `file` until it's closed. Fails with `409 Conflict`
+/// if another request has it locked.
+fn lock_partial(file: &File, upload_id: &str) -> Result<(), Failure>
+{
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(());
+    }
+
+    let error = io::Error::last_os_error();
+    if error.kind() == ErrorKind::WouldBlock {
+        return Err(Failure(Status::Conflict));
+    }
+
+    eprintln!("Couldn't lock upload {}: {}", upload_id, error);
+    Err(Failure(Status::ServiceUnavailable))
+}
+
+fn create_from_session(
+    session: &UploadSession,
+    data: &mut dyn Read,
+    conn: &PgConnection,
+) -> Result<Committed, Failure>
+{
+    match session.upload_type() {
+        Some(UploadType::File) => files::create_file(
+            data,
+            FileName(session.filename.clone().unwrap_or_default()),
+            session.expiration_time,
+            session.owner,
+            conn,
+        ).map(Committed::File),
+        Some(UploadType::Deployment) => dist::queue_deployment(
+            data,
+            Some(session.upload_length as u64),
+            session.version_string.clone().unwrap_or_default(),
+            session.platform_string.clone().unwrap_or_default(),
+            session.deployment_key.clone().unwrap_or_default(),
+            session.owner,
+        ).map(Committed::Deployment),
+        None => {
+            eprintln!("Upload {} has unknown type {}", session.id, session.upload_type);
+            Err(Failure(Status::InternalServerError))
+        }
+    }
+}
+
+/// Appends `data` to `file`. Failing to read `data` isn't an error, what was read
+/// until then is written and the read error returned with the number of bytes.
+fn write_chunk(file: &mut File, data: &mut dyn Read) -> Result<(u64, Option<io::Error>), Failure>
+{
+    let mut buffer = [0; 64 * 1024];
+    let mut written = 0;
+
+    loop {
+        let read = match data.read(&mut buffer) {
+            Ok(0) => return Ok((written, None)),
+            Ok(read) => read,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Ok((written, Some(e))),
+        };
+
+        file.write_all(&buffer[..read]).map_err(|e| {
+            eprintln!("Couldn't write upload: {}", e);
+            Failure(Status::ServiceUnavailable)
+        })?;
+        written += read as u64;
+    }
+}
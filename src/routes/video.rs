@@ -1,21 +1,19 @@
-extern crate base64;
-
-use std::io::prelude::*;
-use std::path::Path;
-
 use chrono::{Local, NaiveDateTime};
 use diesel::{self, prelude::*};
-use rocket::response::{status, Failure, NamedFile};
+use rocket::response::{status, Failure, Redirect};
 use rocket::data::Data;
 use rocket::http::Status;
 use rocket_contrib::{Json, Template};
 
 use DbConn;
 use dbtools;
+use dbtools::storage;
 use {contexts, conv};
-use models::HVideo;
+use models::{BlobKind, HVideo};
 use forms::HVideoChangesetForm;
 use fields::Authentication;
+use routes::http_errors::unexpired;
+use routes::upload::{self, DataUrlReader, Format};
 
 fn new_vid(
     vid_data: Data,
@@ -27,53 +25,35 @@ fn new_vid(
 {
     use schema::horus_videos;
     let iid = dbtools::get_random_char_id(8);
-    let pathstr = dbtools::get_path_video(&iid);
-
-    let video = HVideo {
-        id: iid.clone(),
-        title: Some(title),
-        owner: auth.get_userid(),
-        filepath: pathstr.clone(),
-        date_added: Local::now().naive_utc(),
-        is_expiry: exp.is_some(),
-        expiration_time: exp,
-        password: None
-    };
-
-    let vid_data: Vec<u8> = vid_data.open().bytes().map(|x| x.unwrap()).collect();
-
-    if vid_data.len() < 23 {
-        return Err(Failure(Status::BadRequest));
-    }
-
-    // 1 more character due too "webm" vs "png"
-    let vid_data_decoded = base64::decode(&vid_data[23..]);
-
-    if vid_data_decoded.is_err() {
-        eprintln!(
-            "Couldn't decode webm data: {}",
-            vid_data_decoded.err().unwrap()
-        );
-        return Err(Failure(Status::BadRequest));
-    }
-
-    let vid_data_decoded = vid_data_decoded.unwrap();
-
-    let s3result = dbtools::s3::resource_to_s3(&pathstr, &vid_data_decoded);
-
-    if s3result.is_err() {
-        return Err(Failure(Status::ServiceUnavailable));
-    }
-
-    let result = diesel::insert_into(horus_videos::table)
-        .values(&video)
-        .get_result::<HVideo>(&*conn);
 
-    if result.is_err() {
-        return Err(Failure(Status::InternalServerError));
-    }
-
-    let result = result.unwrap();
+    // 1 more character than images due too "webm" vs "png"
+    let mut vid_data_decoded = DataUrlReader::new(vid_data.open(), 23);
+    let webm = Format::Exactly("video/webm");
+    let result = upload::store_blob(
+        &mut vid_data_decoded,
+        webm,
+        BlobKind::Video,
+        auth.get_userid(),
+        &conn,
+        |blob| {
+            let video = HVideo {
+                id: iid,
+                title: Some(title),
+                owner: blob.owner,
+                filepath: blob.filepath.clone(),
+                date_added: Local::now().naive_utc(),
+                is_expiry: exp.is_some(),
+                expiration_time: exp,
+                password: None,
+                blob_hash: Some(blob.hash.clone()),
+                blob_kind: blob.kind.clone(),
+            };
+
+            diesel::insert_into(horus_videos::table)
+                .values(&video)
+                .get_result::<HVideo>(&*conn)
+        },
+    )?;
 
     Ok(status::Created(
         String::from("/video/") + result.id.as_str(),
@@ -205,10 +185,14 @@ pub fn delete(
 
 fn delete_internal(video: HVideo, conn: DbConn) -> Result<status::Custom<()>, Failure>
 {
-    let s3result = dbtools::s3::delete_s3_object(&video.filepath);
+    // Videos stored before blobs own their object, blobs are released below.
+    if video.blob_hash.is_none() {
+        let deleted = storage::backend().delete(&video.filepath);
 
-    if s3result.is_err() {
-        return Err(Failure(Status::ServiceUnavailable));
+        if let Err(e) = deleted {
+            eprintln!("Couldn't delete video: {}", e);
+            return Err(Failure(Status::ServiceUnavailable));
+        }
     }
 
     let result = diesel::delete(&video).execute(&*conn);
@@ -221,6 +205,10 @@ fn delete_internal(video: HVideo, conn: DbConn) -> Result<status::Custom<()>, Fa
         return Err(Failure(Status::InternalServerError));
     }
 
+    if let Some(ref hash) = video.blob_hash {
+        upload::release_blob(hash, BlobKind::Video, video.owner, &conn);
+    }
+
     Ok(status::Custom(Status::Ok, ()))
 }
 
@@ -265,38 +253,46 @@ pub fn update(
     }
 }
 
+/// Redirects to the video in storage. Videos behind a password are only
+/// linked to through `password::check`, once it has been entered.
 #[get("/full/<vid_id>")]
-pub fn full(vid_id: String, conn: DbConn) -> Option<NamedFile>
+pub fn full(vid_id: String, conn: DbConn) -> Result<Redirect, Failure>
 {
     use schema::horus_videos::dsl::*;
     let video = horus_videos.find(vid_id).get_result::<HVideo>(&*conn);
 
     if video.is_err() {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
-    let video = video.unwrap();
-    let video_path = Path::new(&video.filepath);
-    NamedFile::open(video_path).ok()
+    let video = unexpired(video.unwrap())?;
+
+    if video.password.is_some() {
+        return Err(Failure(Status::NotFound));
+    }
+
+    Ok(Redirect::to(&storage::backend().public_url(&video.filepath)))
 }
 
 #[get("/<vid_id>")]
-pub fn show(vid_id: String, conn: DbConn) -> Option<Template>
+pub fn show(vid_id: String, conn: DbConn) -> Result<Template, Failure>
 {
     use schema::horus_videos::dsl::*;
     let video = horus_videos.find(&vid_id).get_result::<HVideo>(&*conn);
 
     if video.is_err() {
-        return None;
+        return Err(Failure(Status::NotFound));
     }
-    let video = video.unwrap();
-    let mut metatag = String::from("<meta property=\"og:video\" content=\"https://s3.eu-central-1.amazonaws.com/horuscdn/live/videos/");
-    metatag += &video.id.clone();
-    metatag += ".webm\" />";
+    let video = unexpired(video.unwrap())?;
+    let uri = storage::backend().public_url(&video.filepath);
+    let mut metatag = String::from("<meta property=\"og:video\" content=\"");
+    metatag += &uri;
+    metatag += "\" />";
     let context = contexts::ShowVideo {
         password: video.password.is_some(),
         item: video,
+        src: uri,
         meta_tag: Some(metatag),
     };
 
-    Some(Template::render("show_video", &context))
+    Ok(Template::render("show_video", &context))
 }
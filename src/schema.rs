@@ -15,6 +15,19 @@ table! {
     }
 }
 
+table! {
+    horus_blobs (hash, kind, owner) {
+        hash -> Varchar,
+        kind -> Varchar,
+        owner -> Int4,
+        filepath -> Varchar,
+        size -> Int8,
+        ref_count -> Int4,
+        created_at -> Timestamp,
+        content_type -> Varchar,
+    }
+}
+
 table! {
     horus_files (id) {
         id -> Varchar,
@@ -26,6 +39,8 @@ table! {
         expiration_time -> Nullable<Timestamp>,
         download_counter -> Nullable<Int4>,
         password -> Nullable<Varchar>,
+        blob_hash -> Nullable<Varchar>,
+        blob_kind -> Varchar,
     }
 }
 
@@ -39,6 +54,33 @@ table! {
         is_expiry -> Bool,
         expiration_time -> Nullable<Timestamp>,
         password -> Nullable<Varchar>,
+        thumbnail_path -> Nullable<Varchar>,
+        blob_hash -> Nullable<Varchar>,
+        blob_kind -> Varchar,
+    }
+}
+
+table! {
+    horus_job_events (id) {
+        id -> Int8,
+        job_id -> Int4,
+        event_type -> Varchar,
+        event_data -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    horus_job_schedules (id) {
+        id -> Int4,
+        owner -> Int4,
+        job_name -> Varchar,
+        job_data -> Nullable<Bytea>,
+        priority -> Int4,
+        cron_expression -> Varchar,
+        next_run -> Timestamp,
+        last_run -> Nullable<Timestamp>,
+        enabled -> Bool,
     }
 }
 
@@ -52,6 +94,18 @@ table! {
         time_queued -> Timestamp,
         priority -> Int4,
         logs -> Nullable<Text>,
+        claimed_by -> Nullable<Varchar>,
+        heartbeat -> Nullable<Timestamp>,
+        attempts -> Int4,
+        max_attempts -> Int4,
+        run_after -> Nullable<Timestamp>,
+        cancel_requested -> Bool,
+        deadline -> Nullable<Timestamp>,
+        timed_out -> Bool,
+        progress -> Nullable<Int2>,
+        stage -> Nullable<Varchar>,
+        job_result -> Nullable<Jsonb>,
+        depends_on -> Array<Int4>,
     }
 }
 
@@ -86,6 +140,24 @@ table! {
     }
 }
 
+table! {
+    horus_upload_sessions (id) {
+        id -> Varchar,
+        owner -> Int4,
+        upload_type -> Varchar,
+        upload_length -> Int8,
+        upload_offset -> Int8,
+        filename -> Nullable<Varchar>,
+        expiration_time -> Nullable<Timestamp>,
+        version_string -> Nullable<Varchar>,
+        platform_string -> Nullable<Varchar>,
+        deployment_key -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_activity -> Timestamp,
+        upload_host -> Varchar,
+    }
+}
+
 table! {
     horus_users (id) {
         id -> Int4,
@@ -117,6 +189,8 @@ table! {
         is_expiry -> Bool,
         expiration_time -> Nullable<Timestamp>,
         password -> Nullable<Varchar>,
+        blob_hash -> Nullable<Varchar>,
+        blob_kind -> Varchar,
     }
 }
 
@@ -134,10 +208,14 @@ joinable!(auth_tokens -> horus_users (uid));
 joinable!(deployment_keys -> horus_license_keys (license_key));
 joinable!(horus_files -> horus_users (owner));
 joinable!(horus_images -> horus_users (owner));
+joinable!(horus_job_events -> horus_jobs (job_id));
+joinable!(horus_job_schedules -> horus_users (owner));
 joinable!(horus_jobs -> horus_users (owner));
 joinable!(horus_licenses -> horus_license_keys (key));
 joinable!(horus_licenses -> horus_users (owner));
 joinable!(horus_pastes -> horus_users (owner));
+joinable!(horus_upload_sessions -> deployment_keys (deployment_key));
+joinable!(horus_upload_sessions -> horus_users (owner));
 joinable!(horus_versions -> deployment_keys (deployed_with));
 joinable!(horus_videos -> horus_users (owner));
 joinable!(session_tokens -> horus_users (uid));
@@ -145,12 +223,16 @@ joinable!(session_tokens -> horus_users (uid));
 allow_tables_to_appear_in_same_query!(
     auth_tokens,
     deployment_keys,
+    horus_blobs,
     horus_files,
     horus_images,
+    horus_job_events,
+    horus_job_schedules,
     horus_jobs,
     horus_license_keys,
     horus_licenses,
     horus_pastes,
+    horus_upload_sessions,
     horus_users,
     horus_versions,
     horus_videos,
@@ -0,0 +1,82 @@
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+
+use rocket::{Data, Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Status};
+
+/// Set once SIGTERM or SIGINT is received, never unset.
+static SHUTTING_DOWN: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// The number of requests being handled, not counting those turned away.
+static IN_FLIGHT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Requests received while shutting down are sent here, no route matches it.
+const UNAVAILABLE_PATH: &'static str = "/__shutting_down";
+
+/// Cached on a request that was turned away, so its response isn't counted
+/// off `IN_FLIGHT` even if the client asked for `UNAVAILABLE_PATH` itself.
+struct TurnedAway(bool);
+
+/// Whether the server is shutting down. Jugglers stop claiming jobs and
+/// requests are turned away once it is.
+pub fn is_shutting_down() -> bool
+{
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Starts shutting down. Returns false if the shutdown had already begun.
+pub fn begin() -> bool
+{
+    !SHUTTING_DOWN.swap(true, Ordering::SeqCst)
+}
+
+/// The number of requests accepted before the shutdown that haven't been answered
+/// yet. The process waits for these before exiting, like for running jobs.
+pub fn requests_in_flight() -> usize
+{
+    IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+/// Answers requests with 503 Service Unavailable while the server shuts down,
+/// so clients retry against another instance (or this one once it's back)
+/// instead of starting work that would be cut off. Requests that were already
+/// being handled are left alone and counted until their response is ready, see
+/// `requests_in_flight`.
+pub struct ShutdownFairing;
+
+impl Fairing for ShutdownFairing
+{
+    fn info(&self) -> Info
+    {
+        Info {
+            name: "Graceful shutdown",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data)
+    {
+        // Counted before checking, so the shutdown can't miss a request it lets through.
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+
+        if is_shutting_down() {
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+            request.local_cache(|| TurnedAway(true));
+            request.set_uri(UNAVAILABLE_PATH);
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response)
+    {
+        if !request.local_cache(|| TurnedAway(false)).0 {
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+
+        response.set_status(Status::ServiceUnavailable);
+        response.set_header(ContentType::Plain);
+        response.set_raw_header("Retry-After", "30");
+        response.set_sized_body(Cursor::new("The server is shutting down."));
+    }
+}
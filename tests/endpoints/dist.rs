@@ -94,11 +94,33 @@ fn deploy()
             response
                 .body_string()
                 .unwrap()
-                .contains("queued for processing.")
+                .parse::<i32>()
+                .is_ok(),
+            "Expected the id of the deployment job."
         );
     });
 }
 
+#[test]
+fn deploy_refuses_existing_version()
+{
+    run(|| {
+        let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+        conn.batch_execute(&sql_insert_version()).unwrap();
+
+        let client = get_client();
+        let req = client
+            .post(format!("/dist/deploy/new/{}/{}", VERSION_PLATFORM, VERSION))
+            .header(Header::new("content-type", "application/octet-stream"))
+            .header(api_key_header())
+            .header(depkey_header())
+            .body("test_body");
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Conflict);
+    });
+}
+
 fn run<T>(test: T) -> ()
 where
     T: FnOnce() -> () + panic::UnwindSafe,
@@ -123,6 +145,7 @@ fn unsetup_db()
     let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
 
     let mut unsetup_sql = format!("DELETE FROM horus_jobs WHERE owner = {};", USER_ID);
+    unsetup_sql.push_str(sql_delete_version().as_str());
     unsetup_sql.push_str(sql_delete_user().as_str());
 
     conn.batch_execute(&unsetup_sql).unwrap();
@@ -113,6 +113,73 @@ fn list()
     });
 }
 
+#[test]
+fn new_duplicate_stored_once()
+{
+    run(|| {
+        let client = get_client();
+        // SHA-256 of the content.
+        let blob_hash = "a3c629f8dca536166b9e44c409e3425ce57bbaf04663c0a61e7d614576faaadf";
+
+        for new_fname in ["first.txt", "second.txt"].iter() {
+            let req = client
+                .post("/file/new")
+                .header(auth_header())
+                .header(Header::new("content-type", "application/octet-stream"))
+                .header(Header::new("content-disposition", *new_fname))
+                .body("dedupcontent");
+            let response = req.dispatch();
+
+            assert_eq!(response.status(), Status::Created);
+        }
+
+        let req = client
+            .get(format!("/file/{}/list/0", USER_ID))
+            .header(auth_header());
+        let mut response = req.dispatch();
+        let bs = response.body_string().unwrap();
+
+        assert!(bs.contains("first.txt"));
+        assert!(bs.contains("second.txt"));
+        let blob_path = format!("live/files/{}/{}", USER_ID, blob_hash);
+        assert_eq!(bs.matches(&blob_path).count(), 2);
+    });
+}
+
+#[test]
+fn new_same_content_as_image_stored_apart()
+{
+    run(|| {
+        let client = get_client();
+        // SHA-256 of the content, a PNG signature and some text.
+        let blob_hash = "eb9199179db8d2b68bdefba60878e8c1f649e294fe8ad888f2cfeb58165cacbe";
+
+        let req = client
+            .post("/image/new")
+            .header(auth_header())
+            .header(Header::new("content-type", "image/png"))
+            .body("data:image/png;base64,iVBORw0KGgpraW5kc2FwYXJ0");
+        assert_eq!(req.dispatch().status(), Status::Created);
+
+        let req = client
+            .post("/file/new")
+            .header(auth_header())
+            .header(Header::new("content-type", "application/octet-stream"))
+            .header(Header::new("content-disposition", "kinds.png"))
+            .body(&b"\x89PNG\r\n\x1a\nkindsapart"[..]);
+        assert_eq!(req.dispatch().status(), Status::Created);
+
+        let req = client
+            .get(format!("/file/{}/list/0", USER_ID))
+            .header(auth_header());
+        let mut response = req.dispatch();
+        let bs = response.body_string().unwrap();
+
+        assert!(bs.contains(&format!("live/files/{}/{}", USER_ID, blob_hash)));
+        assert!(!bs.contains(&format!("live/images/{}/{}", USER_ID, blob_hash)));
+    });
+}
+
 fn run<T>(test: T) -> ()
 where
     T: FnOnce() -> () + panic::UnwindSafe,
@@ -147,6 +214,7 @@ fn get_client() -> Client
     let rocket = rocket::ignite()
         .attach(Template::fairing())
         .mount("/file", routes![get, list, new, new_exp, delete])
+        .mount("/image", routes![horus_server::routes::image::new])
         .manage(horus_server::dbtools::init_pool());
 
     Client::new(rocket).expect("valid rocket instance")
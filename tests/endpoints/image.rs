@@ -43,6 +43,39 @@ fn new()
     });
 }
 
+#[test]
+fn new_with_invalid_data_rejected()
+{
+    run(|| {
+        let client = get_client();
+        let req = client
+            .post("/image/new")
+            .header(auth_header())
+            .header(Header::new("content-type", "image/png"))
+            .body("data:image/png;base64,not*base64");
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+    });
+}
+
+#[test]
+fn new_with_other_format_rejected()
+{
+    run(|| {
+        let client = get_client();
+        // A GIF declared as a PNG.
+        let req = client
+            .post("/image/new")
+            .header(auth_header())
+            .header(Header::new("content-type", "image/png"))
+            .body("data:image/png;base64,R0lGODlhIG5vdCBhIHBuZw==");
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+    });
+}
+
 #[test]
 fn new_titled_with_exp()
 {
@@ -138,6 +171,55 @@ fn list()
     });
 }
 
+#[test]
+fn thumb_falls_back_while_pending()
+{
+    run(|| {
+        let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+        conn.batch_execute(&sql_insert_thumbnail_job()).unwrap();
+
+        let client = get_client();
+        let req = client.get("/image/thumb/".to_string() + IMAGE_ID);
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::SeeOther);
+        assert!(
+            response
+                .headers()
+                .get_one("location")
+                .unwrap()
+                .ends_with(IMAGE_PATH)
+        );
+    });
+}
+
+#[test]
+fn thumb_without_job_not_found()
+{
+    run(|| {
+        let client = get_client();
+        let req = client.get("/image/thumb/".to_string() + IMAGE_ID);
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn show_expired_gone()
+{
+    run(|| {
+        let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+        conn.batch_execute(&sql_expire_image()).unwrap();
+
+        let client = get_client();
+        let req = client.get("/image/".to_string() + IMAGE_ID);
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Gone);
+    });
+}
+
 #[test]
 fn delete_authless_fails()
 {
@@ -185,7 +267,7 @@ fn get_client() -> Client
         .attach(Template::fairing())
         .mount(
             "/image",
-            routes![show, list, new, new_exp, new_titled, delete, update],
+            routes![show, list, new, new_exp, new_titled, delete, update, thumb],
         )
         .manage(horus_server::dbtools::init_pool());
 
@@ -1,11 +1,17 @@
 use std::panic;
+use std::thread;
+use std::time::Duration;
 
-use rocket::{self, http::{Header, Status}, local::Client};
+use rocket::{self, http::{ContentType, Header, Status}, local::Client};
 use rocket_contrib::Json;
 use diesel::connection::SimpleConnection;
 use serde_json;
 
 use horus_server::{self, routes::jobs::*};
+use horus_server::job_juggler::{self, ExecutableJob, JobContext, JobJuggler, JobRegistry,
+                                JobResult, LoggableJob};
+use horus_server::models::{JobPriority, JobStatus, NewJob};
+use horus_server::models::job_structures::{binarize, JobPayload};
 use test::{run_test, sql::*};
 
 #[test]
@@ -24,6 +30,30 @@ pub fn job_status()
     });
 }
 
+#[test]
+pub fn job_state_has_result()
+{
+    run(|| {
+        let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+        conn.batch_execute(&sql_complete_job_with_result()).unwrap();
+
+        let client = get_client();
+        let req = client
+            .get(format!("/jobs/state/{}", JOB_ID))
+            .header(auth_header());
+        let mut response = req.dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let body = response.body_string().unwrap();
+        let state: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(state["job_status"], 10);
+        assert_eq!(
+            state["job_result"]["thumbnail_path"],
+            format!("thumbnails/{}.png", JOB_ID)
+        );
+    });
+}
+
 #[test]
 pub fn job_status_not_exists()
 {
@@ -80,6 +110,407 @@ pub fn denies_unauthed_user()
     });
 }
 
+#[test]
+pub fn job_detail_has_logs()
+{
+    run(|| {
+        let client = get_client();
+        let req = client
+            .get(format!("/jobs/{}", JOB_ID))
+            .header(auth_header());
+        let mut response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let bs = response.body_string().unwrap();
+        assert!(bs.contains(JOB_NAME));
+        assert!(bs.contains("\"logs\""));
+    });
+}
+
+#[test]
+pub fn cancels_queued_job()
+{
+    run(|| {
+        let client = get_client();
+        let req = client
+            .post(format!("/jobs/cancel/{}", JOB_ID))
+            .header(auth_header());
+        let response = req.dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let req = client
+            .get(format!("/jobs/state/{}", JOB_ID))
+            .header(auth_header());
+        let mut response = req.dispatch();
+        let body = response.body_string().unwrap();
+        let state: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(state["job_status"], 5);
+        assert!(state["job_result"].is_null());
+
+        // It can't be cancelled twice.
+        let req = client
+            .post(format!("/jobs/cancel/{}", JOB_ID))
+            .header(auth_header());
+        let response = req.dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+    });
+}
+
+#[test]
+pub fn cancels_interrupted_job()
+{
+    run(|| {
+        let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+        conn.batch_execute(&sql_interrupt_job()).unwrap();
+
+        let client = get_client();
+        let req = client
+            .post(format!("/jobs/cancel/{}", JOB_ID))
+            .header(auth_header());
+        let response = req.dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(poll_status(&client, JOB_ID), JobStatus::Cancelled as i32);
+    });
+}
+
+#[test]
+pub fn admin_list_denies_user()
+{
+    run(|| {
+        let client = get_client();
+        let req = client.get("/jobs/admin/0").header(auth_header());
+        let response = req.dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    });
+}
+
+#[test]
+pub fn admin_list_filters()
+{
+    run(|| {
+        let client = get_client();
+        let req = client
+            .get(format!("/jobs/admin/0?status={}&name={}", JOB_STATUS, JOB_NAME))
+            .header(admin_auth_header());
+        let mut response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.body_string().unwrap().contains(JOB_NAME));
+
+        let req = client
+            .get(format!("/jobs/admin/0?name=not_{}", JOB_NAME))
+            .header(admin_auth_header());
+        let mut response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert!(!response.body_string().unwrap().contains(JOB_NAME));
+    });
+}
+
+#[test]
+pub fn reconcile_denies_user()
+{
+    run(|| {
+        let client = get_client();
+        let req = client
+            .post("/jobs/admin/reconcile")
+            .header(ContentType::JSON)
+            .header(auth_header())
+            .body("{}");
+        let response = req.dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    });
+}
+
+#[test]
+pub fn reconcile_queues_job()
+{
+    run(|| {
+        let client = get_client();
+        let req = client
+            .post("/jobs/admin/reconcile")
+            .header(ContentType::JSON)
+            .header(admin_auth_header())
+            .body(r#"{"orphans": "quarantine", "dry_run": true}"#);
+        let mut response = req.dispatch();
+        assert_eq!(response.status(), Status::Accepted);
+
+        let job_id = response.body_string().unwrap();
+        let req = client
+            .get("/jobs/admin/0?name=maintenance:reconcile")
+            .header(admin_auth_header());
+        let mut response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let listed = format!("\"id\":{}", job_id);
+        assert!(response.body_string().unwrap().contains(&listed));
+    });
+}
+
+#[test]
+pub fn stuck_jobs_lists_timed_out()
+{
+    run(|| {
+        let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+        conn.batch_execute(&sql_time_out_job()).unwrap();
+
+        let client = get_client();
+        let req = client.get("/jobs/admin/stuck/0").header(admin_auth_header());
+        let mut response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.body_string().unwrap().contains(JOB_NAME));
+    });
+}
+
+#[test]
+pub fn streams_job_events()
+{
+    run(|| {
+        let client = get_client();
+        let req = client
+            .get(format!("/jobs/events/{}", JOB_ID))
+            .header(auth_header());
+        let mut response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("content-type").unwrap(),
+            "text/event-stream"
+        );
+        let bs = response.body_string().unwrap();
+        assert!(bs.contains("event: status"));
+        assert!(bs.contains(&format!("data: {}", JOB_STATUS)));
+    });
+}
+
+#[test]
+pub fn job_events_end_when_finished()
+{
+    run(|| {
+        let client = get_client();
+        let req = client
+            .post(format!("/jobs/cancel/{}", JOB_ID))
+            .header(auth_header());
+        req.dispatch();
+
+        let req = client
+            .get(format!("/jobs/events/{}", JOB_ID))
+            .header(auth_header())
+            .header(Header::new("Last-Event-ID", i64::max_value().to_string()));
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::NoContent);
+    });
+}
+
+#[test]
+pub fn rejects_unknown_dependencies()
+{
+    run(|| {
+        let job = test_job("test:orphan", None).with_dependencies(vec![JOB_ID, i32::max_value()]);
+
+        assert!(job_juggler::enqueue_job(job).is_err());
+    });
+}
+
+#[test]
+pub fn waits_for_dependencies()
+{
+    run(|| {
+        start_juggler("test:gated");
+        let client = get_client();
+        let job = test_job("test:gated", None).with_dependencies(vec![JOB_ID]);
+        let job_id = job_juggler::enqueue_job(job).unwrap();
+
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(poll_status(&client, job_id), JobStatus::Waiting as i32);
+
+        let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+        conn.batch_execute(&sql_complete_job_with_result()).unwrap();
+        conn.batch_execute("NOTIFY horus_jobs;").unwrap();
+
+        assert!(wait_for_status(&client, job_id, JobStatus::Complete as i32));
+    });
+}
+
+#[test]
+pub fn fails_dependents_of_failed_jobs()
+{
+    run(|| {
+        let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+        conn.batch_execute(&sql_fail_job()).unwrap();
+
+        let client = get_client();
+        let job = test_job("test:doomed", None).with_dependencies(vec![JOB_ID]);
+        let dependent = job_juggler::enqueue_job(job).unwrap();
+        let job = test_job("test:doomed", None).with_dependencies(vec![dependent]);
+        let transitive = job_juggler::enqueue_job(job).unwrap();
+
+        assert_eq!(poll_status(&client, dependent), JobStatus::Failed as i32);
+        assert_eq!(poll_status(&client, transitive), JobStatus::Failed as i32);
+    });
+}
+
+#[test]
+pub fn enqueues_follow_ups()
+{
+    run(|| {
+        start_juggler("test:follow_up");
+        let client = get_client();
+        let job = test_job("test:follow_up:first", Some("test:follow_up:second"));
+        let first = job_juggler::enqueue_job(job).unwrap();
+
+        assert!(wait_for_status(&client, first, JobStatus::Complete as i32));
+
+        // The follow-up is enqueued along with storing the result of the first job.
+        let req = client
+            .get(format!("/jobs/all/{}/0", USER_ID))
+            .header(auth_header());
+        let mut response = req.dispatch();
+        let jobs: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let second = jobs
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|j| j["job_name"] == "test:follow_up:second")
+            .expect("Expected the follow-up job.");
+
+        let second = second["id"].as_i64().unwrap() as i32;
+        assert!(wait_for_status(&client, second, JobStatus::Complete as i32));
+    });
+}
+
+#[test]
+pub fn fails_jobs_of_unknown_types()
+{
+    run(|| {
+        start_juggler("test:unknown");
+        let client = get_client();
+        // Claimed by the juggler, but no type is registered for the name.
+        let job = test_job("test:unknown_type", None);
+        let job_id = job_juggler::enqueue_job(job).unwrap();
+
+        assert!(wait_for_status(&client, job_id, JobStatus::Failed as i32));
+    });
+}
+
+#[test]
+pub fn caps_active_jobs_per_owner()
+{
+    run(|| {
+        let client = get_client();
+        // Enqueued before the jugglers start, so each of them claims as many as it may
+        // in one go.
+        let jobs: Vec<i32> = (0..6)
+            .map(|_| job_juggler::enqueue_job(slow_job("test:capped:slow")).unwrap())
+            .collect();
+        start_juggler("test:capped");
+        start_juggler("test:capped:slow");
+
+        // The queued job from the setup leaves room for three of them.
+        let active = jobs
+            .iter()
+            .map(|&id| poll_status(&client, id))
+            .filter(|&s| s == JobStatus::Queued as i32 || s == JobStatus::Running as i32)
+            .count();
+        assert!(active <= 3);
+
+        for &id in &jobs {
+            assert!(wait_for_status(&client, id, JobStatus::Complete as i32));
+        }
+    });
+}
+
+/// A job that completes after `pause_ms`, optionally following up with another one.
+#[derive(Serialize, Deserialize, LoggableJob)]
+#[LogName = "log_data"]
+struct TestJob
+{
+    follow_up: Option<String>,
+    pause_ms: u64,
+    log_data: String,
+}
+
+impl JobPayload for TestJob
+{
+    const TYPE_NAME: &'static str = "TestJob";
+}
+
+impl ExecutableJob for TestJob
+{
+    const MAX_RUNTIME: u64 = 10;
+
+    fn execute(self, ctx: &JobContext) -> (Box<Self>, JobResult)
+    {
+        thread::sleep(Duration::from_millis(self.pause_ms));
+        if let Some(ref name) = self.follow_up {
+            ctx.follow_up(test_job(name, None));
+        }
+        (Box::new(self), JobResult::Complete)
+    }
+}
+
+fn test_job(name: &str, follow_up: Option<&str>) -> NewJob
+{
+    let data = TestJob {
+        follow_up: follow_up.map(|n| n.to_string()),
+        pause_ms: 0,
+        log_data: String::new(),
+    };
+    NewJob::new(USER_ID, name.to_string(), Some(binarize(&data)), JobPriority::Normal)
+}
+
+/// A job that keeps its juggler busy for half a second.
+fn slow_job(name: &str) -> NewJob
+{
+    let data = TestJob {
+        follow_up: None,
+        pause_ms: 500,
+        log_data: String::new(),
+    };
+    NewJob::new(USER_ID, name.to_string(), Some(binarize(&data)), JobPriority::Normal)
+}
+
+/// Starts a juggler that only claims the jobs whose name starts with `prefix`, so it
+/// leaves the jobs of other tests alone, and runs them as `TestJob`s.
+fn start_juggler(prefix: &'static str)
+{
+    let mut registry = JobRegistry::new();
+    registry.register::<TestJob>(prefix);
+
+    let mut juggler = JobJuggler::with_registry(format!("test-{}", prefix), registry).unwrap();
+    juggler.claim_only(&format!("{}%", prefix));
+    juggler.initialize().unwrap();
+
+    thread::spawn(move || {
+        juggler.juggle();
+    });
+}
+
+fn poll_status(client: &Client, job_id: i32) -> i32
+{
+    let req = client
+        .get(format!("/jobs/poll/{}", job_id))
+        .header(auth_header());
+    let mut response = req.dispatch();
+
+    serde_json::from_str(&response.body_string().unwrap()).unwrap()
+}
+
+/// Polls the job until it has `status`, giving up after ten seconds.
+fn wait_for_status(client: &Client, job_id: i32, status: i32) -> bool
+{
+    for _ in 0..100 {
+        if poll_status(client, job_id) == status {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    false
+}
+
 fn run<T>(test: T) -> ()
 where
     T: FnOnce() -> () + panic::UnwindSafe,
@@ -113,7 +544,19 @@ fn get_client() -> Client
     let rocket = rocket::ignite()
         .mount(
             "/jobs",
-            routes![retrieve_job_status, list_active_jobs, list_all_jobs],
+            routes![
+                retrieve_job_status,
+                retrieve_job_state,
+                list_active_jobs,
+                list_all_jobs,
+                job_detail,
+                cancel_job,
+                admin_list_jobs,
+                admin_list_jobs_unfiltered,
+                stuck_jobs,
+                job_events,
+                reconcile
+            ],
         )
         .manage(horus_server::dbtools::init_pool());
 
@@ -8,3 +8,4 @@ mod manage;
 mod dist;
 mod key;
 mod jobs;
+mod upload;
@@ -33,6 +33,26 @@ fn does_show()
     });
 }
 
+#[test]
+fn show_expired_gone()
+{
+    run(|| {
+        let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+        conn.batch_execute(&sql_expire_paste()).unwrap();
+
+        let client = get_client();
+        let req = client.get(String::from("/") + PASTE_ID);
+        let response = req.dispatch();
+
+        assert_eq!(
+            response.status(),
+            Status::Gone,
+            "Bad response status, expected 410 Gone, got {}",
+            response.status()
+        );
+    });
+}
+
 #[test]
 fn does_list()
 {
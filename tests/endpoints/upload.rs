@@ -0,0 +1,159 @@
+use std::panic;
+
+use rocket::{self, http::{Header, Status}, local::Client};
+use diesel::connection::SimpleConnection;
+
+use horus_server::{self, routes::files, routes::upload::*};
+use test::{run_test, sql::*};
+
+#[test]
+fn resumes_and_commits_file()
+{
+    run(|| {
+        let client = get_client();
+        let upload = start_upload(&client, "resumed.txt", 12);
+
+        let response = append_chunk(&client, &upload, 0, "dummy");
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(response.headers().get_one("upload-offset"), Some("5"));
+
+        let response = client.head(upload.clone()).header(auth_header()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("upload-offset"), Some("5"));
+        assert_eq!(response.headers().get_one("upload-length"), Some("12"));
+
+        let response = append_chunk(&client, &upload, 5, "content");
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(response.headers().get_one("upload-offset"), Some("12"));
+
+        let req = client
+            .post(upload.clone() + "/commit")
+            .header(auth_header());
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Created);
+
+        let loc = response.headers().get_one("location").unwrap();
+        let mut response = client.get(loc).dispatch();
+        assert!(response.body_string().unwrap().contains("resumed.txt"));
+
+        let response = client.head(upload).header(auth_header()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    });
+}
+
+#[test]
+fn wrong_offset_conflicts()
+{
+    run(|| {
+        let client = get_client();
+        let upload = start_upload(&client, "conflict.txt", 12);
+
+        let response = append_chunk(&client, &upload, 3, "dummy");
+        assert_eq!(response.status(), Status::Conflict);
+    });
+}
+
+#[test]
+fn incomplete_upload_not_committed()
+{
+    run(|| {
+        let client = get_client();
+        let upload = start_upload(&client, "incomplete.txt", 12);
+        append_chunk(&client, &upload, 0, "dummy");
+
+        let req = client
+            .post(upload.clone() + "/commit")
+            .header(auth_header());
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Conflict);
+    });
+}
+
+#[test]
+fn too_long_upload_rejected()
+{
+    run(|| {
+        let client = get_client();
+        let upload = start_upload(&client, "long.txt", 4);
+
+        let response = append_chunk(&client, &upload, 0, "dummycontent");
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+
+        let response = client.head(upload).header(auth_header()).dispatch();
+        assert_eq!(response.headers().get_one("upload-offset"), Some("0"));
+    });
+}
+
+/// Returns the path of the new upload.
+fn start_upload(client: &Client, filename: &str, length: usize) -> String
+{
+    let req = client
+        .post("/upload/file")
+        .header(auth_header())
+        .header(Header::new("upload-length", length.to_string()))
+        .header(Header::new("content-disposition", filename.to_string()));
+    let response = req.dispatch();
+
+    assert_eq!(response.status(), Status::Created);
+    response.headers().get_one("location").unwrap().to_string()
+}
+
+fn append_chunk<'c>(
+    client: &'c Client,
+    upload: &str,
+    offset: usize,
+    chunk: &str,
+) -> rocket::local::LocalResponse<'c>
+{
+    client
+        .patch(upload.to_string())
+        .header(auth_header())
+        .header(Header::new("content-type", "application/offset+octet-stream"))
+        .header(Header::new("upload-offset", offset.to_string()))
+        .body(chunk)
+        .dispatch()
+}
+
+fn run<T>(test: T) -> ()
+where
+    T: FnOnce() -> () + panic::UnwindSafe,
+{
+    run_test(test, setup_db, unsetup_db);
+}
+
+fn setup_db()
+{
+    let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+    let mut setup_sql = String::new();
+
+    setup_sql.push_str(sql_insert_user().as_str());
+    setup_sql.push_str(sql_insert_license().as_str());
+
+    conn.batch_execute(&setup_sql).unwrap();
+}
+
+fn unsetup_db()
+{
+    let conn = horus_server::dbtools::get_db_conn_requestless().unwrap();
+    // Uploads and files go with the user.
+    let unsetup_sql = sql_delete_user();
+
+    conn.batch_execute(&unsetup_sql).unwrap();
+}
+
+fn get_client() -> Client
+{
+    use rocket_contrib::Template;
+    let rocket = rocket::ignite()
+        .attach(Template::fairing())
+        .mount("/file", routes![files::get])
+        .mount(
+            "/upload",
+            routes![new_file, new_file_exp, status, append, commit, terminate],
+        )
+        .manage(horus_server::dbtools::init_pool());
+
+    Client::new(rocket).expect("valid rocket instance")
+}
@@ -3,9 +3,13 @@
 extern crate diesel;
 extern crate horus_server;
 #[macro_use]
+extern crate horus_server_derive;
+#[macro_use]
 extern crate rocket;
 #[macro_use]
 extern crate rocket_contrib;
+#[macro_use]
+extern crate serde_derive;
 extern crate serde_json;
 
 mod endpoints;
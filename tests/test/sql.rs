@@ -20,6 +20,9 @@ pub const PASTE_DATA: &'static str = "paste_data_123 paste data lalala";
 pub const VIDEO_ID: &'static str = "defghij";
 pub const VIDEO_PATH: &'static str = "/live/videos/defghij.webm";
 
+pub const VERSION: &'static str = "99.99.98";
+pub const VERSION_PLATFORM: &'static str = "linux";
+
 pub const JOB_ID: i32 = 582;
 pub const JOB_NAME: &'static str = "test_job";
 pub const JOB_DATA: &'static str = "this_is_test_data";
@@ -40,6 +43,12 @@ pub fn auth_header<'a>() -> Header<'a>
     Header::new("x-api-test", USER_ID.to_string() + "/0")
 }
 
+/// Returns a header for test authentication as an admin
+pub fn admin_auth_header<'a>() -> Header<'a>
+{
+    Header::new("x-api-test", USER_ID.to_string() + "/1")
+}
+
 /// Returns a header for test api key.
 pub fn api_key_header<'a>() -> Header<'a>
 {
@@ -82,6 +91,18 @@ pub fn sql_insert_depkey() -> String
     )
 }
 
+/// Requires the calling of sql_insert_depkey first.
+pub fn sql_insert_version() -> String
+{
+    format!(
+        "INSERT INTO horus_versions(deployed_with, aws_bucket_path, version_string, platform) \
+         values('{}', '/live/packages/{v}/{p}.zip', '{v}', '{p}') ON CONFLICT DO NOTHING;",
+        DEPKEY_HASH,
+        v = VERSION,
+        p = VERSION_PLATFORM
+    )
+}
+
 pub fn sql_insert_job() -> String
 {
     format!(
@@ -92,6 +113,38 @@ pub fn sql_insert_job() -> String
     )
 }
 
+/// Requires the calling of sql_insert_job first.
+pub fn sql_time_out_job() -> String
+{
+    format!(
+        "UPDATE horus_jobs SET job_status = 2, timed_out = true, \
+         deadline = now() - interval '1 minute' WHERE id = {};",
+        JOB_ID
+    )
+}
+
+/// Requires the calling of sql_insert_job first.
+pub fn sql_fail_job() -> String
+{
+    format!("UPDATE horus_jobs SET job_status = 2 WHERE id = {};", JOB_ID)
+}
+
+/// Requires the calling of sql_insert_job first.
+pub fn sql_interrupt_job() -> String
+{
+    format!("UPDATE horus_jobs SET job_status = 6 WHERE id = {};", JOB_ID)
+}
+
+/// Requires the calling of sql_insert_job first.
+pub fn sql_complete_job_with_result() -> String
+{
+    format!(
+        "UPDATE horus_jobs SET job_status = 10, \
+         job_result = '{{\"thumbnail_path\": \"thumbnails/{}.png\"}}' WHERE id = {};",
+        JOB_ID, JOB_ID
+    )
+}
+
 /// Requires the calling of sql_insert_user first.
 pub fn sql_insert_session() -> String
 {
@@ -131,6 +184,36 @@ pub fn sql_insert_image() -> String
     )
 }
 
+/// Requires the calling of sql_insert_image first.
+pub fn sql_insert_thumbnail_job() -> String
+{
+    format!(
+        "INSERT INTO horus_jobs(owner, job_name, job_status, priority) \
+         values({}, 'thumbnail:image:{}', 0, 0);",
+        USER_ID, IMAGE_ID
+    )
+}
+
+/// Requires the calling of sql_insert_image first.
+pub fn sql_expire_image() -> String
+{
+    format!(
+        "UPDATE horus_images SET is_expiry = true, \
+         expiration_time = (now() AT TIME ZONE 'utc') - interval '1 day' WHERE id = '{}';",
+        IMAGE_ID
+    )
+}
+
+/// Requires the calling of sql_insert_paste first.
+pub fn sql_expire_paste() -> String
+{
+    format!(
+        "UPDATE horus_pastes SET is_expiry = true, \
+         expiration_time = (now() AT TIME ZONE 'utc') - interval '1 day' WHERE id = '{}';",
+        PASTE_ID
+    )
+}
+
 /// Requires the calling of sql_insert_user first.
 pub fn sql_insert_file() -> String
 {
@@ -157,6 +240,14 @@ pub fn sql_delete_license() -> String
     )
 }
 
+pub fn sql_delete_version() -> String
+{
+    format!(
+        "DELETE FROM horus_versions WHERE version_string = '{}' AND platform = '{}';",
+        VERSION, VERSION_PLATFORM
+    )
+}
+
 pub fn sql_delete_session() -> String
 {
     format!("DELETE FROM session_tokens WHERE uid = {};", USER_ID)